@@ -0,0 +1,150 @@
+//! # Wallet
+//!
+//! Command-line wallet talking to a blockchain node over HTTP.
+//!
+//! The key is read from `WALLET_KEY_PATH` (`wallet.key` by default), and the node is
+//! reached at `NODE_URL` (`http://localhost:8000` by default).
+
+extern crate blockchain;
+extern crate reqwest;
+extern crate serde_json;
+
+use std::env;
+use std::error::Error;
+use std::io;
+use std::process;
+
+use serde_json::Value;
+
+use blockchain::ledger::crypto::{Address, Keypair};
+use blockchain::ledger::sync::{self, BlockSource, HttpPeer};
+use blockchain::ledger::util::Timestamp;
+use blockchain::ledger::example::stupid::{StupidBlock, StupidTransaction};
+
+const USAGE: &str = "Usage: wallet <command>
+
+Commands:
+    new                                 Generate a key and store it
+    address                             Show the address of the stored key
+    balance [<address>]                 Show the balance of an address, ours by default
+    send <recipient> <amount> [<fee>]   Sign a transfer and submit it to the node
+    history [<address>]                 List the transactions of an address, ours by default
+
+Environment:
+    WALLET_KEY_PATH                     Path of the key, wallet.key by default
+    NODE_URL                            Base URL of the node, http://localhost:8000 by default";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<(), Box<Error>> {
+    let key_path = env::var("WALLET_KEY_PATH").unwrap_or(String::from("wallet.key"));
+    let node = env::var("NODE_URL").unwrap_or(String::from("http://localhost:8000"));
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+
+    match args.as_slice() {
+        ["new"] => {
+            let keypair = Keypair::generate();
+            match keypair.write_to(&key_path) {
+                Ok(()) => println!("{}", keypair.address()),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    return Err(From::from(format!("A key is already stored at {}", key_path)));
+                },
+                Err(e) => return Err(From::from(e))
+            }
+        },
+        ["address"] => println!("{}", Keypair::read_from(&key_path)?.address()),
+        ["balance"] => print_balance(&node, &Keypair::read_from(&key_path)?.address())?,
+        ["balance", address] => print_balance(&node, &address.parse()?)?,
+        ["send", recipient, amount] => send(&node, &key_path, recipient, amount, "0")?,
+        ["send", recipient, amount, fee] => send(&node, &key_path, recipient, amount, fee)?,
+        ["history"] => print_history(&node, &Keypair::read_from(&key_path)?.address())?,
+        ["history", address] => print_history(&node, &address.parse()?)?,
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    }
+    Ok(())
+}
+
+fn print_balance(node: &str, address: &Address) -> Result<(), Box<Error>> {
+    let balance = get(&format!("{}/balances/{}", node, address))?;
+    println!("Confirmed: {}", balance["confirmed"]);
+    println!("Available: {}", balance["available"]);
+    Ok(())
+}
+
+/// Signs a transfer with the stored key and submits it through `POST /transactions`.
+fn send(node: &str, key_path: &str, recipient: &str, amount: &str, fee: &str) -> Result<(), Box<Error>> {
+    let keypair = Keypair::read_from(key_path)?;
+    let nonce = Timestamp::current_nanos().0;
+    let nonce = nonce.as_secs() * 1_000_000_000 + nonce.subsec_nanos() as u64;
+    let tx = StupidTransaction::transfer(&keypair, recipient.parse()?, amount.parse()?, fee.parse()?, nonce);
+
+    let mut resp = reqwest::Client::new().post(&format!("{}/transactions", node)).json(&tx).send()?;
+    let body: Value = resp.json()?;
+    if ! resp.status().is_success() {
+        return Err(From::from(reason(&body)));
+    }
+    println!("Submitted {} for block {}", tx.hash(), body["index"]);
+    Ok(())
+}
+
+/// Lists the transactions of `address`, walking the chain of the node page by page.
+fn print_history(node: &str, address: &Address) -> Result<(), Box<Error>> {
+    let peer = HttpPeer::new(node)?;
+    let tip = BlockSource::<StupidBlock>::tip(&peer)?;
+    let mut blocks: Vec<StupidBlock> = Vec::new();
+    sync::fetch_after(&peer, 0, &tip, |page| {
+        blocks.extend(page);
+        true
+    })?;
+
+    if let Some(last) = blocks.iter().filter(|block| block.is_pruned()).map(|block| block.index()).max() {
+        println!("History is incomplete: the node pruned the transactions of blocks up to {}", last);
+    }
+    for block in &blocks {
+        for tx in block.transactions().iter().filter(|tx| tx.involves(address)) {
+            match *tx {
+                StupidTransaction::Transfer { ref sender, ref recipient, amount, fee, .. } => {
+                    if sender == address {
+                        println!("block {}: sent {} to {}, fee {}", block.index(), amount, recipient, fee);
+                    } else {
+                        println!("block {}: received {} from {}", block.index(), amount, sender);
+                    }
+                },
+                StupidTransaction::Reward { amount, .. } => {
+                    println!("block {}: mined {}", block.index(), amount);
+                },
+                StupidTransaction::Lock { amount, fee, .. } => {
+                    println!("block {}: locked {} in {}, fee {}", block.index(), amount, tx.hash(), fee);
+                },
+                StupidTransaction::Unlock { ref lock, fee, .. } => {
+                    println!("block {}: unlocked {}, fee {}", block.index(), lock, fee);
+                },
+                StupidTransaction::Snapshot { .. } => ()
+            }
+        }
+    }
+    Ok(())
+}
+
+fn get(url: &str) -> Result<Value, Box<Error>> {
+    let mut resp = reqwest::get(url)?;
+    let body: Value = resp.json()?;
+    if ! resp.status().is_success() {
+        return Err(From::from(reason(&body)));
+    }
+    Ok(body)
+}
+
+/// Gets the reason of an error response of the node.
+fn reason(body: &Value) -> String {
+    body["reason"].as_str().unwrap_or("Unexpected response from node").to_string()
+}
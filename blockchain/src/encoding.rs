@@ -0,0 +1,70 @@
+//! # Encoding
+//!
+//! Negotiates between JSON and the binary encoding of the ledger codec, which peers use to
+//! exchange blocks.
+
+use std::io::{self, Read};
+
+use rocket::{Data, Outcome, Request};
+use rocket::data::{self, FromData};
+use rocket::http::{ContentType, MediaType, Status};
+use rocket::request::{self, FromRequest};
+use rocket::response::{self, Content, Responder};
+use rocket_contrib::Json;
+use serde::Serialize;
+
+use blockchain::ledger::codec::{self, Decode, Encode};
+
+/// Largest binary body read from a request, like the JSON bodies.
+const LIMIT: u64 = 1 << 20;
+
+/// Encoding preferred by the client, as told by its `Accept` header.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Encoding {
+    Json,
+    Binary
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Encoding {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Encoding, ()> {
+        let binary = request.accept().is_some_and(|accept| accept.preferred().media_type() == &MediaType::Binary);
+        Outcome::Success(if binary { Encoding::Binary } else { Encoding::Json })
+    }
+}
+
+/// Value answered in the encoding preferred by the client.
+#[derive(Debug)]
+pub struct Encoded<T> {
+    pub encoding: Encoding,
+    pub value: T
+}
+
+impl<'r, T: Serialize + Encode> Responder<'r> for Encoded<T> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self.encoding {
+            Encoding::Json => Json(self.value).respond_to(request),
+            Encoding::Binary => Content(ContentType::Binary, codec::to_bytes(&self.value)).respond_to(request)
+        }
+    }
+}
+
+/// Request body in the binary encoding.
+#[derive(Debug)]
+pub struct Binary<T>(pub T);
+
+impl<T: Decode> FromData for Binary<T> {
+    type Error = io::Error;
+
+    fn from_data(_request: &Request, data: Data) -> data::Outcome<Binary<T>, io::Error> {
+        let mut bytes = Vec::new();
+        if let Err(e) = data.open().take(LIMIT).read_to_end(&mut bytes) {
+            return Outcome::Failure((Status::InternalServerError, e));
+        }
+        match codec::from_bytes(&bytes) {
+            Ok(value) => Outcome::Success(Binary(value)),
+            Err(e) => Outcome::Failure((Status::BadRequest, io::Error::new(io::ErrorKind::InvalidData, e)))
+        }
+    }
+}
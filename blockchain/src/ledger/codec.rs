@@ -0,0 +1,283 @@
+//! # Codec
+//!
+//! Encodes blocks and transactions in a compact binary form, for peers to exchange them.
+//!
+//! The encoding is canonical: a value has exactly one encoding, and decoding rejects
+//! anything else. Numbers are unsigned LEB128 varints in their shortest form, byte strings
+//! and lists are prefixed with their length, and enum variants with a one-byte tag. Fields
+//! follow their declaration order, so a decoded block hashes the same as the original.
+
+use std::time::Duration;
+
+use ledger::error::DecodeError;
+use ledger::util::{Hash, Timestamp, HASH_LEN};
+
+/// Media type of binary encoded bodies on the HTTP API.
+pub const MEDIA_TYPE: &str = "application/octet-stream";
+
+/// Longest varint, which encodes a `u64`.
+const MAX_VARINT_LEN: usize = 10;
+
+pub trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+pub trait Decode: Sized {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError>;
+}
+
+/// Encodes `value` on its own.
+pub fn to_bytes<T: Encode + ?Sized>(value: &T) -> Vec<u8> {
+    let mut out = Vec::new();
+    value.encode(&mut out);
+    out
+}
+
+/// Decodes a value taking up the whole of `bytes`.
+pub fn from_bytes<T: Decode>(bytes: &[u8]) -> Result<T, DecodeError> {
+    let mut reader = Reader::new(bytes);
+    let value = T::decode(&mut reader)?;
+    if ! reader.is_empty() {
+        return Err(DecodeError::TrailingBytes);
+    }
+    Ok(value)
+}
+
+/// Cursor over encoded bytes.
+#[derive(Debug)]
+pub struct Reader<'a> {
+    bytes: &'a [u8]
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Counts the bytes left to read.
+    pub fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.read_exact(1)?[0])
+    }
+
+    /// Reads the next `len` bytes.
+    pub fn read_exact(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if len > self.bytes.len() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (read, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(read)
+    }
+
+    /// Reads a varint, rejecting encodings longer than needed.
+    pub fn read_varint(&mut self) -> Result<u64, DecodeError> {
+        let mut value: u64 = 0;
+        for i in 0..MAX_VARINT_LEN {
+            let byte = self.read_u8()?;
+            let bits = (byte & 0x7f) as u64;
+            if i == MAX_VARINT_LEN - 1 && bits > 1 {
+                return Err(DecodeError::BadNumber);
+            }
+            value |= bits << (7 * i);
+            if byte & 0x80 == 0 {
+                if byte == 0 && i > 0 {
+                    return Err(DecodeError::BadNumber);
+                }
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::BadNumber)
+    }
+
+    /// Reads a length-prefixed byte string.
+    pub fn read_bytes(&mut self) -> Result<Vec<u8>, DecodeError> {
+        let len = usize::decode(self)?;
+        self.read_exact(len).map(|bytes| bytes.to_vec())
+    }
+}
+
+pub fn write_varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Writes a length-prefixed byte string.
+pub fn write_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    bytes.len().encode(out);
+    out.extend_from_slice(bytes);
+}
+
+impl Encode for u64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        write_varint(*self, out);
+    }
+}
+
+impl Decode for u64 {
+    fn decode(reader: &mut Reader) -> Result<u64, DecodeError> {
+        reader.read_varint()
+    }
+}
+
+impl Encode for u32 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        write_varint(*self as u64, out);
+    }
+}
+
+impl Decode for u32 {
+    fn decode(reader: &mut Reader) -> Result<u32, DecodeError> {
+        let value = reader.read_varint()?;
+        if value > u32::max_value() as u64 {
+            return Err(DecodeError::BadNumber);
+        }
+        Ok(value as u32)
+    }
+}
+
+impl Encode for usize {
+    fn encode(&self, out: &mut Vec<u8>) {
+        write_varint(*self as u64, out);
+    }
+}
+
+impl Decode for usize {
+    fn decode(reader: &mut Reader) -> Result<usize, DecodeError> {
+        let value = reader.read_varint()?;
+        if value > usize::max_value() as u64 {
+            return Err(DecodeError::BadNumber);
+        }
+        Ok(value as usize)
+    }
+}
+
+/// Encodes a list as its length followed by its items.
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self[..].encode(out);
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.len().encode(out);
+        for item in self {
+            item.encode(out);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(reader: &mut Reader) -> Result<Vec<T>, DecodeError> {
+        let len = usize::decode(reader)?;
+        // Every item takes a byte at least, which bounds what a short input can allocate.
+        let mut items = Vec::with_capacity(len.min(reader.remaining()));
+        for _ in 0..len {
+            items.push(T::decode(reader)?);
+        }
+        Ok(items)
+    }
+}
+
+/// Encodes an optional value as a tag, 0 for none and 1 for some, followed by the value.
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            Some(ref value) => {
+                out.push(1);
+                value.encode(out);
+            },
+            None => out.push(0)
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(reader: &mut Reader) -> Result<Option<T>, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(reader)?)),
+            tag => Err(DecodeError::UnknownTag { tag })
+        }
+    }
+}
+
+/// Encodes a hash as its raw bytes, which have a fixed length.
+impl Encode for Hash {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for Hash {
+    fn decode(reader: &mut Reader) -> Result<Hash, DecodeError> {
+        let bytes = reader.read_exact(HASH_LEN)?;
+        Ok(Hash::from_slice(bytes).expect("Read as many bytes as a hash has"))
+    }
+}
+
+/// Encodes a timestamp as its seconds followed by its nanoseconds.
+impl Encode for Timestamp {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.as_secs().encode(out);
+        self.0.subsec_nanos().encode(out);
+    }
+}
+
+impl Decode for Timestamp {
+    fn decode(reader: &mut Reader) -> Result<Timestamp, DecodeError> {
+        let secs = u64::decode(reader)?;
+        let nanos = u32::decode(reader)?;
+        if nanos >= 1_000_000_000 {
+            return Err(DecodeError::BadNumber);
+        }
+        Ok(Timestamp::new(Duration::new(secs, nanos)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varints_are_shortest() {
+        for &(value, len) in &[(0, 1), (127, 1), (128, 2), (16383, 2), (16384, 3), (u64::max_value(), 10)] {
+            let bytes = to_bytes(&value);
+            assert_eq!(bytes.len(), len);
+            assert_eq!(from_bytes::<u64>(&bytes), Ok(value));
+        }
+        assert_eq!(from_bytes::<u64>(&[0x80, 0x00]), Err(DecodeError::BadNumber));
+        assert_eq!(from_bytes::<u64>(&[0xff; 10]), Err(DecodeError::BadNumber));
+        assert_eq!(from_bytes::<u32>(&to_bytes(&(1u64 << 32))), Err(DecodeError::BadNumber));
+    }
+
+    #[test]
+    fn rejects_truncated_and_trailing_bytes() {
+        let bytes = to_bytes(&vec![1usize, 300, 70000]);
+        assert_eq!(from_bytes::<Vec<usize>>(&bytes), Ok(vec![1, 300, 70000]));
+        assert_eq!(from_bytes::<Vec<usize>>(&bytes[..bytes.len() - 1]), Err(DecodeError::UnexpectedEnd));
+
+        let mut trailing = bytes.to_vec();
+        trailing.push(0);
+        assert_eq!(from_bytes::<Vec<usize>>(&trailing), Err(DecodeError::TrailingBytes));
+        assert_eq!(from_bytes::<Vec<usize>>(&[0xff, 0xff, 0xff, 0x0f]), Err(DecodeError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn timestamps_keep_nanos() {
+        let timestamp = Timestamp::new(Duration::new(1_500_000_000, 999_999_999));
+        assert_eq!(from_bytes::<Timestamp>(&to_bytes(&timestamp)), Ok(timestamp));
+        assert_eq!(from_bytes::<Timestamp>(&[1, 0x80, 0x94, 0xeb, 0xdc, 0x03]), Err(DecodeError::BadNumber));
+    }
+}
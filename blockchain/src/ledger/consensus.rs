@@ -0,0 +1,317 @@
+//! # Consensus
+//!
+//! Decides who may seal the next block of a chain. With proof of work, anyone may seal a
+//! block by finding a proof on its digest. With proof of authority, a configured set
+//! of validators takes turns signing blocks, in round-robin.
+//!
+//! An engine checks the seals of the blocks a node receives, and seals the blocks the node
+//! produces. A seal covers the digest of the block it seals, taken without the seal.
+
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use objecthash::{ObjectHash, ObjectHasher};
+
+use ledger::Block;
+use ledger::codec::{Decode, Encode, Reader};
+use ledger::crypto::{Address, Keypair, Signature};
+use ledger::error::DecodeError;
+use ledger::mining::Target;
+use ledger::pow::{self, Search};
+use ledger::util::Hash;
+
+pub trait Consensus<B: Block>: fmt::Debug + Send + Sync {
+    /// Computes the difficulty required from the block that follows `chain`.
+    fn next_difficulty(&self, chain: &[B]) -> u32;
+    /// Checks that `seal` lets the block with `digest` follow `target`.
+    fn is_valid_seal(&self, target: &Target, digest: &Hash, seal: &Seal) -> bool;
+    /// Seals the block with `digest` so that it can follow `target`.
+    ///
+    /// Gives nothing when `abort` is raised first, or when the node may not seal the block.
+    fn seal(&self, target: &Target, digest: &Hash, abort: Arc<AtomicBool>) -> Option<Sealed>;
+}
+
+/// What lets a block into the chain.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Seal {
+    /// Proof of work on the block digest.
+    Work { proof: usize },
+    /// Signature of the validator whose turn it is, over the block digest.
+    Authority { signature: Signature }
+}
+
+impl Seal {
+    /// Gets the proof of work, if the seal is one.
+    pub fn proof(&self) -> Option<usize> {
+        match *self {
+            Seal::Work { proof } => Some(proof),
+            Seal::Authority { .. } => None
+        }
+    }
+}
+
+/// Encodes a seal as a tag, 0 for work and 1 for authority, followed by its field.
+impl Encode for Seal {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            Seal::Work { proof } => {
+                out.push(0);
+                proof.encode(out);
+            },
+            Seal::Authority { ref signature } => {
+                out.push(1);
+                signature.encode(out);
+            }
+        }
+    }
+}
+
+impl Decode for Seal {
+    fn decode(reader: &mut Reader) -> Result<Seal, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(Seal::Work { proof: usize::decode(reader)? }),
+            1 => Ok(Seal::Authority { signature: Signature::decode(reader)? }),
+            tag => Err(DecodeError::UnknownTag { tag })
+        }
+    }
+}
+
+/// Seal found by an engine.
+#[derive(Debug, Clone)]
+pub struct Sealed {
+    pub seal: Seal,
+    /// Proofs tried per second, for engines that search for proofs.
+    pub hash_rate: Option<f64>
+}
+
+/// Engine a chain runs on, as set by its genesis.
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ConsensusConfig {
+    #[default]
+    ProofOfWork,
+    /// Blocks are signed by `validators` in turn, the first one signing the first block.
+    ProofOfAuthority { validators: Vec<Address> }
+}
+
+impl ConsensusConfig {
+    /// Builds the engine, sealing blocks with `keypair` when it belongs to a validator.
+    ///
+    /// Proof of work starts from `initial_difficulty`.
+    pub fn engine<B: Block>(&self, initial_difficulty: u32, keypair: Option<Keypair>) -> Arc<Consensus<B>> {
+        match *self {
+            ConsensusConfig::ProofOfWork => Arc::new(ProofOfWork::new(initial_difficulty)),
+            ConsensusConfig::ProofOfAuthority { ref validators } => {
+                Arc::new(ProofOfAuthority::new(validators.to_vec(), keypair))
+            }
+        }
+    }
+}
+
+/// Makes ConsensusConfig hashable.
+impl ObjectHash for ConsensusConfig {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        match *self {
+            ConsensusConfig::ProofOfWork => {
+                objecthash_struct!(
+                    hasher,
+                    "type" => &String::from("proof_of_work")
+                )
+            },
+            ConsensusConfig::ProofOfAuthority { ref validators } => {
+                objecthash_struct!(
+                    hasher,
+                    "type" => &String::from("proof_of_authority"),
+                    "validators" => validators
+                )
+            }
+        }
+    }
+}
+
+/// Proof of work, retargeting the difficulty as blocks come.
+#[derive(Debug, Clone, Copy)]
+pub struct ProofOfWork {
+    /// Difficulty of the first block.
+    pub initial_difficulty: u32,
+    pub search: Search
+}
+
+impl ProofOfWork {
+    pub fn new(initial_difficulty: u32) -> ProofOfWork {
+        ProofOfWork { initial_difficulty, search: Search::default() }
+    }
+}
+
+impl<B: Block> Consensus<B> for ProofOfWork {
+    fn next_difficulty(&self, chain: &[B]) -> u32 {
+        pow::next_difficulty(chain, self.initial_difficulty)
+    }
+
+    /// Checks the proof against the block digest, which covers the previous block through
+    /// its hash.
+    fn is_valid_seal(&self, target: &Target, digest: &Hash, seal: &Seal) -> bool {
+        match *seal {
+            Seal::Work { proof } => pow::is_valid_proof(digest, proof, target.difficulty),
+            Seal::Authority { .. } => false
+        }
+    }
+
+    fn seal(&self, target: &Target, digest: &Hash, abort: Arc<AtomicBool>) -> Option<Sealed> {
+        pow::search(digest, target.difficulty, self.search, abort)
+            .map(|found| Sealed { seal: Seal::Work { proof: found.proof }, hash_rate: Some(found.hash_rate()) })
+    }
+}
+
+/// Proof of authority, where validators sign blocks in round-robin.
+///
+/// Every block weighs the same, so the longest chain wins.
+#[derive(Debug)]
+pub struct ProofOfAuthority {
+    validators: Vec<Address>,
+    keypair: Option<Keypair>
+}
+
+impl ProofOfAuthority {
+    /// Creates an engine on `validators`, sealing blocks with `keypair` on its turns.
+    pub fn new(validators: Vec<Address>, keypair: Option<Keypair>) -> ProofOfAuthority {
+        ProofOfAuthority { validators, keypair }
+    }
+
+    pub fn validators(&self) -> &[Address] {
+        &self.validators
+    }
+
+    /// Gets the validator whose turn it is to sign the block at `index`, counting from 1.
+    pub fn validator(&self, index: usize) -> Option<&Address> {
+        if self.validators.is_empty() || index == 0 {
+            return None;
+        }
+        self.validators.get((index - 1) % self.validators.len())
+    }
+}
+
+impl<B: Block> Consensus<B> for ProofOfAuthority {
+    fn next_difficulty(&self, _chain: &[B]) -> u32 {
+        0
+    }
+
+    fn is_valid_seal(&self, target: &Target, digest: &Hash, seal: &Seal) -> bool {
+        match *seal {
+            Seal::Authority { ref signature } => {
+                self.validator(target.height + 1).is_some_and(|validator| validator.verify(digest.as_bytes(), signature))
+            },
+            Seal::Work { .. } => false
+        }
+    }
+
+    /// Signs the block on the turns of the node, right away.
+    fn seal(&self, target: &Target, digest: &Hash, _abort: Arc<AtomicBool>) -> Option<Sealed> {
+        let keypair = self.keypair.as_ref()?;
+        if self.validator(target.height + 1) != Some(&keypair.address()) {
+            return None;
+        }
+        Some(Sealed { seal: Seal::Authority { signature: keypair.sign(digest.as_bytes()) }, hash_rate: None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use ledger::util::Timestamp;
+    use super::*;
+
+    #[derive(Debug)]
+    struct TestBlock {
+        timestamp: Timestamp
+    }
+
+    impl Block for TestBlock {
+        fn timestamp(&self) -> &Timestamp {
+            &self.timestamp
+        }
+
+        fn difficulty(&self) -> u32 {
+            0
+        }
+
+        fn is_valid(&self) -> bool {
+            true
+        }
+    }
+
+    fn target(height: usize) -> Target {
+        Target { height, last_block_hash: hash(height as u8), difficulty: 0 }
+    }
+
+    fn hash(byte: u8) -> Hash {
+        Hash::from_slice(&[byte; 32]).unwrap()
+    }
+
+    fn seal(engine: &Consensus<TestBlock>, height: usize, digest: &Hash) -> Option<Seal> {
+        engine.seal(&target(height), digest, Arc::new(AtomicBool::new(false))).map(|sealed| sealed.seal)
+    }
+
+    #[test]
+    fn validators_sign_in_turn() {
+        let (alice, bob) = (Keypair::generate(), Keypair::generate());
+        let validators = vec![alice.address(), bob.address()];
+        let alice = ProofOfAuthority::new(validators.to_vec(), Some(alice));
+        let bob = ProofOfAuthority::new(validators.to_vec(), Some(bob));
+        let observer = ProofOfAuthority::new(validators, None);
+        let digest = hash(7);
+
+        for height in 0..4 {
+            let (signer, idle) = if height % 2 == 0 { (&alice, &bob) } else { (&bob, &alice) };
+            assert!(seal(idle, height, &digest).is_none());
+            let sealed = seal(signer, height, &digest).unwrap();
+            assert!(Consensus::<TestBlock>::is_valid_seal(&observer, &target(height), &digest, &sealed));
+            assert!(! Consensus::<TestBlock>::is_valid_seal(&observer, &target(height + 1), &digest, &sealed));
+            assert!(! Consensus::<TestBlock>::is_valid_seal(&observer, &target(height), &hash(8), &sealed));
+        }
+        assert!(seal(&observer, 0, &digest).is_none());
+    }
+
+    #[test]
+    fn authority_rejects_outsiders_and_work() {
+        let (validator, outsider) = (Keypair::generate(), Keypair::generate());
+        let engine = ProofOfAuthority::new(vec![validator.address()], None);
+        let digest = hash(7);
+
+        let forged = Seal::Authority { signature: outsider.sign(digest.as_bytes()) };
+        assert!(! Consensus::<TestBlock>::is_valid_seal(&engine, &target(0), &digest, &forged));
+        assert!(! Consensus::<TestBlock>::is_valid_seal(&engine, &target(0), &digest, &Seal::Work { proof: 0 }));
+        assert!(ProofOfAuthority::new(Vec::new(), None).validator(1).is_none());
+    }
+
+    #[test]
+    fn work_seals_with_proof() {
+        let engine = ProofOfWork::new(8);
+        let target = Target { height: 1, last_block_hash: hash(1), difficulty: 8 };
+        let sealed = Consensus::<TestBlock>::seal(&engine, &target, &hash(7), Arc::new(AtomicBool::new(false))).unwrap();
+        assert!(sealed.hash_rate.is_some());
+        assert!(Consensus::<TestBlock>::is_valid_seal(&engine, &target, &hash(7), &sealed.seal));
+        assert!(! Consensus::<TestBlock>::is_valid_seal(&engine, &target, &hash(8), &sealed.seal));
+    }
+
+    #[test]
+    fn work_checks_first_block() {
+        let engine = ProofOfWork::new(8);
+        let target = Target { height: 0, last_block_hash: hash(0), difficulty: 8 };
+        let invalid = (0..).find(|proof| ! pow::is_valid_proof(&hash(7), *proof, 8)).unwrap();
+        assert!(! Consensus::<TestBlock>::is_valid_seal(&engine, &target, &hash(7), &Seal::Work { proof: invalid }));
+    }
+
+    #[test]
+    fn seal_is_untagged_json() {
+        let json = serde_json::to_string(&Seal::Work { proof: 3 }).unwrap();
+        assert_eq!(json, r#"{"proof":3}"#);
+
+        let seal = Seal::Authority { signature: Keypair::generate().sign(&[1]) };
+        let json = serde_json::to_string(&seal).unwrap();
+        assert_eq!(serde_json::from_str::<Seal>(&json).unwrap(), seal);
+    }
+}
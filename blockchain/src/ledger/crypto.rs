@@ -0,0 +1,240 @@
+//! # Crypto
+//!
+//! Contains keypairs, account addresses and signatures.
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::str::FromStr;
+
+use objecthash::{ObjectHash, ObjectHasher};
+use ring::rand::SystemRandom;
+use ring::signature::{self, Ed25519KeyPair};
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de;
+use untrusted::Input;
+
+use ledger::codec::{self, Decode, Encode, Reader};
+use ledger::error::{DecodeError, KeyError};
+use ledger::util::Hex;
+
+/// Length of an Ed25519 public key, in bytes.
+const PUBLIC_KEY_LEN: usize = 32;
+
+/// Ed25519 keypair owning an account.
+pub struct Keypair {
+    pkcs8: Vec<u8>,
+    key_pair: Ed25519KeyPair
+}
+
+impl Keypair {
+    pub fn generate() -> Keypair {
+        let rng = SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).expect("Unable to generate keypair");
+        Self::from_pkcs8(&pkcs8[..]).expect("Generated keypair is invalid")
+    }
+
+    pub fn from_pkcs8(pkcs8: &[u8]) -> Result<Keypair, KeyError> {
+        let key_pair = Ed25519KeyPair::from_pkcs8(Input::from(pkcs8)).map_err(|_| KeyError)?;
+        Ok(Keypair { pkcs8: pkcs8.to_vec(), key_pair })
+    }
+
+    /// Reads a keypair stored as PKCS#8 bytes.
+    pub fn read_from<P: AsRef<Path>>(path: P) -> io::Result<Keypair> {
+        let pkcs8 = fs::read(path)?;
+        Self::from_pkcs8(&pkcs8).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Writes the keypair as PKCS#8 bytes to a new file, readable by its owner only.
+    ///
+    /// Fails if the file exists, rather than overwrite another key.
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        options.open(path)?.write_all(&self.pkcs8)
+    }
+
+    pub fn pkcs8(&self) -> &[u8] {
+        &self.pkcs8
+    }
+
+    pub fn address(&self) -> Address {
+        Address(self.key_pair.public_key_bytes().to_vec())
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        Signature(self.key_pair.sign(message).as_ref().to_vec())
+    }
+}
+
+impl fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Keypair({})", self.address())
+    }
+}
+
+/// Account address, i.e. the public key of the account owner.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub struct Address(Vec<u8>);
+
+impl Address {
+    /// Checks that `signature` was made over `message` by the owner of this address.
+    pub fn verify(&self, message: &[u8], signature: &Signature) -> bool {
+        signature::verify(&signature::ED25519, Input::from(&self.0), Input::from(message),
+            Input::from(&signature.0)).is_ok()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", Hex::from_bytes(&self.0))
+    }
+}
+
+impl FromStr for Address {
+    type Err = KeyError;
+
+    fn from_str(s: &str) -> Result<Address, KeyError> {
+        match Hex::to_bytes(s) {
+            Some(ref bytes) if bytes.len() == PUBLIC_KEY_LEN => Ok(Address(bytes.to_vec())),
+            _ => Err(KeyError)
+        }
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Address, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|_| de::Error::custom("invalid address"))
+    }
+}
+
+/// Makes Address hashable.
+impl ObjectHash for Address {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        self.0.objecthash(hasher)
+    }
+}
+
+/// Encodes an address as its raw bytes, which have a fixed length.
+impl Encode for Address {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.0);
+    }
+}
+
+impl Decode for Address {
+    fn decode(reader: &mut Reader) -> Result<Address, DecodeError> {
+        reader.read_exact(PUBLIC_KEY_LEN).map(|bytes| Address(bytes.to_vec()))
+    }
+}
+
+/// Ed25519 signature.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Signature(Vec<u8>);
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", Hex::from_bytes(&self.0))
+    }
+}
+
+impl Serialize for Signature {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Signature {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Signature, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Hex::to_bytes(&s).map(Signature).ok_or(de::Error::custom("invalid signature"))
+    }
+}
+
+/// Makes Signature hashable.
+impl ObjectHash for Signature {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        self.0.objecthash(hasher)
+    }
+}
+
+impl Encode for Signature {
+    fn encode(&self, out: &mut Vec<u8>) {
+        codec::write_bytes(&self.0, out);
+    }
+}
+
+impl Decode for Signature {
+    fn decode(reader: &mut Reader) -> Result<Signature, DecodeError> {
+        reader.read_bytes().map(Signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_and_verify() {
+        let keypair = Keypair::generate();
+        let signature = keypair.sign(b"message");
+        assert!(keypair.address().verify(b"message", &signature));
+        assert!(! keypair.address().verify(b"other message", &signature));
+        assert!(! Keypair::generate().address().verify(b"message", &signature));
+    }
+
+    #[test]
+    fn restore_from_pkcs8() {
+        let keypair = Keypair::generate();
+        let restored = Keypair::from_pkcs8(keypair.pkcs8()).unwrap();
+        assert_eq!(restored.address(), keypair.address());
+        assert!(Keypair::from_pkcs8(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn write_to_creates_private_file() {
+        let path = ::std::env::temp_dir().join("blockchain-crypto-write-to.pk8");
+        let _ = fs::remove_file(&path);
+        let keypair = Keypair::generate();
+        keypair.write_to(&path).unwrap();
+
+        assert_eq!(Keypair::read_from(&path).unwrap().address(), keypair.address());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        let err = Keypair::generate().write_to(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(Keypair::read_from(&path).unwrap().address(), keypair.address());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn parse_address() {
+        let address = Keypair::generate().address();
+        assert_eq!(address.to_string().len(), 2 * PUBLIC_KEY_LEN);
+        assert_eq!(address.to_string().parse::<Address>().unwrap(), address);
+        assert!("0x0001".parse::<Address>().is_err());
+        assert!("abc".parse::<Address>().is_err());
+    }
+}
@@ -4,35 +4,329 @@
 
 use std::error::Error;
 use std::fmt;
+use std::io;
+
+use ledger::crypto::Address;
+use ledger::util::Hash;
 
 /// Error type for Block-related failures.
-#[derive(Debug, Serialize, Deserialize)]
-pub struct BlockError;
+///
+/// Variants about a block in a chain carry the `index` of that block.
+#[derive(Debug)]
+pub enum BlockError {
+    /// There are no pending transactions to put in the block.
+    EmptyMempool,
+    /// Nobody is set to receive the reward of the block.
+    MissingReward,
+    /// The node may not seal the block at `index`, e.g. when it is another validator's turn.
+    CannotSeal { index: usize },
+    /// The block does not come right after the block before it.
+    BadIndex { index: usize, expected: usize },
+    /// The block timestamp precedes the median timestamp of the last blocks.
+    TimestampTooEarly { index: usize },
+    /// The block timestamp is too far ahead of the clock of the node.
+    TimestampTooLate { index: usize },
+    /// The block seal does not satisfy the consensus engine.
+    InvalidSeal { index: usize },
+    /// The block does not point to the hash of the block before it.
+    BadPreviousHash { index: usize },
+    /// The block difficulty does not follow the retarget rule.
+    BadDifficulty { index: usize, expected: u32, actual: u32 },
+    /// The block transactions do not match the transactions root of the block.
+    BadTransactionsRoot { index: usize },
+    /// The block holds more than one reward, or a reward of the wrong amount or index.
+    InvalidReward { index: usize },
+    /// The block holds more transactions than a block may.
+    TooManyTransactions { index: usize, count: usize, limit: usize },
+    /// The block holds a transaction that cannot be applied.
+    InvalidTransaction { index: usize, error: TransactionError },
+    /// The block lacks the snapshot its index calls for, holds one that is not due, or
+    /// commits to another state than the one it leads to.
+    InvalidSnapshot { index: usize },
+    /// The block comes without its transactions.
+    Pruned { index: usize },
+    /// The block cannot be persisted.
+    Storage(io::Error)
+}
 
 impl fmt::Display for BlockError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Cannot add block")
+        match *self {
+            BlockError::TimestampTooEarly { index } |
+            BlockError::TimestampTooLate { index } |
+            BlockError::InvalidSeal { index } |
+            BlockError::BadPreviousHash { index } |
+            BlockError::BadTransactionsRoot { index } |
+            BlockError::InvalidReward { index } |
+            BlockError::InvalidSnapshot { index } => {
+                write!(f, "{} in block {}", self.description(), index)
+            },
+            BlockError::CannotSeal { index } => write!(f, "Not allowed to seal block {}", index),
+            BlockError::Pruned { index } => write!(f, "Block {} is pruned", index),
+            BlockError::BadIndex { index, expected } => {
+                write!(f, "Bad index in block {}: expected {}", index, expected)
+            },
+            BlockError::BadDifficulty { index, expected, actual } => {
+                write!(f, "Bad difficulty in block {}: expected {} but got {}", index, expected, actual)
+            },
+            BlockError::TooManyTransactions { index, count, limit } => {
+                write!(f, "Too many transactions in block {}: {} over a limit of {}", index, count, limit)
+            },
+            BlockError::InvalidTransaction { index, ref error } => {
+                write!(f, "Invalid transaction in block {}: {}", index, error)
+            },
+            BlockError::Storage(ref e) => write!(f, "Cannot store block: {}", e),
+            _ => write!(f, "{}", self.description())
+        }
     }
 }
 
 impl Error for BlockError {
     fn description(&self) -> &str {
-        "Cannot add block"
+        match *self {
+            BlockError::EmptyMempool => "No pending transactions",
+            BlockError::MissingReward => "No reward recipient",
+            BlockError::BadIndex { .. } => "Bad index",
+            BlockError::TimestampTooEarly { .. } => "Timestamp too early",
+            BlockError::TimestampTooLate { .. } => "Timestamp too far in the future",
+            BlockError::CannotSeal { .. } => "Not allowed to seal",
+            BlockError::InvalidSeal { .. } => "Invalid seal",
+            BlockError::BadPreviousHash { .. } => "Bad previous hash",
+            BlockError::BadDifficulty { .. } => "Bad difficulty",
+            BlockError::BadTransactionsRoot { .. } => "Bad transactions root",
+            BlockError::InvalidReward { .. } => "Invalid reward",
+            BlockError::TooManyTransactions { .. } => "Too many transactions",
+            BlockError::InvalidTransaction { .. } => "Invalid transaction",
+            BlockError::InvalidSnapshot { .. } => "Invalid snapshot",
+            BlockError::Pruned { .. } => "Pruned block",
+            BlockError::Storage(_) => "Cannot store block"
+        }
+    }
+
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match *self {
+            BlockError::InvalidTransaction { ref error, .. } => Some(error),
+            BlockError::Storage(ref e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for BlockError {
+    fn from(e: io::Error) -> BlockError {
+        BlockError::Storage(e)
     }
 }
 
 /// Error type for Transaction-related failures.
-#[derive(Debug, Serialize, Deserialize)]
-pub struct TransactionError;
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum TransactionError {
+    /// The transaction is not signed by its sender.
+    InvalidSignature,
+    /// The transaction is not well-formed.
+    Malformed { reason: String },
+    /// The transaction is a mining reward, which only miners can create.
+    UnexpectedReward,
+    /// The transaction is a state snapshot, which only miners can create.
+    UnexpectedSnapshot,
+    /// The transaction does not move anything.
+    ZeroAmount,
+    /// The same transaction is already pending or in the chain.
+    Duplicate { hash: Hash },
+    /// The sender cannot afford the transaction.
+    InsufficientFunds { address: Address, balance: usize, amount: usize },
+    /// The transaction spends an output that does not exist.
+    UnknownOutput { tx_hash: Hash, index: usize },
+    /// The transaction spends an output that is already spent.
+    DoubleSpend { tx_hash: Hash, index: usize },
+    /// The transaction outputs are worth more than the outputs it spends.
+    OutputsExceedInputs { inputs: usize, outputs: usize },
+    /// The mempool is full of transactions paying at least the same fee rate.
+    FeeTooLow { fee_rate: usize, minimum: usize },
+    /// The transaction spends a lock that does not exist or is already spent.
+    UnknownLock { hash: Hash },
+    /// The script of the lock the transaction spends does not accept it.
+    ScriptFailed { error: ScriptError }
+}
 
 impl fmt::Display for TransactionError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Cannot add transaction")
+        match *self {
+            TransactionError::Malformed { ref reason } => write!(f, "Malformed transaction: {}", reason),
+            TransactionError::Duplicate { ref hash } => {
+                write!(f, "Duplicate transaction {}", hash)
+            },
+            TransactionError::InsufficientFunds { ref address, balance, amount } => {
+                write!(f, "Insufficient funds: {} has {} but spends {}", address, balance, amount)
+            },
+            TransactionError::OutputsExceedInputs { inputs, outputs } => {
+                write!(f, "Outputs exceed inputs: {} spent into {}", inputs, outputs)
+            },
+            TransactionError::FeeTooLow { fee_rate, minimum } => {
+                write!(f, "Fee too low: {} per kilobyte but the mempool requires {}", fee_rate, minimum)
+            },
+            TransactionError::UnknownLock { ref hash } => write!(f, "Unknown lock {}", hash),
+            TransactionError::ScriptFailed { ref error } => write!(f, "Script failed: {}", error),
+            TransactionError::UnknownOutput { ref tx_hash, index } |
+            TransactionError::DoubleSpend { ref tx_hash, index } => {
+                write!(f, "{}: output {} of {}", self.description(), index, tx_hash)
+            },
+            _ => write!(f, "{}", self.description())
+        }
     }
 }
 
 impl Error for TransactionError {
     fn description(&self) -> &str {
-        "Cannot add transaction"
+        match *self {
+            TransactionError::InvalidSignature => "Invalid transaction signature",
+            TransactionError::Malformed { .. } => "Malformed transaction",
+            TransactionError::UnexpectedReward => "Cannot add reward transaction",
+            TransactionError::UnexpectedSnapshot => "Cannot add snapshot transaction",
+            TransactionError::ZeroAmount => "Transaction amount is zero",
+            TransactionError::Duplicate { .. } => "Duplicate transaction",
+            TransactionError::InsufficientFunds { .. } => "Insufficient funds",
+            TransactionError::UnknownOutput { .. } => "Unknown transaction output",
+            TransactionError::DoubleSpend { .. } => "Transaction output is already spent",
+            TransactionError::OutputsExceedInputs { .. } => "Outputs exceed inputs",
+            TransactionError::FeeTooLow { .. } => "Fee too low",
+            TransactionError::UnknownLock { .. } => "Unknown lock",
+            TransactionError::ScriptFailed { .. } => "Script failed"
+        }
+    }
+
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match *self {
+            TransactionError::ScriptFailed { ref error } => Some(error),
+            _ => None
+        }
+    }
+}
+
+/// Error type for scripts that do not accept a transaction.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum ScriptError {
+    /// An operation needs more values than the stack holds.
+    StackUnderflow,
+    /// An operation finds a value of the wrong type on the stack.
+    TypeMismatch,
+    /// A multi-signature check takes more keys than allowed.
+    TooManyKeys,
+    /// The script takes more steps than allowed.
+    OutOfSteps,
+    /// A `verify` operation pops `false`.
+    VerifyFailed,
+    /// The script does not end with `true` alone on the stack.
+    Rejected
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+impl Error for ScriptError {
+    fn description(&self) -> &str {
+        match *self {
+            ScriptError::StackUnderflow => "Stack underflow",
+            ScriptError::TypeMismatch => "Type mismatch",
+            ScriptError::TooManyKeys => "Too many keys",
+            ScriptError::OutOfSteps => "Out of steps",
+            ScriptError::VerifyFailed => "Verify failed",
+            ScriptError::Rejected => "Script rejected the transaction"
+        }
+    }
+}
+
+/// Error type for Peer-related failures.
+#[derive(Debug, PartialEq)]
+pub enum PeerError {
+    /// The URL cannot be parsed as the HTTP base URL of a peer.
+    InvalidUrl { url: String },
+    /// The URL is the URL of the node itself.
+    OwnUrl { url: String }
+}
+
+impl fmt::Display for PeerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PeerError::InvalidUrl { ref url } => write!(f, "Invalid peer URL: {}", url),
+            PeerError::OwnUrl { ref url } => write!(f, "Cannot add the node itself as a peer: {}", url)
+        }
+    }
+}
+
+impl Error for PeerError {
+    fn description(&self) -> &str {
+        match *self {
+            PeerError::InvalidUrl { .. } => "Invalid peer URL",
+            PeerError::OwnUrl { .. } => "Cannot add the node itself as a peer"
+        }
+    }
+}
+
+/// Error type for failures to decode a binary encoded value.
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    /// The input ends in the middle of a value.
+    UnexpectedEnd,
+    /// The input goes on after the value.
+    TrailingBytes,
+    /// A number is out of range, or not encoded in its shortest form.
+    BadNumber,
+    /// An enum variant has a tag that no variant has.
+    UnknownTag { tag: u8 }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::UnknownTag { tag } => write!(f, "Unknown tag {}", tag),
+            _ => write!(f, "{}", self.description())
+        }
+    }
+}
+
+impl Error for DecodeError {
+    fn description(&self) -> &str {
+        match *self {
+            DecodeError::UnexpectedEnd => "Unexpected end of input",
+            DecodeError::TrailingBytes => "Trailing bytes after value",
+            DecodeError::BadNumber => "Bad number",
+            DecodeError::UnknownTag { .. } => "Unknown tag"
+        }
+    }
+}
+
+/// Error type for failures to parse a hash.
+#[derive(Debug, PartialEq)]
+pub struct HashError;
+
+impl fmt::Display for HashError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid hash")
+    }
+}
+
+impl Error for HashError {
+    fn description(&self) -> &str {
+        "Invalid hash"
+    }
+}
+
+/// Error type for Key-related failures.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyError;
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid key")
+    }
+}
+
+impl Error for KeyError {
+    fn description(&self) -> &str {
+        "Invalid key"
     }
 }
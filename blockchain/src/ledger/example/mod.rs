@@ -1 +1,3 @@
 pub mod stupid;
+pub mod utxo;
+pub mod simulation;
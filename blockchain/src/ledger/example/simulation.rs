@@ -0,0 +1,446 @@
+//! # Simulation
+//!
+//! Runs several Stupid Ledger nodes in one process, over a simulated network. Gossip
+//! messages travel with a latency, may get dropped, and never cross a partition. Syncing
+//! reads the chain of a peer directly, failing when the peer is across a partition.
+//!
+//! Time is simulated too, and every random choice comes from a seeded generator, so the
+//! same seed and the same calls replay the same run.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::Duration;
+
+use ledger::*;
+use ledger::crypto::Address;
+use ledger::genesis::Genesis;
+use ledger::gossip::{Outcome, Seen};
+use ledger::sync::{BlockSource, Tip};
+use ledger::util::Timestamp;
+use ledger::example::stupid::{StupidBlock, StupidLedger, StupidTransaction};
+
+/// Time at which every simulation starts, in seconds since the Unix epoch.
+pub const EPOCH: u64 = 1_500_000_000;
+
+/// Network of simulated nodes, fully connected unless partitioned.
+#[derive(Debug)]
+pub struct Network {
+    nodes: Vec<Node>,
+    /// Partition group of every node. Nodes only reach the nodes of their own group.
+    groups: Vec<usize>,
+    /// Messages in flight, by delivery time and sending order.
+    queue: BTreeMap<(Duration, u64), Envelope>,
+    sent: u64,
+    now: Duration,
+    latency: (Duration, Duration),
+    drop_rate: f64,
+    rng: Rng,
+    delivered: usize,
+    dropped: usize
+}
+
+#[derive(Debug)]
+struct Node {
+    ledger: StupidLedger,
+    address: Address,
+    seen: Seen
+}
+
+#[derive(Debug)]
+struct Envelope {
+    from: usize,
+    to: usize,
+    message: Message
+}
+
+#[derive(Debug, Clone)]
+enum Message {
+    Block(StupidBlock),
+    Transaction(StupidTransaction)
+}
+
+impl Network {
+    /// Starts `nodes` nodes on `genesis`, without latency nor drops.
+    ///
+    /// Node `i` mines for an address made of the number `i + 1`.
+    pub fn new(nodes: usize, genesis: Genesis, seed: u64) -> Network {
+        let nodes: Vec<Node> = (0..nodes).map(|i| Node {
+            ledger: StupidLedger::with_genesis(genesis.clone(), Vec::new()).expect("Genesis allocations fit"),
+            address: format!("{:064x}", i + 1).parse().expect("Address is 32 bytes of hex"),
+            seen: Seen::default()
+        }).collect();
+        Network {
+            groups: vec![0; nodes.len()],
+            nodes,
+            queue: BTreeMap::new(),
+            sent: 0,
+            now: Duration::from_secs(0),
+            latency: (Duration::from_secs(0), Duration::from_secs(0)),
+            drop_rate: 0.0,
+            rng: Rng(seed),
+            delivered: 0,
+            dropped: 0
+        }
+    }
+
+    /// Delays every message by a time picked between `min` and `max`, to the millisecond.
+    pub fn set_latency(&mut self, min: Duration, max: Duration) {
+        self.latency = (min, max.max(min));
+    }
+
+    /// Drops every message with probability `rate`.
+    pub fn set_drop_rate(&mut self, rate: f64) {
+        self.drop_rate = rate;
+    }
+
+    /// Splits the network into `groups` of nodes. Nodes left out of every group are cut
+    /// off from all the others.
+    ///
+    /// Messages in flight are dropped on arrival when they would cross the partition.
+    pub fn partition(&mut self, groups: &[&[usize]]) {
+        let len = self.nodes.len();
+        self.groups = (len..2 * len).collect();
+        for (group, members) in groups.iter().enumerate() {
+            for &node in members.iter() {
+                self.groups[node] = group;
+            }
+        }
+    }
+
+    /// Joins every node back into one network.
+    pub fn heal(&mut self) {
+        self.groups = vec![0; self.nodes.len()];
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn ledger(&self, node: usize) -> &StupidLedger {
+        &self.nodes[node].ledger
+    }
+
+    /// Gets the address node `node` mines for.
+    pub fn address(&self, node: usize) -> &Address {
+        &self.nodes[node].address
+    }
+
+    /// Gets the simulated time.
+    pub fn now(&self) -> Timestamp {
+        Timestamp::new(Duration::from_secs(EPOCH) + self.now)
+    }
+
+    /// Counts the messages delivered so far.
+    pub fn delivered(&self) -> usize {
+        self.delivered
+    }
+
+    /// Counts the messages dropped so far, on purpose or by a partition.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Counts the messages in flight.
+    pub fn in_flight(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Checks that every node has the same chain tip.
+    pub fn is_converged(&self) -> bool {
+        let tips: Vec<Tip> = self.nodes.iter().map(|node| node.ledger.tip()).collect();
+        tips.windows(2).all(|pair| pair[0] == pair[1])
+    }
+
+    /// Has node `node` mine a block at the current time, then gossips it.
+    pub fn mine(&mut self, node: usize) -> Result<StupidBlock, BlockError> {
+        let now = self.now();
+        let block = {
+            let node = &mut self.nodes[node];
+            node.ledger.reward(node.address.clone());
+            let block = node.ledger.seal_block(now)?.last().expect("Chain holds the sealed block").clone();
+            node.seen.insert(StupidLedger::hash(&block));
+            block
+        };
+        self.broadcast(node, Message::Block(block.clone()));
+        Ok(block)
+    }
+
+    /// Submits `tx` to node `node`, which gossips it once accepted.
+    pub fn submit(&mut self, node: usize, tx: StupidTransaction) -> Result<usize, TransactionError> {
+        let index = self.nodes[node].ledger.add_transaction(tx.clone())?;
+        self.nodes[node].seen.insert(tx.hash());
+        self.broadcast(node, Message::Transaction(tx));
+        Ok(index)
+    }
+
+    /// Delivers the next message in flight, moving the time up to its arrival.
+    ///
+    /// Returns false when no message is in flight.
+    pub fn step(&mut self) -> bool {
+        let key = match self.queue.keys().next() {
+            Some(&key) => key,
+            None => return false
+        };
+        let envelope = self.queue.remove(&key).expect("Queue holds the message");
+        self.now = self.now.max(key.0);
+        self.deliver(envelope);
+        true
+    }
+
+    /// Delivers the messages arriving within `duration`, then moves the time past it.
+    pub fn advance(&mut self, duration: Duration) {
+        let until = self.now + duration;
+        while self.queue.keys().next().is_some_and(|&(arrival, _)| arrival <= until) {
+            self.step();
+        }
+        self.now = until;
+    }
+
+    /// Delivers messages until none is in flight, returning how many were delivered.
+    pub fn run(&mut self) -> usize {
+        let delivered = self.delivered;
+        while self.step() {}
+        self.delivered - delivered
+    }
+
+    /// Sends `message` from `from` to every other node, dropping some at random.
+    fn broadcast(&mut self, from: usize, message: Message) {
+        let (min, max) = (as_millis(self.latency.0), as_millis(self.latency.1));
+        for to in (0..self.nodes.len()).filter(|&to| to != from) {
+            if self.rng.chance(self.drop_rate) {
+                self.dropped += 1;
+                continue;
+            }
+            let arrival = self.now + Duration::from_millis(min + self.rng.below(max - min + 1));
+            self.queue.insert((arrival, self.sent), Envelope { from, to, message: message.clone() });
+            self.sent += 1;
+        }
+    }
+
+    fn deliver(&mut self, envelope: Envelope) {
+        if self.groups[envelope.from] != self.groups[envelope.to] {
+            self.dropped += 1;
+            return;
+        }
+        self.delivered += 1;
+        match envelope.message {
+            Message::Block(block) => self.receive_block(envelope.to, block),
+            Message::Transaction(tx) => self.receive_transaction(envelope.to, tx)
+        }
+    }
+
+    /// Takes a block like a node does: appends it, or syncs with the peers on a fork, and
+    /// relays it when the chain changed.
+    fn receive_block(&mut self, to: usize, block: StupidBlock) {
+        let hash = StupidLedger::hash(&block);
+        if self.nodes[to].seen.contains(&hash) {
+            return;
+        }
+        let outcome = match self.nodes[to].ledger.receive_block(block.clone()) {
+            Ok(outcome) => outcome,
+            Err(_) => return
+        };
+        self.nodes[to].seen.insert(hash);
+        let replaced = outcome == Outcome::Fork && self.sync(to);
+        if outcome == Outcome::Appended || replaced {
+            self.broadcast(to, Message::Block(block));
+        }
+    }
+
+    fn receive_transaction(&mut self, to: usize, tx: StupidTransaction) {
+        if self.nodes[to].seen.contains(&tx.hash()) {
+            return;
+        }
+        if self.nodes[to].ledger.add_transaction(tx.clone()).is_ok() {
+            self.nodes[to].seen.insert(tx.hash());
+            self.broadcast(to, Message::Transaction(tx));
+        }
+    }
+
+    /// Syncs node `node` with every other node, returning whether its chain was replaced.
+    fn sync(&mut self, node: usize) -> bool {
+        let groups = &self.groups;
+        let (before, rest) = self.nodes.split_at_mut(node);
+        let (this, after) = rest.split_first_mut().expect("Network holds the node");
+        let peers: Vec<Peer> = before.iter().enumerate()
+            .chain(after.iter().enumerate().map(|(i, other)| (node + 1 + i, other)))
+            .map(|(i, other)| Peer { ledger: &other.ledger, reachable: groups[i] == groups[node] })
+            .collect();
+        this.ledger.sync_from(&peers).expect("Simulated nodes keep no storage").reorg.is_some()
+    }
+}
+
+fn as_millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + duration.subsec_nanos() as u64 / 1_000_000
+}
+
+/// Chain of another node, as seen through the simulated network.
+struct Peer<'a> {
+    ledger: &'a StupidLedger,
+    reachable: bool
+}
+
+impl<'a> BlockSource<StupidBlock> for Peer<'a> {
+    fn tip(&self) -> Result<Tip, Box<Error>> {
+        if ! self.reachable {
+            return Err(From::from("Peer is unreachable"));
+        }
+        Ok(self.ledger.tip())
+    }
+
+    fn blocks(&self, from: usize, limit: usize) -> Result<Vec<StupidBlock>, Box<Error>> {
+        if ! self.reachable {
+            return Err(From::from("Peer is unreachable"));
+        }
+        Ok(self.ledger.blocks(from, limit).to_vec())
+    }
+
+    fn headers(&self, from: usize, limit: usize) -> Result<Vec<StupidBlock>, Box<Error>> {
+        if ! self.reachable {
+            return Err(From::from("Peer is unreachable"));
+        }
+        Ok(self.ledger.headers(from, limit))
+    }
+}
+
+/// SplitMix64 generator, good enough to pick latencies and drops.
+#[derive(Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Picks a number below `bound`.
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+
+    /// Picks true with probability `p`.
+    fn chance(&mut self, p: f64) -> bool {
+        p > 0.0 && (self.next() >> 11) as f64 / (1u64 << 53) as f64 <= p
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ledger::crypto::Keypair;
+    use ledger::genesis::Allocation;
+    use super::*;
+
+    fn genesis() -> Genesis {
+        Genesis { difficulty: 1, ..Genesis::new("simulation") }
+    }
+
+    fn millis(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn gossip_arrives_after_latency() {
+        let mut network = Network::new(4, genesis(), 1);
+        network.set_latency(millis(50), millis(100));
+        network.mine(0).unwrap();
+
+        network.advance(millis(49));
+        assert!((1..4).all(|node| network.ledger(node).chain.is_empty()));
+        network.advance(millis(51));
+        assert!(network.is_converged());
+        assert_eq!(network.ledger(3).chain.len(), 1);
+    }
+
+    #[test]
+    fn transactions_spread_and_get_mined() {
+        let sender = Keypair::generate();
+        let mut genesis = genesis();
+        genesis.allocations.push(Allocation { address: sender.address(), amount: 10 });
+        let mut network = Network::new(3, genesis, 2);
+        network.set_latency(millis(10), millis(20));
+
+        let tx = StupidTransaction::transfer(&sender, network.address(2).clone(), 4, 1, 0);
+        network.submit(0, tx.clone()).unwrap();
+        network.run();
+        assert!(network.ledger(2).mempool().contains(&tx.hash()));
+
+        network.mine(2).unwrap();
+        network.run();
+        assert!(network.is_converged());
+        assert!(network.ledger(0).mempool().is_empty());
+        assert_eq!(network.ledger(1).confirmed_balances().balance(network.address(2)), 4 + 1 + 1);
+    }
+
+    #[test]
+    fn partitions_fork_then_converge_on_most_work() {
+        let mut network = Network::new(4, genesis(), 3);
+        network.set_latency(millis(10), millis(30));
+        network.mine(0).unwrap();
+        network.run();
+
+        network.partition(&[&[0, 1], &[2, 3]]);
+        for _ in 0..2 {
+            network.mine(0).unwrap();
+            network.run();
+        }
+        for _ in 0..3 {
+            network.mine(3).unwrap();
+            network.run();
+        }
+        assert_eq!(network.ledger(1).chain.len(), 3);
+        assert_eq!(network.ledger(2).chain.len(), 4);
+        assert!(! network.is_converged());
+
+        network.heal();
+        network.mine(2).unwrap();
+        network.run();
+        assert!(network.is_converged());
+        assert_eq!(network.ledger(0).chain.len(), 5);
+        assert_eq!(network.ledger(0).chain[1].transactions()[0].addresses(), vec![network.address(3).clone()]);
+    }
+
+    #[test]
+    fn nodes_catch_up_after_drops() {
+        let mut network = Network::new(3, genesis(), 4);
+        network.set_latency(millis(5), millis(50));
+        network.set_drop_rate(0.5);
+        for round in 0..9 {
+            network.mine(round % 3).unwrap();
+            network.advance(millis(20));
+        }
+        network.run();
+        assert!(network.dropped() > 0);
+
+        network.set_drop_rate(0.0);
+        for node in 0..3 {
+            network.mine(node).unwrap();
+            network.run();
+        }
+        assert!(network.is_converged());
+    }
+
+    #[test]
+    fn same_seed_replays_exactly() {
+        let replay = |seed| {
+            let mut network = Network::new(4, genesis(), seed);
+            network.set_latency(millis(1), millis(200));
+            network.set_drop_rate(0.3);
+            for round in 0..8 {
+                network.mine(round % 4).unwrap();
+                network.advance(millis(40));
+            }
+            network.run();
+            let chains: Vec<Vec<StupidBlock>> = (0..4).map(|node| network.ledger(node).chain.clone()).collect();
+            (chains, network.delivered(), network.dropped())
+        };
+        assert_eq!(replay(5), replay(5));
+        assert_ne!(replay(5), replay(6));
+    }
+}
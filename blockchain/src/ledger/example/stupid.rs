@@ -2,250 +2,3031 @@
 //!
 //! Stupid Ledger is a distributed ledger implemented with vanilla blockchain.
 
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
 
-use objecthash;
 use objecthash::{ObjectHash, ObjectHasher};
 
-use reqwest;
-use reqwest::Url;
-
 use ledger::*;
-use ledger::util::{Hex, Timestamp};
+use ledger::codec::{self, Decode, Encode, Reader};
+use ledger::consensus::{Consensus, ProofOfWork, Seal};
+use ledger::crypto::{Address, Keypair, Signature};
+use ledger::genesis::{Allocation, Genesis};
+use ledger::gossip::Outcome;
+use ledger::index::{Index, Location};
+use ledger::peers::PeerTable;
+use ledger::mempool::{Entry, Mempool};
+use ledger::mining::Target;
+use ledger::merkle::{self, MerkleProof};
+use ledger::metrics::{Counters, Exposition, Kind};
+use ledger::storage::Storage;
+use ledger::sync::{self, BlockSource, HttpPeer, SnapshotSource, Tip};
+use ledger::pow::{self, INITIAL_DIFFICULTY};
+use ledger::script::{self, Clock, Script};
+use ledger::util::{Hash, Timestamp};
+
+/// Amount rewarded to the miner of a block, on top of the block fees.
+pub const MINING_REWARD: usize = 1;
+/// Highest number of transfers in a block.
+pub const MAX_BLOCK_TRANSACTIONS: usize = 100;
+/// Number of blocks whose median timestamp the timestamp of the next block may not precede.
+pub const MEDIAN_TIME_BLOCKS: usize = 11;
+/// Time by which a block timestamp may run ahead of the clock of the node, in seconds.
+pub const MAX_FUTURE_DRIFT: u64 = 2 * 60 * 60;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct StupidLedger {
+    genesis: Genesis,
     pub chain: Vec<StupidBlock>,
-    pub peers: Vec<String>,
-    block_txs: Vec<StupidTransaction>
+    pub peers: PeerTable,
+    #[serde(skip)]
+    mempool: Mempool<StupidTransaction>,
+    miner: Option<Address>,
+    #[serde(skip, default = "default_consensus")]
+    consensus: Arc<Consensus<StupidBlock>>,
+    #[serde(skip)]
+    storage: Option<Box<Storage<StupidBlock> + Send>>,
+    #[serde(skip)]
+    index: Index,
+    /// State after the latest snapshot block of the chain.
+    #[serde(default)]
+    snapshot: Option<Snapshot>,
+    /// Whether to prune the chain whenever it takes a snapshot.
+    #[serde(default)]
+    pub pruning: bool,
+    /// Balances after the chain, updated as blocks come.
+    #[serde(skip)]
+    confirmed: Balances,
+    /// Number of transactions in the pruned blocks, which the index no longer holds.
+    #[serde(skip)]
+    pruned_transactions: usize,
+    #[serde(skip)]
+    counters: Counters
 }
 
 impl StupidLedger {
-    pub fn new(chain: Vec<StupidBlock>) -> StupidLedger {
-        StupidLedger { chain, peers: Vec::new(), block_txs: Vec::new() }
+    /// Creates a ledger on the default development genesis.
+    ///
+    /// Fails if `chain` is not a valid chain on it.
+    pub fn new(chain: Vec<StupidBlock>) -> Result<StupidLedger, BlockError> {
+        Self::with_genesis(Genesis::default(), chain)
+    }
+
+    /// Creates a ledger running the consensus engine configured by `genesis`.
+    ///
+    /// Under proof of authority, the ledger has no key to seal blocks with.
+    pub fn with_genesis(genesis: Genesis, chain: Vec<StupidBlock>) -> Result<StupidLedger, BlockError> {
+        let consensus = genesis.consensus.engine(genesis.difficulty, None);
+        Self::with_consensus(genesis, consensus, chain)
+    }
+
+    /// Fails if `chain` is not a valid chain on `genesis`, e.g. if it overspends or holds
+    /// pruned blocks, which need a snapshot to stand for them.
+    pub fn with_consensus(genesis: Genesis, consensus: Arc<Consensus<StupidBlock>>, chain: Vec<StupidBlock>)
+        -> Result<StupidLedger, BlockError>
+    {
+        let mut ledger = Self::empty(genesis, consensus)?;
+        ledger.validate_chain(&chain)?;
+        ledger.chain = chain;
+        ledger.update_index();
+        ledger.update_snapshot()?;
+        ledger.update_confirmed();
+        Ok(ledger)
+    }
+
+    /// Creates a ledger from the chain and the snapshot kept in `storage`, and keeps them
+    /// there from now on.
+    ///
+    /// Fails if the stored chain or snapshot cannot be read, or if they do not make a valid
+    /// chain on `genesis`.
+    pub fn with_storage(genesis: Genesis, consensus: Arc<Consensus<StupidBlock>>,
+        mut storage: Box<Storage<StupidBlock> + Send>) -> io::Result<StupidLedger>
+    {
+        let chain = storage.load()?;
+        let stored: Option<StoredSnapshot> = match storage.load_snapshot()? {
+            Some(bytes) => Some(codec::from_bytes(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?),
+            None => None
+        };
+        let (snapshot, pruned_transactions) = match stored {
+            Some(stored) => (Some(stored.snapshot), stored.pruned_transactions),
+            None => (None, 0)
+        };
+        let mut ledger = Self::empty(genesis, consensus).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        ledger.validate_from(&chain, snapshot.as_ref())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        // The stored count covers every block before the snapshot, some of which may still
+        // hold their transactions if the node stopped in the middle of pruning them.
+        let unpruned = snapshot.as_ref().map_or(0, |snapshot| Self::count_transactions(&chain[..snapshot.height - 1]));
+        ledger.pruned_transactions = pruned_transactions.saturating_sub(unpruned);
+        ledger.chain = chain;
+        ledger.snapshot = snapshot;
+        ledger.update_index();
+        ledger.update_snapshot()?;
+        ledger.update_confirmed();
+        ledger.storage = Some(storage);
+        Ok(ledger)
+    }
+
+    /// Creates a ledger without blocks, failing if the allocations of `genesis` overflow.
+    fn empty(genesis: Genesis, consensus: Arc<Consensus<StupidBlock>>) -> Result<StupidLedger, BlockError> {
+        let confirmed = Balances::from_genesis(&genesis)
+            .map_err(|error| BlockError::InvalidTransaction { index: 0, error })?;
+        Ok(StupidLedger { genesis, chain: Vec::new(), peers: PeerTable::default(), mempool: Mempool::default(),
+            miner: None, consensus, storage: None, index: Index::default(), snapshot: None, pruning: false,
+            confirmed, pruned_transactions: 0, counters: Counters::default() })
+    }
+
+    pub fn genesis(&self) -> &Genesis {
+        &self.genesis
+    }
+
+    pub fn consensus(&self) -> Arc<Consensus<StupidBlock>> {
+        self.consensus.clone()
+    }
+
+    /// Computes the difficulty required from the block that follows `chain`.
+    pub fn next_difficulty(&self, chain: &[StupidBlock]) -> u32 {
+        self.consensus.next_difficulty(chain)
+    }
+
+    /// Checks every block of `chain` in turn, reporting the first rule a block breaks.
+    ///
+    /// Blocks pruned off the start of `chain` only have their headers checked, up to the
+    /// block of the snapshot of the ledger, whose state stands for their transactions.
+    pub fn validate_chain(&self, chain: &[StupidBlock]) -> Result<(), BlockError> {
+        self.validate_from(chain, self.snapshot.as_ref())
     }
 
-    pub fn is_valid_chain(chain: &Vec<StupidBlock>) -> bool {
-        if chain.len() == 0 {
-            return true;
+    /// Checks `chain` like `validate_chain`, with the pruned blocks standing for `snapshot`.
+    fn validate_from(&self, chain: &[StupidBlock], snapshot: Option<&Snapshot>) -> Result<(), BlockError> {
+        let pruned = chain.iter().take_while(|block| block.is_pruned()).count();
+        let (mut balances, start) = if pruned == 0 {
+            (Balances::from_genesis(&self.genesis).map_err(|error| BlockError::InvalidTransaction { index: 0, error })?, 0)
+        } else {
+            let snapshot = match snapshot {
+                Some(snapshot) if snapshot.height > pruned && snapshot.height <= chain.len() => snapshot,
+                _ => return Err(BlockError::Pruned { index: pruned })
+            };
+            for position in 0..snapshot.height {
+                self.validate_header(&chain[..position], &chain[position].header)?;
+            }
+            let block = &chain[snapshot.height - 1];
+            if ! block.is_valid() || block.state_root() != Some(&snapshot.hash()) {
+                return Err(BlockError::InvalidSnapshot { index: snapshot.height });
+            }
+            (Balances::from_snapshot(snapshot), snapshot.height)
+        };
+
+        for position in start..chain.len() {
+            self.validate_block(&chain[..position], &chain[position], &mut balances)?;
         }
+        Ok(())
+    }
 
-        let mut last_block = &chain[0];
-        let mut last_block_hash = Self::hash(last_block);
-        for index in 1..chain.len() {
-            if chain[index].previous_hash != last_block_hash {
-                return false;
+    /// Checks that a block with `header` can follow `chain`, without looking at the
+    /// transactions of the block.
+    pub fn validate_header(&self, chain: &[StupidBlock], header: &StupidHeader) -> Result<(), BlockError> {
+        let index = header.index;
+        if index != chain.len() + 1 {
+            return Err(BlockError::BadIndex { index, expected: chain.len() + 1 });
+        }
+        if let Some(median) = median_timestamp(chain) {
+            if header.timestamp.0 < median {
+                return Err(BlockError::TimestampTooEarly { index });
             }
-            if ! Self::is_valid_proof(last_block_hash, last_block.proof, chain[index].proof) {
-                return false;
+        }
+        if header.timestamp.0 > Timestamp::current_nanos().0 + Duration::from_secs(MAX_FUTURE_DRIFT) {
+            return Err(BlockError::TimestampTooLate { index });
+        }
+        let target = self.target_of(chain);
+        if header.difficulty != target.difficulty {
+            return Err(BlockError::BadDifficulty { index, expected: target.difficulty, actual: header.difficulty });
+        }
+        if header.previous_hash != target.last_block_hash {
+            return Err(BlockError::BadPreviousHash { index });
+        }
+        if ! self.consensus.is_valid_seal(&target, &header.digest(), &header.seal) {
+            return Err(BlockError::InvalidSeal { index });
+        }
+        Ok(())
+    }
+
+    /// Checks that a block with `header`, on another fork, was worked for: its seal holds
+    /// on the header alone, at no less than the difficulty the chain requires from its next
+    /// block.
+    ///
+    /// The block before it is unknown, so its difficulty cannot be checked against the
+    /// retarget rule. Forks that retargeted below the chain are left to explicit syncs.
+    fn validate_fork_header(&self, header: &StupidHeader) -> Result<(), BlockError> {
+        let index = header.index;
+        let expected = self.next_difficulty(&self.chain);
+        if header.difficulty < expected {
+            return Err(BlockError::BadDifficulty { index, expected, actual: header.difficulty });
+        }
+        let target = Target {
+            height: index.saturating_sub(1),
+            last_block_hash: header.previous_hash.clone(),
+            difficulty: header.difficulty
+        };
+        if ! self.consensus.is_valid_seal(&target, &header.digest(), &header.seal) {
+            return Err(BlockError::InvalidSeal { index });
+        }
+        Ok(())
+    }
+
+    /// Checks that `block` can follow `chain`, whose transactions are already applied to
+    /// `balances`, then applies the transactions of `block` too.
+    fn validate_block(&self, chain: &[StupidBlock], block: &StupidBlock, balances: &mut Balances) -> Result<(), BlockError> {
+        self.validate_header(chain, &block.header)?;
+
+        let index = block.index();
+        if block.is_pruned() {
+            return Err(BlockError::Pruned { index });
+        }
+        if ! block.is_valid() {
+            return Err(BlockError::BadTransactionsRoot { index });
+        }
+        let count = block.transactions().iter().filter(|tx| ! tx.is_from_miner()).count();
+        if count > MAX_BLOCK_TRANSACTIONS {
+            return Err(BlockError::TooManyTransactions { index, count, limit: MAX_BLOCK_TRANSACTIONS });
+        }
+        if ! block.has_valid_reward() {
+            return Err(BlockError::InvalidReward { index });
+        }
+        if ! block.has_valid_snapshot(&self.genesis) {
+            return Err(BlockError::InvalidSnapshot { index });
+        }
+        balances.apply_block(block)
+            .map_err(|error| BlockError::InvalidTransaction { index, error })?;
+        if let Some(state_root) = block.state_root() {
+            if *state_root != balances.snapshot(index).hash() {
+                return Err(BlockError::InvalidSnapshot { index });
             }
+        }
+        Ok(())
+    }
 
-            last_block = &chain[index];
-            last_block_hash = Self::hash(last_block);
+    pub fn is_valid_chain(&self, chain: &[StupidBlock]) -> bool {
+        self.validate_chain(chain).is_ok()
+    }
+
+    /// Computes the balances once every pending transaction is in the chain.
+    ///
+    /// Pending unlocks whose time lock has not come yet are left out.
+    pub fn balances(&self) -> Balances {
+        let mut balances = self.confirmed.clone();
+        let clock = self.next_clock(Timestamp::current_nanos());
+        for entry in self.mempool.iter() {
+            let _ = balances.apply(&entry.tx, &clock);
         }
-        true
+        balances
     }
 
-    pub fn register_peer(&mut self, address: String) {
-        self.peers.push(address);
+    /// Gets the balances of the transactions in the chain only.
+    pub fn confirmed_balances(&self) -> &Balances {
+        &self.confirmed
     }
 
-    pub fn resolve_conflicts(&mut self) -> Result<bool, Box<Error>> {
-        let mut max_len = self.chain.len();
-        let mut new_chain = None;
+    /// Gets the state after the latest snapshot block of the chain.
+    pub fn snapshot(&self) -> Option<&Snapshot> {
+        self.snapshot.as_ref()
+    }
 
-        for peer in &self.peers {
-            let url = Url::parse(peer)?.join("/blocks")?;
-            let blocks_res = url.as_str();
-            let mut resp = reqwest::get(blocks_res)?;
+    /// Drops the transactions of the blocks before the latest snapshot, which stands for
+    /// them, from memory and from storage. Gets the number of blocks pruned.
+    ///
+    /// The ledger can then no longer switch to a fork that leaves the chain before the
+    /// snapshot, nor look the pruned transactions up. It keeps counting them in its
+    /// statistics, storing their number along with the snapshot.
+    pub fn prune(&mut self) -> io::Result<usize> {
+        let height = match self.snapshot {
+            Some(ref snapshot) => snapshot.height,
+            None => return Ok(0)
+        };
+        let count = self.chain[..height - 1].iter().filter(|block| ! block.is_pruned()).count();
+        if count == 0 {
+            return Ok(0);
+        }
 
-            if resp.status().is_success() {
-                let chain: Vec<StupidBlock> = resp.json()?;
+        let pruned_transactions = self.pruned_transactions + Self::count_transactions(&self.chain[..height - 1]);
+        if let Some(ref mut storage) = self.storage {
+            if let Some(ref snapshot) = self.snapshot {
+                let stored = StoredSnapshot { snapshot: snapshot.clone(), pruned_transactions };
+                storage.save_snapshot(&codec::to_bytes(&stored))?;
+            }
+        }
+        for block in &mut self.chain[..height - 1] {
+            block.prune();
+        }
+        if let Some(ref mut storage) = self.storage {
+            storage.replace(&self.chain)?;
+        }
+        self.pruned_transactions = pruned_transactions;
+        self.index.truncate(0);
+        self.update_index();
+        Ok(count)
+    }
 
-                if max_len < chain.len() && Self::is_valid_chain(&chain) {
-                    max_len = chain.len();
-                    new_chain = Some(chain);
-                }
+    /// Starts the empty ledger from the latest snapshot of `source` instead of the first
+    /// block, fetching the headers before the snapshot and the blocks from it on.
+    ///
+    /// The headers are checked like any header, the snapshot against the state its block
+    /// commits to, and the blocks that follow like any block. Fails if `source` is on
+    /// another genesis or serves an invalid chain.
+    ///
+    /// The statistics only count the transactions from the snapshot on, since the ledger
+    /// never holds the ones before.
+    pub fn bootstrap<S: SnapshotSource<StupidBlock, Snapshot>>(&mut self, source: &S) -> Result<(), Box<Error>> {
+        if ! self.chain.is_empty() {
+            return Err(From::from("Cannot bootstrap a ledger that has blocks"));
+        }
+        let tip = source.tip()?;
+        if tip.genesis != self.genesis.hash() {
+            return Err(From::from("Source is on another genesis"));
+        }
+        let snapshot = source.snapshot()?;
+        if snapshot.height == 0 || snapshot.height > tip.height {
+            return Err(From::from("Snapshot is not part of the chain of the source"));
+        }
+
+        let (mut chain, height) = (Vec::new(), snapshot.height - 1);
+        let is_valid = sync::fetch_headers(source, height, |page| self.extend_headers(&mut chain, page, true))?
+            && sync::fetch_after(source, height, &tip, |page| self.extend_headers(&mut chain, page, false))?;
+        if ! is_valid {
+            return Err(From::from("Source serves invalid headers"));
+        }
+        self.validate_from(&chain, Some(&snapshot))?;
+
+        if let Some(ref mut storage) = self.storage {
+            let stored = StoredSnapshot { snapshot: snapshot.clone(), pruned_transactions: 0 };
+            storage.save_snapshot(&codec::to_bytes(&stored))?;
+            storage.replace(&chain)?;
+        }
+        self.chain = chain;
+        self.snapshot = Some(snapshot);
+        self.update_index();
+        self.update_snapshot()?;
+        self.update_confirmed();
+        self.prune_pending();
+        Ok(())
+    }
+
+    /// Appends the blocks of `page` to `chain` as long as their headers are valid, dropping
+    /// their transactions if `prune` is set, and gives whether they all were.
+    fn extend_headers(&self, chain: &mut Vec<StupidBlock>, page: Vec<StupidBlock>, prune: bool) -> bool {
+        for mut block in page {
+            if self.validate_header(chain, &block.header).is_err() {
+                return false;
             }
+            if prune {
+                block.prune();
+            }
+            chain.push(block);
         }
+        true
+    }
 
-        if let Some(chain) = new_chain {
-            self.chain = chain;
-            Ok(true)
-        } else {
-            Ok(false)
+    /// Gets the block at `index`, counting from 1.
+    pub fn block(&self, index: usize) -> Option<&StupidBlock> {
+        index.checked_sub(1).and_then(|i| self.chain.get(i))
+    }
+
+    /// Gets at most `limit` blocks, starting at `from`, counting from 1.
+    pub fn blocks(&self, from: usize, limit: usize) -> &[StupidBlock] {
+        let start = from.saturating_sub(1).min(self.chain.len());
+        let end = start.saturating_add(limit).min(self.chain.len());
+        &self.chain[start..end]
+    }
+
+    /// Gets at most `limit` blocks without their transactions, starting at `from`,
+    /// counting from 1.
+    pub fn headers(&self, from: usize, limit: usize) -> Vec<StupidBlock> {
+        self.blocks(from, limit).iter().map(StupidBlock::pruned).collect()
+    }
+
+    /// Gets the block with `hash`.
+    pub fn block_by_hash(&self, hash: &Hash) -> Option<&StupidBlock> {
+        self.index.block_height(hash).and_then(|height| self.block(height))
+    }
+
+    /// Finds the transaction with `hash` in the chain, along with its place in the chain.
+    pub fn transaction(&self, hash: &Hash) -> Option<(Location, &StupidTransaction)> {
+        let location = self.index.transaction(hash)?;
+        self.located(location).map(|tx| (location, tx))
+    }
+
+    /// Finds the transactions involving `address` in the chain, oldest first.
+    pub fn transactions_of(&self, address: &Address) -> Vec<(Location, &StupidTransaction)> {
+        self.index.transactions_of(address).iter()
+            .filter_map(|&location| self.located(location).map(|tx| (location, tx)))
+            .collect()
+    }
+
+    /// Counts the blocks from the block at `location` up to the tip, both included.
+    pub fn confirmations(&self, location: &Location) -> usize {
+        (self.chain.len() + 1).saturating_sub(location.block)
+    }
+
+    pub fn stats(&self) -> ChainStats {
+        let average_block_time = match (self.chain.first(), self.chain.last()) {
+            (Some(first), Some(last)) if self.chain.len() > 1 => {
+                let elapsed = as_secs(&last.header.timestamp) - as_secs(&first.header.timestamp);
+                Some(elapsed / (self.chain.len() - 1) as f64)
+            },
+            _ => None
+        };
+        ChainStats {
+            height: self.chain.len(),
+            transactions: self.index.transaction_count() + self.pruned_transactions,
+            average_block_time,
+            difficulty: self.next_difficulty(&self.chain)
         }
     }
-}
 
-impl DistributedLedger<StupidBlock, StupidTransaction> for StupidLedger {
-    type LedgerRepr = Vec<StupidBlock>;
-    type Proof = usize;
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
 
-    fn hash(obj: &StupidBlock) -> Vec<u8> {
-        objecthash::digest(obj).as_ref().to_vec()
+    /// Records the proofs tried per second by a seal found away from the ledger.
+    pub fn record_hash_rate(&mut self, hash_rate: f64) {
+        self.counters.hash_rate = Some(hash_rate);
     }
 
-    fn is_valid_proof(last_block_hash: Vec<u8>, last_proof: Self::Proof, proof: Self::Proof) -> bool {
-        let last_block_hash = Hex::from_bytes(&last_block_hash[..]);
-        let s = format!("{}{}{}", last_block_hash, last_proof, proof);
-        objecthash::digest(&s).as_ref()[..2] == [0, 0] // TODO: apply dynamic difficulty
+    /// Writes the metrics of the chain, the pending transactions and the peers at `now`.
+    pub fn write_metrics(&self, now: &Timestamp, out: &mut Exposition) {
+        out.single("blockchain_height", Kind::Gauge, "Number of blocks in the chain.", self.chain.len() as f64);
+        if let Some(block) = self.chain.last() {
+            out.single("blockchain_last_block_timestamp_seconds", Kind::Gauge,
+                "Timestamp of the last block of the chain, in seconds since the epoch.", as_secs(&block.header.timestamp));
+        }
+        out.single("blockchain_mempool_transactions", Kind::Gauge, "Number of pending transactions.",
+            self.mempool.len() as f64);
+        out.single("blockchain_reorgs_total", Kind::Counter, "Switches to another fork since the node started.",
+            self.counters.reorgs as f64);
+        if let Some(hash_rate) = self.counters.hash_rate {
+            out.single("blockchain_hash_rate", Kind::Gauge, "Proofs tried per second while sealing the latest block.",
+                hash_rate);
+        }
+
+        out.single("blockchain_peers", Kind::Gauge, "Number of known peers.", self.peers.len() as f64);
+        out.single("blockchain_peers_available", Kind::Gauge, "Number of known peers not backing off.",
+            self.peers.available(now).len() as f64);
+        out.family("blockchain_peer_failures", Kind::Gauge, "Failures in a row of each peer, until it answers again.");
+        for peer in self.peers.peers() {
+            out.sample("blockchain_peer_failures", &[("peer", &peer.url)], peer.failures as f64);
+        }
+        out.family("blockchain_peer_last_seen_seconds", Kind::Gauge,
+            "Time each peer last answered, in seconds since the epoch.");
+        for peer in self.peers.peers() {
+            if let Some(ref last_seen) = peer.last_seen {
+                out.sample("blockchain_peer_last_seen_seconds", &[("peer", &peer.url)], as_secs(last_seen));
+            }
+        }
     }
 
-    fn new_block(&mut self, timestamp: Timestamp, proof: Self::Proof) -> Result<&Self::LedgerRepr, BlockError> {
-        let block = StupidBlock::new(
-            self.chain.len() + 1,
-            timestamp,
-            self.block_txs.to_vec(),
-            proof,
-            if let Some(last_block) = self.last_block() {
-                Self::hash(last_block)
-            } else {
-                vec![0; 32]
+    pub fn tip(&self) -> Tip {
+        Tip { height: self.chain.len(), hash: self.last_block_hash(), genesis: self.genesis.hash() }
+    }
+
+    /// Snapshots what the seal of the next block has to satisfy.
+    pub fn target(&self) -> Target {
+        self.target_of(&self.chain)
+    }
+
+    pub fn mempool(&self) -> &Mempool<StupidTransaction> {
+        &self.mempool
+    }
+
+    /// Drops the pending transactions that are too old at `now`, along with the ones that
+    /// depended on them.
+    pub fn expire_pending(&mut self, now: &Timestamp) -> Vec<Entry<StupidTransaction>> {
+        let expired = self.mempool.expire(now);
+        if ! expired.is_empty() {
+            self.prune_pending();
+        }
+        expired
+    }
+
+    /// Rewards `recipient` for mining the next block.
+    pub fn reward(&mut self, recipient: Address) {
+        self.miner = Some(recipient);
+    }
+
+    /// Assembles the next block from the pending transactions and the reward, leaving it
+    /// to be sealed. Until then, the block carries an empty proof of work.
+    pub fn prepare_block(&mut self, timestamp: Timestamp) -> Result<StupidBlock, BlockError> {
+        self.expire_pending(&timestamp);
+        let index = self.chain.len() + 1;
+        let clock = self.next_clock(timestamp.clone());
+        let mut transactions = self.select_transactions(&clock);
+        if let Some(miner) = self.miner.take() {
+            let reward = total_fees(&transactions).and_then(|fees| fees.checked_add(MINING_REWARD))
+                .ok_or(BlockError::InvalidReward { index })?;
+            transactions.push(StupidTransaction::reward(miner, reward, index));
+        }
+        if transactions.is_empty() {
+            return Err(BlockError::EmptyMempool);
+        }
+        if self.genesis.is_snapshot_height(index) {
+            let mut balances = self.confirmed.clone();
+            for tx in &transactions {
+                balances.apply(tx, &clock).expect("Selected transactions apply");
             }
-        );
-        self.block_txs.clear();
-        self.chain.push(block);
+            transactions.push(StupidTransaction::snapshot(balances.snapshot(index).hash()));
+        }
+
+        Ok(StupidBlock::new(
+            index,
+            timestamp,
+            transactions,
+            Seal::Work { proof: 0 },
+            self.next_difficulty(&self.chain),
+            self.last_block_hash()
+        ))
+    }
+
+    /// Prepares the next block and seals it with the consensus engine, then appends it.
+    pub fn seal_block(&mut self, timestamp: Timestamp) -> Result<&Vec<StupidBlock>, BlockError> {
+        let block = self.prepare_block(timestamp)?;
+        let abort = Arc::new(AtomicBool::new(false));
+        let sealed = self.consensus.seal(&self.target(), &block.header.digest(), abort)
+            .ok_or(BlockError::CannotSeal { index: block.index() })?;
+        let confirmed = self.confirmed_after(&block)?;
+        self.append(block.with_seal(sealed.seal), confirmed)?;
+        if let Some(hash_rate) = sealed.hash_rate {
+            self.record_hash_rate(hash_rate);
+        }
         Ok(&self.chain)
     }
 
-    fn add_transaction(&mut self, tx: StupidTransaction) -> Result<usize, TransactionError> {
-        self.block_txs.push(tx);
-        Ok(self.chain.len() + 1)
+    /// Takes a block gossiped by a peer, appending it when it extends the chain.
+    ///
+    /// Pending transactions that the block already holds, or that no longer apply, are
+    /// dropped. A block on another fork has to reach the tip of the chain and to carry a
+    /// valid seal before it is reported as a fork, since the node syncs for it.
+    pub fn receive_block(&mut self, block: StupidBlock) -> Result<Outcome, BlockError> {
+        if self.block(block.index()) == Some(&block) {
+            return Ok(Outcome::Known);
+        }
+        if block.index() != self.chain.len() + 1 || block.header.previous_hash != self.last_block_hash() {
+            if block.index() < self.chain.len() {
+                return Ok(Outcome::Stale);
+            }
+            self.validate_fork_header(&block.header)?;
+            return Ok(Outcome::Fork);
+        }
+
+        let mut confirmed = self.confirmed.clone();
+        self.validate_block(&self.chain, &block, &mut confirmed)?;
+        self.append(block, confirmed)?;
+        Ok(Outcome::Appended)
     }
 
-    fn find_proof(&self, last_proof: Self::Proof) -> Self::Proof {
-        let last_block_hash = if let Some(last_block) = self.last_block() {
-            Self::hash(last_block)
-        } else {
-            vec![0; 32]
+    /// Adds the peer at `url`, returning whether it was unknown.
+    pub fn register_peer(&mut self, url: &str) -> Result<bool, PeerError> {
+        self.peers.add(url)
+    }
+
+    /// Replaces the chain with the valid peer chain carrying the most work, if it carries
+    /// more than the chain itself.
+    ///
+    /// Peers backing off are skipped. The others are recorded as seen or failed, and a
+    /// failing peer does not keep the node from syncing with the rest.
+    ///
+    /// Holds the ledger while fetching from the peers. Nodes sharing a ledger between
+    /// threads sync with `peer_sync` and `finish_sync` instead.
+    pub fn resolve_conflicts(&mut self) -> io::Result<Option<Reorg>> {
+        let synced = self.peer_sync().fetch();
+        self.finish_sync(synced)
+    }
+
+    /// Starts a sync with the peers that are not backing off, on a copy of the chain, to
+    /// fetch from them without holding the ledger.
+    pub fn peer_sync(&self) -> PeerSync {
+        let ledger = StupidLedger { genesis: self.genesis.clone(), chain: self.chain.clone(),
+            peers: PeerTable::default(), mempool: Mempool::default(), miner: None, consensus: self.consensus.clone(),
+            storage: None, index: Index::default(), snapshot: self.snapshot.clone(), pruning: false,
+            confirmed: Balances::default(), pruned_transactions: 0, counters: Counters::default() };
+        let started = Timestamp::current_nanos();
+        PeerSync { urls: self.peers.available(&started), ledger, started }
+    }
+
+    /// Records how the peers of `synced` fared, then switches to the chain it fetched if
+    /// that chain still carries more work than the chain, which may have moved meanwhile.
+    pub fn finish_sync(&mut self, synced: PeerSynced) -> io::Result<Option<Reorg>> {
+        for url in &synced.seen {
+            self.peers.record_success(url, &synced.started);
+        }
+        for url in &synced.failed {
+            self.peers.record_failure(url, &synced.started);
+        }
+        match synced.chain {
+            Some(chain) if Self::is_better_chain(&chain, &self.chain) => self.reorganize(chain).map(Some),
+            _ => Ok(None)
+        }
+    }
+
+    /// Replaces the chain with the valid chain of `sources` carrying the most work, if it
+    /// carries more than the chain itself.
+    ///
+    /// Only the blocks that follow the last block shared with a source are fetched and
+    /// validated. Sources on another genesis, or serving invalid blocks, are ignored, and
+    /// sources that fail are reported. Fails only when the new chain cannot be stored.
+    pub fn sync_from<S: BlockSource<StupidBlock>>(&mut self, sources: &[S]) -> io::Result<Synced> {
+        let (best_chain, failures) = self.fetch_best(sources);
+        let reorg = match best_chain {
+            Some(chain) => Some(self.reorganize(chain)?),
+            None => None
         };
-        let mut proof = 0;
-        while ! Self::is_valid_proof(last_block_hash.to_vec(), last_proof, proof) {
-            proof += 1;
+        Ok(Synced { reorg, failures })
+    }
+
+    /// Fetches the valid chain of `sources` carrying the most work, if it carries more than
+    /// the chain itself, along with the errors of the sources that failed.
+    fn fetch_best<S: BlockSource<StupidBlock>>(&self, sources: &[S])
+        -> (Option<Vec<StupidBlock>>, Vec<(usize, Box<Error>)>)
+    {
+        let mut best_chain: Option<Vec<StupidBlock>> = None;
+        let mut failures = Vec::new();
+
+        for (position, source) in sources.iter().enumerate() {
+            let chain = match self.fetch_chain(source) {
+                Ok(Some(chain)) => chain,
+                Ok(None) => continue,
+                Err(e) => {
+                    failures.push((position, e));
+                    continue;
+                }
+            };
+
+            let is_better = match best_chain {
+                Some(ref best_chain) => Self::is_better_chain(&chain, best_chain),
+                None => Self::is_better_chain(&chain, &self.chain)
+            };
+            if is_better {
+                best_chain = Some(chain);
+            }
         }
-        proof
+        (best_chain, failures)
     }
 
-    fn last_block(&self) -> Option<&StupidBlock> {
-        self.chain.last()
+    /// Rebuilds the chain of `source` from the blocks shared with it and the blocks that
+    /// follow them, validating every page of blocks as it arrives.
+    ///
+    /// Gives nothing when `source` is on another genesis, has no block the chain lacks,
+    /// serves an invalid one, or forks off before the pruned blocks of the chain.
+    fn fetch_chain<S: BlockSource<StupidBlock>>(&self, source: &S) -> Result<Option<Vec<StupidBlock>>, Box<Error>> {
+        let tip = source.tip()?;
+        if tip.genesis != self.genesis.hash() {
+            return Ok(None);
+        }
+        if tip.height == 0 || self.block(tip.height).map(Self::hash) == Some(tip.hash.clone()) {
+            return Ok(None);
+        }
+
+        let shared = sync::common_ancestor(&self.chain, tip.height, source)?;
+        let mut chain = self.chain[..shared].to_vec();
+        let mut balances = match self.replay(&chain) {
+            Some(balances) => balances,
+            None => return Ok(None)
+        };
+
+        let is_valid = sync::fetch_after(source, shared, &tip, |page| {
+            for block in page {
+                if self.validate_block(&chain, &block, &mut balances).is_err() {
+                    return false;
+                }
+                chain.push(block);
+            }
+            true
+        })?;
+        Ok(if is_valid { Some(chain) } else { None })
     }
 
-    fn is_valid(&self) -> bool {
-        Self::is_valid_chain(&self.chain)
+    /// Checks whether `candidate` carries more work than `current`, ties going to the lowest
+    /// tip hash.
+    pub fn is_better_chain(candidate: &[StupidBlock], current: &[StupidBlock]) -> bool {
+        let tip_hash = |chain: &[StupidBlock]| chain.last().map(Self::hash).unwrap_or_default();
+        pow::is_better_chain(candidate, &tip_hash(candidate), current, &tip_hash(current))
     }
-}
 
-/// Defines a stupid block of transactions in the blockchain.
-#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-pub struct StupidBlock {
-    index: usize,
-    timestamp: Timestamp,
-    transactions: Vec<StupidTransaction>,
-    pub proof: usize,
-    previous_hash: Vec<u8>
-}
+    /// Switches to `chain`, returning the transfers of the rolled back blocks to the pending
+    /// transactions when they still apply.
+    fn reorganize(&mut self, chain: Vec<StupidBlock>) -> io::Result<Reorg> {
+        if let Some(ref mut storage) = self.storage {
+            storage.replace(&chain)?;
+        }
 
-impl StupidBlock {
-    pub fn new(index: usize, timestamp: Timestamp, transactions: Vec<StupidTransaction>,
-        proof: usize, previous_hash: Vec<u8>) -> StupidBlock
-    {
-        StupidBlock { index, timestamp, transactions, proof, previous_hash }
+        let fork = self.chain.iter().zip(&chain).take_while(|&(old, new)| old == new).count();
+        let rolled_back = self.chain.split_off(fork);
+        let applied = chain[fork..].to_vec();
+        self.chain = chain;
+        self.index.truncate(fork);
+        self.update_index();
+        self.update_snapshot()?;
+        self.update_confirmed();
+        if ! rolled_back.is_empty() {
+            self.counters.reorgs += 1;
+        }
+
+        let pending = self.mempool.drain();
+        let now = Timestamp::current_nanos();
+        for tx in rolled_back.iter().flat_map(|block| block.transactions().iter()).filter(|tx| ! tx.is_from_miner()) {
+            let _ = self.mempool.insert(Entry::new(tx.clone(), tx.hash(), tx.fee(), now.clone()));
+        }
+        for entry in pending {
+            let _ = self.mempool.insert(entry);
+        }
+        self.prune_pending();
+
+        let returned = self.mempool.iter()
+            .filter(|entry| rolled_back.iter().any(|block| block.transactions().contains(&entry.tx)))
+            .map(|entry| entry.tx.clone())
+            .collect();
+        Ok(Reorg { rolled_back, applied, returned })
     }
-}
 
-/// Makes StupidBlock hashable.
-impl ObjectHash for StupidBlock {
-    #[inline]
-    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
-        objecthash_struct!(
-            hasher,
-            "index" => &self.index,
-            "timestamp" => &self.timestamp,
-            "transactions" => &self.transactions,
-            "proof" => &self.proof,
-            "previous_hash" => &self.previous_hash
-        )
+    /// Snapshots what the seal of the block that follows `chain` has to satisfy.
+    fn target_of(&self, chain: &[StupidBlock]) -> Target {
+        Target {
+            height: chain.len(),
+            last_block_hash: chain.last().map_or(self.genesis.hash(), Self::hash),
+            difficulty: self.next_difficulty(chain)
+        }
     }
-}
 
-impl Block for StupidBlock {
-    fn is_valid(&self) -> bool {
-        true
+    /// Computes the confirmed balances once `block`, prepared by the node, is on top of
+    /// the chain.
+    fn confirmed_after(&self, block: &StupidBlock) -> Result<Balances, BlockError> {
+        let mut confirmed = self.confirmed.clone();
+        confirmed.apply_block(block)
+            .map_err(|error| BlockError::InvalidTransaction { index: block.index(), error })?;
+        Ok(confirmed)
     }
-}
 
-/// Defines a stupid transaction in the blockchain.
-#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-pub struct StupidTransaction {
-    sender: String,
-    recipient: String,
-    amount: usize
-}
+    /// Stores `block` and puts it on top of the chain, with `confirmed` as the balances
+    /// after it, dropping the pending transactions that no longer apply.
+    fn append(&mut self, block: StupidBlock, confirmed: Balances) -> Result<(), BlockError> {
+        if let Some(ref mut storage) = self.storage {
+            storage.append(&block)?;
+        }
+        let takes_snapshot = block.state_root().is_some();
+        self.chain.push(block);
+        self.confirmed = confirmed;
+        self.update_index();
+        if takes_snapshot {
+            self.update_snapshot()?;
+        }
+        self.prune_pending();
+        Ok(())
+    }
 
-impl StupidTransaction {
-    pub fn new(sender: String, recipient: String, amount: usize) -> StupidTransaction {
-        StupidTransaction { sender, recipient, amount }
+    fn last_block_hash(&self) -> Hash {
+        if let Some(last_block) = self.last_block() {
+            Self::hash(last_block)
+        } else {
+            self.genesis.hash()
+        }
     }
-}
 
-/// Makes StupidTransaction hashable.
-impl ObjectHash for StupidTransaction {
-    #[inline]
-    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
-        objecthash_struct!(
-            hasher,
-            "sender" => &self.sender,
-            "recipient" => &self.recipient,
-            "amount" => &self.amount
-        )
+    /// Counts the transactions that the blocks of `chain` hold.
+    fn count_transactions(chain: &[StupidBlock]) -> usize {
+        chain.iter().map(|block| block.transactions().len()).sum()
     }
-}
 
-impl Transaction for StupidTransaction {
-    fn is_valid(&self) -> bool {
-        true
+    /// Indexes the blocks of the chain that the index lacks.
+    fn update_index(&mut self) {
+        for block in self.chain.iter().skip(self.index.height()) {
+            let transactions = block.transactions().iter().map(|tx| (tx.hash(), tx.addresses())).collect();
+            self.index.push(Self::hash(block), transactions);
+        }
     }
-}
 
-/// Makes Timestamp hashable.
-impl ObjectHash for Timestamp {
-    #[inline]
-    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
-        objecthash_struct!(
-            hasher,
-            "value" => &(self.0.as_secs()*(1e9 as u64) + self.0.subsec_nanos() as u64)
-        )
+    /// Brings the snapshot up to the latest snapshot block of the chain, then prunes the
+    /// chain if the ledger prunes.
+    fn update_snapshot(&mut self) -> io::Result<()> {
+        let height = match self.chain.iter().rposition(|block| block.state_root().is_some()) {
+            Some(position) => position + 1,
+            None => {
+                self.snapshot = None;
+                return Ok(());
+            }
+        };
+        let is_current = match self.snapshot {
+            Some(ref snapshot) => {
+                snapshot.height == height && self.chain[height - 1].state_root() == Some(&snapshot.hash())
+            },
+            None => false
+        };
+        if ! is_current {
+            self.snapshot = self.replay(&self.chain[..height]).map(|balances| balances.snapshot(height));
+        }
+
+        if self.pruning {
+            self.prune()?;
+        }
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::time::Duration;
-    use super::*;
+    /// Computes the balances after `chain`, starting from the snapshot when `chain` holds
+    /// its block, and from the genesis otherwise.
+    ///
+    /// Gives nothing when `chain` holds a pruned block that the snapshot does not stand
+    /// for, or a transaction that cannot be applied.
+    fn replay(&self, chain: &[StupidBlock]) -> Option<Balances> {
+        let (mut balances, start) = match self.snapshot {
+            Some(ref snapshot) if snapshot.height <= chain.len()
+                && chain[snapshot.height - 1].state_root() == Some(&snapshot.hash()) =>
+            {
+                (Balances::from_snapshot(snapshot), snapshot.height)
+            },
+            _ => (Balances::from_genesis(&self.genesis).ok()?, 0)
+        };
+        for block in &chain[start..] {
+            if block.is_pruned() {
+                return None;
+            }
+            balances.apply_block(block).ok()?;
+        }
+        Some(balances)
+    }
 
-    #[test]
-    fn add_transaction() {
-        let tx = create_transaction();
-        let mut stupid_chain = StupidLedger::new(Vec::new());
-        assert_eq!(stupid_chain.add_transaction(tx.clone()).unwrap(), 1);
+    /// Gets the transaction at `location` in the chain.
+    fn located(&self, location: Location) -> Option<&StupidTransaction> {
+        self.block(location.block).and_then(|block| block.transactions().get(location.position))
     }
 
-    #[test]
-    fn new_block() {
-        let tx = create_transaction();
-        let mut stupid_chain = StupidLedger::new(Vec::new());
-        stupid_chain.add_transaction(tx.clone()).expect("bad transaction");
+    /// Places the next block at `timestamp`, for scripts to check time locks against.
+    fn next_clock(&self, timestamp: Timestamp) -> Clock {
+        Clock { index: self.chain.len() + 1, timestamp }
+    }
 
-        let timestamp = Timestamp::new(Duration::new(12345, 0));
-        assert_eq!(stupid_chain.new_block(timestamp.clone(), 1000).unwrap()[0],
-            StupidBlock::new(1, timestamp, vec![tx.clone()], 1000, vec![0; 32]));
+    /// Rebuilds the confirmed balances from the snapshot, or from the genesis, once the
+    /// chain got replaced.
+    fn update_confirmed(&mut self) {
+        self.confirmed = self.replay(&self.chain).expect("Ledger chain is valid");
+    }
+
+    /// Keeps the pending transfers that still apply on top of the chain.
+    fn prune_pending(&mut self) {
+        let mut balances = self.confirmed.clone();
+        let clock = self.next_clock(Timestamp::current_nanos());
+        self.mempool.retain(|entry| balances.apply(&entry.tx, &clock).is_ok());
+    }
+
+    /// Picks up to `MAX_BLOCK_TRANSACTIONS` pending transfers, the highest fee rates first,
+    /// for the block at `clock`.
+    ///
+    /// A transfer that depends on another one not picked yet waits for another pass.
+    fn select_transactions(&self, clock: &Clock) -> Vec<StupidTransaction> {
+        let mut balances = self.confirmed.clone();
+        let candidates = self.mempool.by_fee_rate();
+        let mut selected = Vec::new();
+        loop {
+            let count = selected.len();
+            for entry in &candidates {
+                if selected.len() == MAX_BLOCK_TRANSACTIONS {
+                    return selected;
+                }
+                if balances.apply(&entry.tx, clock).is_ok() {
+                    selected.push(entry.tx.clone());
+                }
+            }
+            if selected.len() == count {
+                return selected;
+            }
+        }
+    }
+}
+
+impl DistributedLedger<StupidBlock, StupidTransaction> for StupidLedger {
+    type LedgerRepr = Vec<StupidBlock>;
+    type Proof = Seal;
+
+    fn hash(obj: &StupidBlock) -> Hash {
+        Hash::digest(obj)
+    }
+
+    /// Appends the next block with `seal`, trusting the seal without checking it.
+    fn new_block(&mut self, timestamp: Timestamp, seal: Self::Proof) -> Result<&Self::LedgerRepr, BlockError> {
+        let block = self.prepare_block(timestamp)?;
+        let confirmed = self.confirmed_after(&block)?;
+        self.append(block.with_seal(seal), confirmed)?;
+        Ok(&self.chain)
+    }
+
+    fn add_transaction(&mut self, tx: StupidTransaction) -> Result<usize, TransactionError> {
+        if tx.is_reward() {
+            return Err(TransactionError::UnexpectedReward);
+        }
+        if tx.is_snapshot() {
+            return Err(TransactionError::UnexpectedSnapshot);
+        }
+        if ! tx.is_valid() {
+            return Err(TransactionError::InvalidSignature);
+        }
+        self.balances().apply(&tx, &self.next_clock(Timestamp::current_nanos()))?;
+        let entry = Entry::new(tx.clone(), tx.hash(), tx.fee(), Timestamp::current_nanos());
+        if self.mempool.insert(entry)?.is_some() {
+            self.prune_pending();
+        }
+        Ok(self.chain.len() + 1)
+    }
+
+    fn last_block(&self) -> Option<&StupidBlock> {
+        self.chain.last()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.is_valid_chain(&self.chain)
+    }
+}
+
+/// Report of a switch to another fork.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Reorg {
+    /// Blocks of the old chain that are not part of the new one.
+    pub rolled_back: Vec<StupidBlock>,
+    /// Blocks of the new chain that were not part of the old one.
+    pub applied: Vec<StupidBlock>,
+    /// Transfers of the rolled back blocks that went back to the pending transactions.
+    pub returned: Vec<StupidTransaction>
+}
+
+/// Outcome of syncing with several sources.
+#[derive(Debug)]
+pub struct Synced {
+    /// Switch to the chain of a source, if a source has a better chain.
+    pub reorg: Option<Reorg>,
+    /// Errors of the sources that failed, along with their position among the sources.
+    pub failures: Vec<(usize, Box<Error>)>
+}
+
+/// Sync with peers, detached from the ledger it started from.
+#[derive(Debug)]
+pub struct PeerSync {
+    /// Copy of the chain, along with what validating blocks on top of it takes.
+    ledger: StupidLedger,
+    urls: Vec<String>,
+    started: Timestamp
+}
+
+impl PeerSync {
+    /// Fetches from every peer, keeping the best valid chain if it carries more work than
+    /// the chain the sync started from.
+    pub fn fetch(self) -> PeerSynced {
+        let PeerSync { ledger, urls, started } = self;
+        let (mut reachable, mut sources, mut failed) = (Vec::new(), Vec::new(), Vec::new());
+        for url in urls {
+            match HttpPeer::new(&url) {
+                Ok(source) => {
+                    reachable.push(url);
+                    sources.push(source);
+                },
+                Err(_) => failed.push(url)
+            }
+        }
+
+        let (chain, failures) = ledger.fetch_best(&sources);
+        let mut seen = Vec::new();
+        for (position, url) in reachable.into_iter().enumerate() {
+            if failures.iter().any(|&(failure, _)| failure == position) {
+                failed.push(url);
+            } else {
+                seen.push(url);
+            }
+        }
+        PeerSynced { chain, seen, failed, started }
+    }
+}
+
+/// Outcome of a sync with peers, to hand back to the ledger with `finish_sync`.
+#[derive(Debug)]
+pub struct PeerSynced {
+    chain: Option<Vec<StupidBlock>>,
+    seen: Vec<String>,
+    failed: Vec<String>,
+    started: Timestamp
+}
+
+/// Statistics of a chain.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct ChainStats {
+    pub height: usize,
+    pub transactions: usize,
+    /// Average time between two blocks, in seconds, once the chain has two blocks.
+    pub average_block_time: Option<f64>,
+    /// Difficulty required from the next block.
+    pub difficulty: u32
+}
+
+/// Sums the fees of `transactions`, unless the sum overflows.
+fn total_fees(transactions: &[StupidTransaction]) -> Option<usize> {
+    transactions.iter().try_fold(0, |fees: usize, tx| fees.checked_add(tx.fee()))
+}
+
+fn default_consensus() -> Arc<Consensus<StupidBlock>> {
+    Arc::new(ProofOfWork::new(INITIAL_DIFFICULTY))
+}
+
+fn as_secs(timestamp: &Timestamp) -> f64 {
+    timestamp.0.as_secs() as f64 + timestamp.0.subsec_nanos() as f64 / 1e9
+}
+
+/// Finds the median timestamp of the last `MEDIAN_TIME_BLOCKS` blocks of `chain`.
+fn median_timestamp(chain: &[StupidBlock]) -> Option<Duration> {
+    let start = chain.len().saturating_sub(MEDIAN_TIME_BLOCKS);
+    let mut timestamps: Vec<Duration> = chain[start..].iter().map(|block| block.header.timestamp.0).collect();
+    timestamps.sort();
+    timestamps.get(timestamps.len() / 2).cloned()
+}
+
+/// Defines a stupid block of transactions in the blockchain.
+///
+/// The block hash and seal only cover the header, which commits to the transactions
+/// through their Merkle root. A pruned block keeps its header only.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StupidBlock {
+    #[serde(flatten)]
+    header: StupidHeader,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    transactions: Option<Vec<StupidTransaction>>
+}
+
+impl StupidBlock {
+    pub fn new(index: usize, timestamp: Timestamp, transactions: Vec<StupidTransaction>,
+        seal: Seal, difficulty: u32, previous_hash: Hash) -> StupidBlock
+    {
+        let transactions_root = Self::compute_transactions_root(&transactions);
+        let header = StupidHeader { index, timestamp, previous_hash, transactions_root, seal, difficulty };
+        StupidBlock { header, transactions: Some(transactions) }
+    }
+
+    pub fn header(&self) -> &StupidHeader {
+        &self.header
+    }
+
+    pub fn index(&self) -> usize {
+        self.header.index
+    }
+
+    pub fn seal(&self) -> &Seal {
+        &self.header.seal
+    }
+
+    /// Replaces the seal of the block.
+    pub fn with_seal(mut self, seal: Seal) -> StupidBlock {
+        self.header.seal = seal;
+        self
+    }
+
+    /// Gets the transactions of the block, none once it is pruned.
+    pub fn transactions(&self) -> &[StupidTransaction] {
+        self.transactions.as_ref().map_or(&[], |transactions| transactions)
+    }
+
+    pub fn is_pruned(&self) -> bool {
+        self.transactions.is_none()
+    }
+
+    /// Copies the block without its transactions.
+    pub fn pruned(&self) -> StupidBlock {
+        StupidBlock { header: self.header.clone(), transactions: None }
+    }
+
+    /// Gets the hash of the state snapshot that the block commits to, if it takes one.
+    pub fn state_root(&self) -> Option<&Hash> {
+        match self.transactions().last() {
+            Some(&StupidTransaction::Snapshot { ref state_root }) => Some(state_root),
+            _ => None
+        }
+    }
+
+    /// Gets the Merkle root of the transaction hashes, which the block hash covers
+    /// instead of the transactions themselves.
+    pub fn transactions_root(&self) -> &Hash {
+        &self.header.transactions_root
+    }
+
+    /// Proves that the transaction at `index` is part of this block.
+    pub fn transaction_proof(&self, index: usize) -> Option<MerkleProof> {
+        let hashes: Vec<Hash> = self.transactions().iter().map(|tx| tx.hash()).collect();
+        merkle::prove(&hashes, index)
+    }
+
+    fn compute_transactions_root(transactions: &[StupidTransaction]) -> Hash {
+        let hashes: Vec<Hash> = transactions.iter().map(|tx| tx.hash()).collect();
+        merkle::root(&hashes)
+    }
+}
+
+/// Compares blocks by their headers, and by their transactions unless one of them is
+/// pruned.
+impl PartialEq for StupidBlock {
+    fn eq(&self, other: &StupidBlock) -> bool {
+        self.header == other.header && (self.is_pruned() || other.is_pruned() || self.transactions == other.transactions)
+    }
+}
+
+/// Makes StupidBlock hashable, by the hash of its header.
+impl ObjectHash for StupidBlock {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        self.header.objecthash(hasher)
+    }
+}
+
+/// Encodes a block as its header followed by its transactions, if it is not pruned.
+impl Encode for StupidBlock {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.header.encode(out);
+        self.transactions.encode(out);
+    }
+}
+
+impl Decode for StupidBlock {
+    fn decode(reader: &mut Reader) -> Result<StupidBlock, DecodeError> {
+        let header = StupidHeader::decode(reader)?;
+        let transactions = Option::decode(reader)?;
+        Ok(StupidBlock { header, transactions })
+    }
+}
+
+impl StupidBlock {
+    /// Checks that the block holds at most one reward, of `MINING_REWARD` plus the block
+    /// fees, for its own index.
+    fn has_valid_reward(&self) -> bool {
+        let fees = match total_fees(self.transactions()) {
+            Some(fees) => fees,
+            None => return false
+        };
+        let mut rewards = 0;
+        for tx in self.transactions() {
+            if let StupidTransaction::Reward { amount, index, .. } = *tx {
+                rewards += 1;
+                if Some(amount) != fees.checked_add(MINING_REWARD) || index != self.header.index {
+                    return false;
+                }
+            }
+        }
+        rewards <= 1
+    }
+
+    /// Checks that the block ends with a snapshot if `genesis` calls for one at its index,
+    /// and holds none otherwise.
+    fn has_valid_snapshot(&self, genesis: &Genesis) -> bool {
+        let snapshots = self.transactions().iter().filter(|tx| tx.is_snapshot()).count();
+        if genesis.is_snapshot_height(self.header.index) {
+            snapshots == 1 && self.state_root().is_some()
+        } else {
+            snapshots == 0
+        }
+    }
+
+    fn prune(&mut self) {
+        self.transactions = None;
+    }
+}
+
+impl Block for StupidBlock {
+    fn timestamp(&self) -> &Timestamp {
+        &self.header.timestamp
+    }
+
+    fn difficulty(&self) -> u32 {
+        self.header.difficulty
+    }
+
+    /// Checks that the transactions match the Merkle root covered by the block hash, which
+    /// a pruned block cannot show.
+    fn is_valid(&self) -> bool {
+        match self.transactions {
+            Some(ref transactions) => self.header.transactions_root == Self::compute_transactions_root(transactions),
+            None => false
+        }
+    }
+}
+
+/// Header of a stupid block, which can be hashed and validated without the transactions.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct StupidHeader {
+    pub index: usize,
+    pub timestamp: Timestamp,
+    pub previous_hash: Hash,
+    pub transactions_root: Hash,
+    #[serde(flatten)]
+    pub seal: Seal,
+    pub difficulty: u32
+}
+
+impl StupidHeader {
+    /// Computes the digest covered by the seal, i.e. the hash of the header without its seal.
+    pub fn digest(&self) -> Hash {
+        Hash::digest(&UnsealedHeader(self))
+    }
+}
+
+/// Makes StupidHeader hashable.
+///
+/// A proof of work is hashed as the `proof` field, and a signature as the `signature` field.
+impl ObjectHash for StupidHeader {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        match self.seal {
+            Seal::Work { ref proof } => {
+                objecthash_struct!(
+                    hasher,
+                    "index" => &self.index,
+                    "timestamp" => &self.timestamp,
+                    "transactions_root" => &self.transactions_root,
+                    "proof" => proof,
+                    "difficulty" => &self.difficulty,
+                    "previous_hash" => &self.previous_hash
+                )
+            },
+            Seal::Authority { ref signature } => {
+                objecthash_struct!(
+                    hasher,
+                    "index" => &self.index,
+                    "timestamp" => &self.timestamp,
+                    "transactions_root" => &self.transactions_root,
+                    "signature" => signature,
+                    "difficulty" => &self.difficulty,
+                    "previous_hash" => &self.previous_hash
+                )
+            }
+        }
+    }
+}
+
+impl Encode for StupidHeader {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.index.encode(out);
+        self.timestamp.encode(out);
+        self.previous_hash.encode(out);
+        self.transactions_root.encode(out);
+        self.seal.encode(out);
+        self.difficulty.encode(out);
+    }
+}
+
+impl Decode for StupidHeader {
+    fn decode(reader: &mut Reader) -> Result<StupidHeader, DecodeError> {
+        Ok(StupidHeader {
+            index: usize::decode(reader)?,
+            timestamp: Timestamp::decode(reader)?,
+            previous_hash: Hash::decode(reader)?,
+            transactions_root: Hash::decode(reader)?,
+            seal: Seal::decode(reader)?,
+            difficulty: u32::decode(reader)?
+        })
+    }
+}
+
+/// Header fields covered by the seal.
+struct UnsealedHeader<'a>(&'a StupidHeader);
+
+/// Makes UnsealedHeader hashable.
+impl<'a> ObjectHash for UnsealedHeader<'a> {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        objecthash_struct!(
+            hasher,
+            "index" => &self.0.index,
+            "timestamp" => &self.0.timestamp,
+            "transactions_root" => &self.0.transactions_root,
+            "difficulty" => &self.0.difficulty,
+            "previous_hash" => &self.0.previous_hash
+        )
+    }
+}
+
+/// Defines a stupid transaction in the blockchain.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StupidTransaction {
+    /// Moves `amount` from `sender` to `recipient`, authorized by the sender's signature.
+    ///
+    /// The sender also pays `fee` to the miner of the block holding the transfer.
+    Transfer {
+        sender: Address,
+        recipient: Address,
+        amount: usize,
+        #[serde(default)]
+        fee: usize,
+        nonce: u64,
+        signature: Signature
+    },
+    /// Rewards `recipient` for mining the block at `index`.
+    Reward {
+        recipient: Address,
+        amount: usize,
+        index: usize
+    },
+    /// Moves `amount` from `sender` into a lock that `script` guards, authorized by the
+    /// sender's signature.
+    ///
+    /// The sender pays `fee` on top of the amount, like for a transfer.
+    Lock {
+        sender: Address,
+        script: Script,
+        amount: usize,
+        #[serde(default)]
+        fee: usize,
+        nonce: u64,
+        signature: Signature
+    },
+    /// Moves the amount of the lock with hash `lock` to `recipient`, once the script of the
+    /// lock accepts the `witness` signatures.
+    ///
+    /// The `fee` comes out of the locked amount.
+    Unlock {
+        lock: Hash,
+        recipient: Address,
+        #[serde(default)]
+        fee: usize,
+        witness: Vec<Signature>
+    },
+    /// Commits the block that it ends to the hash of the snapshot of the state after the
+    /// block.
+    Snapshot {
+        state_root: Hash
+    }
+}
+
+impl StupidTransaction {
+    /// Creates a transfer from the owner of `keypair`, signed with it.
+    ///
+    /// The `nonce` only distinguishes otherwise identical transfers.
+    pub fn transfer(keypair: &Keypair, recipient: Address, amount: usize, fee: usize, nonce: u64) -> StupidTransaction {
+        let sender = keypair.address();
+        let signature = keypair.sign(Self::transfer_digest(&sender, &recipient, amount, fee, nonce).as_bytes());
+        StupidTransaction::Transfer { sender, recipient, amount, fee, nonce, signature }
+    }
+
+    pub fn reward(recipient: Address, amount: usize, index: usize) -> StupidTransaction {
+        StupidTransaction::Reward { recipient, amount, index }
+    }
+
+    pub fn snapshot(state_root: Hash) -> StupidTransaction {
+        StupidTransaction::Snapshot { state_root }
+    }
+
+    /// Creates a lock of funds of the owner of `keypair` behind `script`, signed with it.
+    pub fn lock(keypair: &Keypair, script: Script, amount: usize, fee: usize, nonce: u64) -> StupidTransaction {
+        let sender = keypair.address();
+        let signature = keypair.sign(Self::lock_digest(&sender, &script, amount, fee, nonce).as_bytes());
+        StupidTransaction::Lock { sender, script, amount, fee, nonce, signature }
+    }
+
+    /// Creates an unlock of the lock with hash `lock`, witnessed by every keypair of
+    /// `signers`.
+    pub fn unlock(lock: Hash, recipient: Address, fee: usize, signers: &[&Keypair]) -> StupidTransaction {
+        let digest = Self::unlock_digest(&lock, &recipient, fee);
+        let witness = signers.iter().map(|keypair| keypair.sign(digest.as_bytes())).collect();
+        StupidTransaction::Unlock { lock, recipient, fee, witness }
+    }
+
+    pub fn hash(&self) -> Hash {
+        Hash::digest(self)
+    }
+
+    /// Gets the fee paid to the miner, which rewards do not pay.
+    pub fn fee(&self) -> usize {
+        match *self {
+            StupidTransaction::Transfer { fee, .. } |
+            StupidTransaction::Lock { fee, .. } |
+            StupidTransaction::Unlock { fee, .. } => fee,
+            StupidTransaction::Reward { .. } |
+            StupidTransaction::Snapshot { .. } => 0
+        }
+    }
+
+    pub fn is_reward(&self) -> bool {
+        match *self {
+            StupidTransaction::Reward { .. } => true,
+            _ => false
+        }
+    }
+
+    pub fn is_snapshot(&self) -> bool {
+        match *self {
+            StupidTransaction::Snapshot { .. } => true,
+            _ => false
+        }
+    }
+
+    /// Checks whether the miner of the block adds the transaction, like rewards and
+    /// snapshots.
+    pub fn is_from_miner(&self) -> bool {
+        self.is_reward() || self.is_snapshot()
+    }
+
+    /// Checks whether `address` sends or receives the transaction.
+    pub fn involves(&self, address: &Address) -> bool {
+        match *self {
+            StupidTransaction::Transfer { ref sender, ref recipient, .. } => sender == address || recipient == address,
+            StupidTransaction::Lock { ref sender, .. } => sender == address,
+            StupidTransaction::Reward { ref recipient, .. } |
+            StupidTransaction::Unlock { ref recipient, .. } => recipient == address,
+            StupidTransaction::Snapshot { .. } => false
+        }
+    }
+
+    /// Lists the addresses that send or receive the transaction.
+    pub fn addresses(&self) -> Vec<Address> {
+        match *self {
+            StupidTransaction::Transfer { ref sender, ref recipient, .. } if sender == recipient => vec![sender.clone()],
+            StupidTransaction::Transfer { ref sender, ref recipient, .. } => vec![sender.clone(), recipient.clone()],
+            StupidTransaction::Lock { ref sender, .. } => vec![sender.clone()],
+            StupidTransaction::Reward { ref recipient, .. } |
+            StupidTransaction::Unlock { ref recipient, .. } => vec![recipient.clone()],
+            StupidTransaction::Snapshot { .. } => Vec::new()
+        }
+    }
+
+    /// Computes the digest covered by the signature of a transfer.
+    fn transfer_digest(sender: &Address, recipient: &Address, amount: usize, fee: usize, nonce: u64) -> Hash {
+        Hash::digest(&UnsignedTransfer { sender, recipient, amount, fee, nonce })
+    }
+
+    /// Computes the digest covered by the signature of a lock.
+    fn lock_digest(sender: &Address, script: &Script, amount: usize, fee: usize, nonce: u64) -> Hash {
+        Hash::digest(&UnsignedLock { sender, script, amount, fee, nonce })
+    }
+
+    /// Computes the digest covered by the witness signatures of an unlock.
+    fn unlock_digest(lock: &Hash, recipient: &Address, fee: usize) -> Hash {
+        Hash::digest(&UnwitnessedUnlock { lock, recipient, fee })
+    }
+}
+
+/// Makes StupidTransaction hashable.
+impl ObjectHash for StupidTransaction {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        match *self {
+            StupidTransaction::Transfer { ref sender, ref recipient, ref amount, ref fee, ref nonce, ref signature } => {
+                objecthash_struct!(
+                    hasher,
+                    "type" => &String::from("transfer"),
+                    "sender" => sender,
+                    "recipient" => recipient,
+                    "amount" => amount,
+                    "fee" => fee,
+                    "nonce" => nonce,
+                    "signature" => signature
+                )
+            },
+            StupidTransaction::Reward { ref recipient, ref amount, ref index } => {
+                objecthash_struct!(
+                    hasher,
+                    "type" => &String::from("reward"),
+                    "recipient" => recipient,
+                    "amount" => amount,
+                    "index" => index
+                )
+            },
+            StupidTransaction::Lock { ref sender, ref script, ref amount, ref fee, ref nonce, ref signature } => {
+                objecthash_struct!(
+                    hasher,
+                    "type" => &String::from("lock"),
+                    "sender" => sender,
+                    "script" => script,
+                    "amount" => amount,
+                    "fee" => fee,
+                    "nonce" => nonce,
+                    "signature" => signature
+                )
+            },
+            StupidTransaction::Unlock { ref lock, ref recipient, ref fee, ref witness } => {
+                objecthash_struct!(
+                    hasher,
+                    "type" => &String::from("unlock"),
+                    "lock" => lock,
+                    "recipient" => recipient,
+                    "fee" => fee,
+                    "witness" => witness
+                )
+            },
+            StupidTransaction::Snapshot { ref state_root } => {
+                objecthash_struct!(
+                    hasher,
+                    "type" => &String::from("snapshot"),
+                    "state_root" => state_root
+                )
+            }
+        }
+    }
+}
+
+/// Encodes a transaction as a tag, 0 for a transfer, 1 for a reward, 2 for a lock, 3 for an
+/// unlock and 4 for a snapshot, followed by its fields.
+impl Encode for StupidTransaction {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            StupidTransaction::Transfer { ref sender, ref recipient, amount, fee, nonce, ref signature } => {
+                out.push(0);
+                sender.encode(out);
+                recipient.encode(out);
+                amount.encode(out);
+                fee.encode(out);
+                nonce.encode(out);
+                signature.encode(out);
+            },
+            StupidTransaction::Reward { ref recipient, amount, index } => {
+                out.push(1);
+                recipient.encode(out);
+                amount.encode(out);
+                index.encode(out);
+            },
+            StupidTransaction::Lock { ref sender, ref script, amount, fee, nonce, ref signature } => {
+                out.push(2);
+                sender.encode(out);
+                script.encode(out);
+                amount.encode(out);
+                fee.encode(out);
+                nonce.encode(out);
+                signature.encode(out);
+            },
+            StupidTransaction::Unlock { ref lock, ref recipient, fee, ref witness } => {
+                out.push(3);
+                lock.encode(out);
+                recipient.encode(out);
+                fee.encode(out);
+                witness.encode(out);
+            },
+            StupidTransaction::Snapshot { ref state_root } => {
+                out.push(4);
+                state_root.encode(out);
+            }
+        }
+    }
+}
+
+impl Decode for StupidTransaction {
+    fn decode(reader: &mut Reader) -> Result<StupidTransaction, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(StupidTransaction::Transfer {
+                sender: Address::decode(reader)?,
+                recipient: Address::decode(reader)?,
+                amount: usize::decode(reader)?,
+                fee: usize::decode(reader)?,
+                nonce: u64::decode(reader)?,
+                signature: Signature::decode(reader)?
+            }),
+            1 => Ok(StupidTransaction::Reward {
+                recipient: Address::decode(reader)?,
+                amount: usize::decode(reader)?,
+                index: usize::decode(reader)?
+            }),
+            2 => Ok(StupidTransaction::Lock {
+                sender: Address::decode(reader)?,
+                script: Script::decode(reader)?,
+                amount: usize::decode(reader)?,
+                fee: usize::decode(reader)?,
+                nonce: u64::decode(reader)?,
+                signature: Signature::decode(reader)?
+            }),
+            3 => Ok(StupidTransaction::Unlock {
+                lock: Hash::decode(reader)?,
+                recipient: Address::decode(reader)?,
+                fee: usize::decode(reader)?,
+                witness: Vec::decode(reader)?
+            }),
+            4 => Ok(StupidTransaction::Snapshot { state_root: Hash::decode(reader)? }),
+            tag => Err(DecodeError::UnknownTag { tag })
+        }
+    }
+}
+
+impl Transaction for StupidTransaction {
+    /// Checks what a transaction can prove on its own: signatures, and the size of scripts
+    /// and witnesses. Scripts themselves run when the transaction is applied, since their
+    /// time locks depend on the block holding it.
+    fn is_valid(&self) -> bool {
+        match *self {
+            StupidTransaction::Transfer { ref sender, ref recipient, amount, fee, nonce, ref signature } => {
+                sender.verify(Self::transfer_digest(sender, recipient, amount, fee, nonce).as_bytes(), signature)
+            },
+            StupidTransaction::Lock { ref sender, ref script, amount, fee, nonce, ref signature } => {
+                script.len() <= script::MAX_SCRIPT_LEN &&
+                    sender.verify(Self::lock_digest(sender, script, amount, fee, nonce).as_bytes(), signature)
+            },
+            // No script check takes more signatures than keys.
+            StupidTransaction::Unlock { ref witness, .. } => witness.len() as u64 <= script::MAX_KEYS,
+            StupidTransaction::Reward { .. } |
+            StupidTransaction::Snapshot { .. } => true
+        }
+    }
+}
+
+/// Transfer fields covered by the sender's signature.
+struct UnsignedTransfer<'a> {
+    sender: &'a Address,
+    recipient: &'a Address,
+    amount: usize,
+    fee: usize,
+    nonce: u64
+}
+
+/// Makes UnsignedTransfer hashable.
+impl<'a> ObjectHash for UnsignedTransfer<'a> {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        objecthash_struct!(
+            hasher,
+            "type" => &String::from("transfer"),
+            "sender" => self.sender,
+            "recipient" => self.recipient,
+            "amount" => &self.amount,
+            "fee" => &self.fee,
+            "nonce" => &self.nonce
+        )
+    }
+}
+
+/// Lock fields covered by the sender's signature.
+struct UnsignedLock<'a> {
+    sender: &'a Address,
+    script: &'a Script,
+    amount: usize,
+    fee: usize,
+    nonce: u64
+}
+
+/// Makes UnsignedLock hashable.
+impl<'a> ObjectHash for UnsignedLock<'a> {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        objecthash_struct!(
+            hasher,
+            "type" => &String::from("lock"),
+            "sender" => self.sender,
+            "script" => self.script,
+            "amount" => &self.amount,
+            "fee" => &self.fee,
+            "nonce" => &self.nonce
+        )
+    }
+}
+
+/// Unlock fields covered by the witness signatures.
+struct UnwitnessedUnlock<'a> {
+    lock: &'a Hash,
+    recipient: &'a Address,
+    fee: usize
+}
+
+/// Makes UnwitnessedUnlock hashable.
+impl<'a> ObjectHash for UnwitnessedUnlock<'a> {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        objecthash_struct!(
+            hasher,
+            "type" => &String::from("unlock"),
+            "lock" => self.lock,
+            "recipient" => self.recipient,
+            "fee" => &self.fee
+        )
+    }
+}
+
+/// Account balances derived from the transactions of a chain.
+#[derive(Debug, Clone, Default)]
+pub struct Balances {
+    accounts: HashMap<Address, usize>,
+    /// Amounts and scripts of the locks not spent yet, by the hash of their lock transaction.
+    locks: HashMap<Hash, (usize, Script)>,
+    seen: HashSet<Hash>
+}
+
+impl Balances {
+    /// Funds the accounts allocated by `genesis`, failing if an account overflows.
+    pub fn from_genesis(genesis: &Genesis) -> Result<Balances, TransactionError> {
+        let mut balances = Balances::default();
+        for allocation in &genesis.allocations {
+            let credited = balances.credited(&allocation.address, allocation.amount)?;
+            balances.accounts.insert(allocation.address.clone(), credited);
+        }
+        Ok(balances)
+    }
+
+    /// Replays every transaction of `chain` on top of `genesis`, failing on the first one
+    /// that cannot be applied.
+    pub fn from_chain(genesis: &Genesis, chain: &[StupidBlock]) -> Result<Balances, TransactionError> {
+        let mut balances = Balances::from_genesis(genesis)?;
+        for block in chain {
+            balances.apply_block(block)?;
+        }
+        Ok(balances)
+    }
+
+    /// Rebuilds the balances that `snapshot` stands for.
+    pub fn from_snapshot(snapshot: &Snapshot) -> Balances {
+        Balances {
+            accounts: snapshot.accounts.iter()
+                .map(|allocation| (allocation.address.clone(), allocation.amount))
+                .collect(),
+            locks: snapshot.locks.iter()
+                .map(|locked| (locked.lock.clone(), (locked.amount, locked.script.clone())))
+                .collect(),
+            seen: snapshot.seen.iter().cloned().collect()
+        }
+    }
+
+    /// Applies every transaction of a block, checking their signatures too.
+    pub fn apply_block(&mut self, block: &StupidBlock) -> Result<(), TransactionError> {
+        let clock = Clock { index: block.index(), timestamp: block.timestamp().clone() };
+        for tx in block.transactions() {
+            if ! tx.is_valid() {
+                return Err(TransactionError::InvalidSignature);
+            }
+            self.apply(tx, &clock)?;
+        }
+        Ok(())
+    }
+
+    pub fn balance(&self, address: &Address) -> usize {
+        *self.accounts.get(address).unwrap_or(&0)
+    }
+
+    /// Gets the amount held by the lock with hash `lock`, unless it is spent.
+    pub fn locked(&self, lock: &Hash) -> Option<usize> {
+        self.locks.get(lock).map(|&(amount, _)| amount)
+    }
+
+    /// Takes a snapshot of the balances, as they are after the block at `height`.
+    pub fn snapshot(&self, height: usize) -> Snapshot {
+        let mut accounts: Vec<Allocation> = self.accounts.iter()
+            .filter(|&(_, &amount)| amount > 0)
+            .map(|(address, &amount)| Allocation { address: address.clone(), amount })
+            .collect();
+        accounts.sort_by_key(|allocation| allocation.address.clone());
+        let mut locks: Vec<LockedFunds> = self.locks.iter()
+            .map(|(lock, &(amount, ref script))| LockedFunds { lock: lock.clone(), amount, script: script.clone() })
+            .collect();
+        locks.sort_by_key(|locked| locked.lock.clone());
+        let mut seen: Vec<Hash> = self.seen.iter().cloned().collect();
+        seen.sort();
+        Snapshot { height, accounts, locks, seen }
+    }
+
+    /// Applies a transaction in the block at `clock`, rejecting empty, duplicate and
+    /// overspending transfers, and unlocks that their lock script does not accept.
+    ///
+    /// Fees leave the sender here, and reach the miner through the block reward.
+    pub fn apply(&mut self, tx: &StupidTransaction, clock: &Clock) -> Result<(), TransactionError> {
+        match *tx {
+            StupidTransaction::Transfer { ref sender, ref recipient, amount, fee, .. } => {
+                // Checked before the debit, which leaves nothing to overflow for the sender.
+                if recipient != sender {
+                    self.credited(recipient, amount)?;
+                }
+                let id = self.debit(tx, sender, amount, fee)?;
+                let credited = self.credited(recipient, amount)?;
+                self.accounts.insert(recipient.clone(), credited);
+                self.seen.insert(id);
+            },
+            StupidTransaction::Lock { ref sender, ref script, amount, fee, .. } => {
+                let id = self.debit(tx, sender, amount, fee)?;
+                self.locks.insert(id.clone(), (amount, script.clone()));
+                self.seen.insert(id);
+            },
+            StupidTransaction::Unlock { ref lock, ref recipient, fee, ref witness } => {
+                let amount = match self.locks.get(lock) {
+                    Some(&(amount, ref script)) => {
+                        if fee > amount {
+                            return Err(TransactionError::Malformed { reason: String::from("Fee exceeds locked amount") });
+                        }
+                        let digest = StupidTransaction::unlock_digest(lock, recipient, fee);
+                        script.eval(&digest, witness, clock)
+                            .map_err(|error| TransactionError::ScriptFailed { error })?;
+                        amount
+                    },
+                    None => return Err(TransactionError::UnknownLock { hash: lock.clone() })
+                };
+
+                let credited = self.credited(recipient, amount - fee)?;
+                self.locks.remove(lock);
+                self.accounts.insert(recipient.clone(), credited);
+            },
+            StupidTransaction::Reward { ref recipient, amount, .. } => {
+                let credited = self.credited(recipient, amount)?;
+                self.accounts.insert(recipient.clone(), credited);
+            },
+            // The block holding it checks the snapshot against the balances once applied.
+            StupidTransaction::Snapshot { .. } => ()
+        }
+        Ok(())
+    }
+
+    /// Takes `amount` and `fee` from `sender` for `tx`, unless the transaction is empty, a
+    /// duplicate or overspends, and gets the hash of the transaction.
+    fn debit(&mut self, tx: &StupidTransaction, sender: &Address, amount: usize, fee: usize) -> Result<Hash, TransactionError> {
+        if amount == 0 {
+            return Err(TransactionError::ZeroAmount);
+        }
+        let id = tx.hash();
+        if self.seen.contains(&id) {
+            return Err(TransactionError::Duplicate { hash: id });
+        }
+        let total = amount.checked_add(fee)
+            .ok_or_else(|| TransactionError::Malformed { reason: String::from("Amount and fee overflow") })?;
+        let balance = self.balance(sender);
+        if balance < total {
+            return Err(TransactionError::InsufficientFunds { address: sender.clone(), balance, amount: total });
+        }
+
+        self.accounts.insert(sender.clone(), balance - total);
+        Ok(id)
+    }
+
+    /// Computes the balance of `recipient` once credited with `amount`, unless it overflows.
+    fn credited(&self, recipient: &Address, amount: usize) -> Result<usize, TransactionError> {
+        self.balance(recipient).checked_add(amount)
+            .ok_or_else(|| TransactionError::Malformed { reason: String::from("Balance overflows") })
+    }
+}
+
+/// State of the balances after the block at `height`, which stands for the transactions
+/// of the blocks up to it.
+///
+/// Lists are sorted, so that the same balances always take the same snapshot.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub height: usize,
+    /// Accounts holding funds, by address.
+    pub accounts: Vec<Allocation>,
+    /// Locks not spent yet, by hash.
+    pub locks: Vec<LockedFunds>,
+    /// Hashes of the transactions that cannot be applied again.
+    pub seen: Vec<Hash>
+}
+
+impl Snapshot {
+    pub fn hash(&self) -> Hash {
+        Hash::digest(self)
+    }
+}
+
+/// Makes Snapshot hashable.
+impl ObjectHash for Snapshot {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        objecthash_struct!(
+            hasher,
+            "height" => &self.height,
+            "accounts" => &self.accounts,
+            "locks" => &self.locks,
+            "seen" => &self.seen
+        )
+    }
+}
+
+impl Encode for Snapshot {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.height.encode(out);
+        self.accounts.encode(out);
+        self.locks.encode(out);
+        self.seen.encode(out);
+    }
+}
+
+impl Decode for Snapshot {
+    fn decode(reader: &mut Reader) -> Result<Snapshot, DecodeError> {
+        Ok(Snapshot {
+            height: usize::decode(reader)?,
+            accounts: Vec::decode(reader)?,
+            locks: Vec::decode(reader)?,
+            seen: Vec::decode(reader)?
+        })
+    }
+}
+
+/// Snapshot as kept in storage, along with the number of transactions in the blocks pruned
+/// before it.
+#[derive(Debug)]
+struct StoredSnapshot {
+    snapshot: Snapshot,
+    pruned_transactions: usize
+}
+
+impl Encode for StoredSnapshot {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.snapshot.encode(out);
+        self.pruned_transactions.encode(out);
+    }
+}
+
+impl Decode for StoredSnapshot {
+    fn decode(reader: &mut Reader) -> Result<StoredSnapshot, DecodeError> {
+        Ok(StoredSnapshot { snapshot: Snapshot::decode(reader)?, pruned_transactions: usize::decode(reader)? })
+    }
+}
+
+/// Funds held by a lock not spent yet.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct LockedFunds {
+    pub lock: Hash,
+    pub amount: usize,
+    pub script: Script
+}
+
+/// Makes LockedFunds hashable.
+impl ObjectHash for LockedFunds {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        objecthash_struct!(
+            hasher,
+            "lock" => &self.lock,
+            "amount" => &self.amount,
+            "script" => &self.script
+        )
+    }
+}
+
+impl Encode for LockedFunds {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.lock.encode(out);
+        self.amount.encode(out);
+        self.script.encode(out);
+    }
+}
+
+impl Decode for LockedFunds {
+    fn decode(reader: &mut Reader) -> Result<LockedFunds, DecodeError> {
+        Ok(LockedFunds { lock: Hash::decode(reader)?, amount: usize::decode(reader)?, script: Script::decode(reader)? })
+    }
+}
+
+/// Makes Timestamp hashable.
+///
+/// The nanoseconds wrap around past `u64::MAX`, so that hashing a block from a peer does
+/// not panic. Such timestamps are too far ahead for a block to be valid anyway.
+impl ObjectHash for Timestamp {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        objecthash_struct!(
+            hasher,
+            "value" => &self.0.as_secs().wrapping_mul(1_000_000_000).wrapping_add(self.0.subsec_nanos() as u64)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::env;
+    use std::fs;
+    use std::time::Duration;
+    use serde_json;
+    use ledger::codec;
+    use ledger::consensus::ConsensusConfig;
+    use ledger::genesis::Allocation;
+    use ledger::mempool::EXPIRY;
+    use ledger::pow::*;
+    use ledger::script::Op;
+    use ledger::storage::FileStorage;
+    use super::*;
+
+    #[test]
+    fn add_transaction() {
+        let sender = Keypair::generate();
+        let tx = create_transaction(&sender);
+        let mut stupid_chain = create_funded_ledger(&sender);
+        assert_eq!(stupid_chain.add_transaction(tx.clone()).unwrap(), 2);
+    }
+
+    #[test]
+    fn add_transaction_rejects_zero_amount() {
+        let sender = Keypair::generate();
+        let tx = StupidTransaction::transfer(&sender, Keypair::generate().address(), 0, 0, 0);
+        let mut stupid_chain = create_funded_ledger(&sender);
+        assert_eq!(stupid_chain.add_transaction(tx), Err(TransactionError::ZeroAmount));
+    }
+
+    #[test]
+    fn add_transaction_rejects_duplicate() {
+        let sender = Keypair::generate();
+        let tx = StupidTransaction::transfer(&sender, sender.address(), MINING_REWARD, 0, 0);
+        let mut stupid_chain = create_funded_ledger(&sender);
+        stupid_chain.add_transaction(tx.clone()).unwrap();
+        assert_eq!(stupid_chain.add_transaction(tx.clone()),
+            Err(TransactionError::Duplicate { hash: tx.hash() }));
+
+        stupid_chain.new_block(Timestamp::new(Duration::new(12345, 0)), Seal::Work { proof: 1000 }).unwrap();
+        assert_eq!(stupid_chain.add_transaction(tx.clone()),
+            Err(TransactionError::Duplicate { hash: tx.hash() }));
+    }
+
+    #[test]
+    fn add_transaction_rejects_overspending() {
+        let sender = Keypair::generate();
+        let mut stupid_chain = create_funded_ledger(&sender);
+        stupid_chain.add_transaction(create_transaction(&sender)).unwrap();
+
+        let tx = StupidTransaction::transfer(&sender, Keypair::generate().address(), MINING_REWARD, 0, 1);
+        assert_eq!(stupid_chain.add_transaction(tx), Err(TransactionError::InsufficientFunds {
+            address: sender.address(), balance: 0, amount: MINING_REWARD
+        }));
+    }
+
+    #[test]
+    fn balances() {
+        let sender = Keypair::generate();
+        let recipient = Keypair::generate().address();
+        let mut stupid_chain = create_funded_ledger(&sender);
+        let tx = StupidTransaction::transfer(&sender, recipient.clone(), MINING_REWARD, 0, 0);
+        stupid_chain.add_transaction(tx).unwrap();
+
+        assert_eq!(stupid_chain.confirmed_balances().balance(&sender.address()), MINING_REWARD);
+        assert_eq!(stupid_chain.confirmed_balances().balance(&recipient), 0);
+        assert_eq!(stupid_chain.balances().balance(&sender.address()), 0);
+        assert_eq!(stupid_chain.balances().balance(&recipient), MINING_REWARD);
+    }
+
+    #[test]
+    fn new_block() {
+        let sender = Keypair::generate();
+        let tx = create_transaction(&sender);
+        let mut stupid_chain = create_funded_ledger(&sender);
+        stupid_chain.add_transaction(tx.clone()).expect("bad transaction");
+
+        let previous_hash = StupidLedger::hash(&stupid_chain.chain[0]);
+        let timestamp = Timestamp::new(Duration::new(12345, 0));
+        assert_eq!(stupid_chain.new_block(timestamp.clone(), Seal::Work { proof: 1000 }).unwrap()[1],
+            StupidBlock::new(2, timestamp, vec![tx.clone()], Seal::Work { proof: 1000 }, INITIAL_DIFFICULTY,
+                previous_hash));
+    }
+
+    #[test]
+    fn is_valid_chain_checks_rewards_and_balances() {
+        let miner = Keypair::generate();
+        let mut stupid_chain = StupidLedger::new(Vec::new()).unwrap();
+        stupid_chain.reward(miner.address());
+        stupid_chain.seal_block(Timestamp::new(Duration::new(12345, 0))).unwrap();
+        assert!(stupid_chain.is_valid());
+
+        let mut double_reward = stupid_chain.chain.clone();
+        double_reward[0].transactions.as_mut().unwrap().push(StupidTransaction::reward(miner.address(), MINING_REWARD, 1));
+        reseal(&mut double_reward[0]);
+        match stupid_chain.validate_chain(&double_reward) {
+            Err(BlockError::InvalidReward { index: 1 }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+
+        let mut overspending = stupid_chain.chain.clone();
+        overspending[0].transactions.as_mut().unwrap().push(StupidTransaction::transfer(&miner, miner.address(), 2, 0, 0));
+        reseal(&mut overspending[0]);
+        match stupid_chain.validate_chain(&overspending) {
+            Err(BlockError::InvalidTransaction { index: 1, error: TransactionError::InsufficientFunds { .. } }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+
+    #[test]
+    fn overflowing_amounts_and_fees_are_rejected() {
+        let owner = Keypair::generate();
+        let mut stupid_chain = create_funded_ledger(&owner);
+        let recipient = Keypair::generate().address();
+
+        let tx = StupidTransaction::transfer(&owner, recipient.clone(), usize::max_value(), 1, 0);
+        match stupid_chain.add_transaction(tx) {
+            Err(TransactionError::Malformed { .. }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        let tx = StupidTransaction::transfer(&owner, recipient.clone(), usize::max_value(), 0, 0);
+        match stupid_chain.add_transaction(tx) {
+            Err(TransactionError::InsufficientFunds { .. }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+
+        let mut overflowing = stupid_chain.chain.clone();
+        {
+            let transactions = overflowing[0].transactions.as_mut().unwrap();
+            transactions.insert(0, StupidTransaction::transfer(&owner, recipient.clone(), 1, usize::max_value(), 0));
+            transactions.insert(0, StupidTransaction::transfer(&owner, recipient, 1, 1, 1));
+        }
+        reseal(&mut overflowing[0]);
+        match stupid_chain.validate_chain(&overflowing) {
+            Err(BlockError::InvalidReward { index: 1 }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+
+    #[test]
+    fn overflowing_credits_are_rejected() {
+        let (alice, bob) = (Keypair::generate(), Keypair::generate());
+        let mut genesis = Genesis::new("testnet");
+        genesis.allocations.push(Allocation { address: alice.address(), amount: 10 });
+        genesis.allocations.push(Allocation { address: bob.address(), amount: usize::max_value() });
+        let mut stupid_chain = StupidLedger::with_genesis(genesis.clone(), Vec::new()).unwrap();
+
+        match stupid_chain.add_transaction(StupidTransaction::transfer(&alice, bob.address(), 1, 0, 0)) {
+            Err(TransactionError::Malformed { .. }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        assert_eq!(stupid_chain.balances().balance(&alice.address()), 10);
+
+        let mut balances = Balances::from_genesis(&genesis).unwrap();
+        let clock = Clock { index: 1, timestamp: Timestamp::new(Duration::new(12345, 0)) };
+        match balances.apply(&StupidTransaction::reward(bob.address(), MINING_REWARD, 1), &clock) {
+            Err(TransactionError::Malformed { .. }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        assert!(balances.apply(&StupidTransaction::transfer(&bob, bob.address(), 1, 0, 0), &clock).is_ok());
+
+        genesis.allocations.push(Allocation { address: bob.address(), amount: 1 });
+        match StupidLedger::with_genesis(genesis, Vec::new()) {
+            Err(BlockError::InvalidTransaction { index: 0, error: TransactionError::Malformed { .. } }) => (),
+            result => panic!("unexpected result: {:?}", result.map(|ledger| ledger.chain))
+        }
+    }
+
+    #[test]
+    fn with_storage_reloads_chain() {
+        let path = env::temp_dir().join("blockchain-stupid-with-storage.dat");
+        let _ = fs::remove_file(&path);
+        let engine = || Arc::new(ProofOfWork::new(INITIAL_DIFFICULTY));
+
+        let mut stupid_chain = StupidLedger::with_storage(Genesis::default(), engine(), Box::new(FileStorage::new(path.clone())))
+            .unwrap();
+        stupid_chain.reward(Keypair::generate().address());
+        stupid_chain.seal_block(Timestamp::new(Duration::new(12345, 0))).unwrap();
+
+        let reloaded = StupidLedger::with_storage(Genesis::default(), engine(), Box::new(FileStorage::new(path.clone())))
+            .unwrap();
+        assert_eq!(reloaded.chain, stupid_chain.chain);
+
+        let mut invalid_chain = stupid_chain.chain.clone();
+        invalid_chain[0].header.difficulty = 0;
+        FileStorage::new(path.clone()).replace(&invalid_chain).unwrap();
+        assert!(StupidLedger::with_storage(Genesis::default(), engine(), Box::new(FileStorage::new(path.clone()))).is_err());
+
+        FileStorage::new(path.clone()).replace(&stupid_chain.chain).unwrap();
+        assert!(StupidLedger::with_storage(Genesis::new("testnet"), engine(), Box::new(FileStorage::new(path))).is_err());
+    }
+
+    #[test]
+    fn next_difficulty_keeps_difficulty_between_retargets() {
+        let stupid_chain = StupidLedger::new(Vec::new()).unwrap();
+        let chain = create_chain(RETARGET_INTERVAL - 1, 1, INITIAL_DIFFICULTY);
+        assert_eq!(stupid_chain.next_difficulty(&[]), INITIAL_DIFFICULTY);
+        assert_eq!(stupid_chain.next_difficulty(&chain), INITIAL_DIFFICULTY);
+    }
+
+    #[test]
+    fn next_difficulty_retargets_on_spacing() {
+        let stupid_chain = StupidLedger::new(Vec::new()).unwrap();
+        let fast = create_chain(RETARGET_INTERVAL, 1, INITIAL_DIFFICULTY);
+        assert_eq!(stupid_chain.next_difficulty(&fast), INITIAL_DIFFICULTY + 1);
+
+        let on_time = create_chain(RETARGET_INTERVAL, TARGET_BLOCK_TIME, INITIAL_DIFFICULTY);
+        assert_eq!(stupid_chain.next_difficulty(&on_time), INITIAL_DIFFICULTY);
+
+        let slow = create_chain(RETARGET_INTERVAL, TARGET_BLOCK_TIME * 3, INITIAL_DIFFICULTY);
+        assert_eq!(stupid_chain.next_difficulty(&slow), INITIAL_DIFFICULTY - 1);
+
+        let slowest = create_chain(RETARGET_INTERVAL, TARGET_BLOCK_TIME * 3, MIN_DIFFICULTY);
+        assert_eq!(stupid_chain.next_difficulty(&slowest), MIN_DIFFICULTY);
+    }
+
+    #[test]
+    fn is_valid_chain_checks_difficulty() {
+        let mut stupid_chain = StupidLedger::new(Vec::new()).unwrap();
+        stupid_chain.reward(Keypair::generate().address());
+        stupid_chain.seal_block(Timestamp::new(Duration::new(12345, 0))).unwrap();
+        assert!(stupid_chain.is_valid());
+
+        stupid_chain.chain[0].header.difficulty = INITIAL_DIFFICULTY - 1;
+        match stupid_chain.validate_chain(&stupid_chain.chain) {
+            Err(BlockError::BadDifficulty { index: 1, expected: INITIAL_DIFFICULTY, .. }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+
+    #[test]
+    fn receive_block_appends_and_prunes_pending() {
+        let miner = Keypair::generate();
+        let mut stupid_chain = create_funded_ledger(&miner);
+        let mut peer_chain = StupidLedger::new(stupid_chain.chain.clone()).unwrap();
+
+        let tx = create_transaction(&miner);
+        stupid_chain.add_transaction(tx.clone()).unwrap();
+        peer_chain.add_transaction(tx).unwrap();
+        peer_chain.seal_block(Timestamp::new(Duration::new(12345, 0))).unwrap();
+
+        let block = peer_chain.chain[1].clone();
+        assert_eq!(stupid_chain.receive_block(block.clone()).unwrap(), Outcome::Appended);
+        assert_eq!(stupid_chain.chain, peer_chain.chain);
+        assert!(stupid_chain.mempool().is_empty());
+        assert_eq!(stupid_chain.receive_block(block).unwrap(), Outcome::Known);
+    }
+
+    #[test]
+    fn receive_block_detects_fork() {
+        let miner = Keypair::generate();
+        let mut stupid_chain = StupidLedger::new(Vec::new()).unwrap();
+        let mut peer_chain = create_funded_ledger(&miner);
+        peer_chain.add_transaction(create_transaction(&miner)).unwrap();
+        peer_chain.seal_block(Timestamp::new(Duration::new(12345, 0))).unwrap();
+        assert_eq!(stupid_chain.receive_block(peer_chain.chain[1].clone()).unwrap(), Outcome::Fork);
+
+        let mut fork = create_funded_ledger(&Keypair::generate());
+        assert_eq!(fork.receive_block(peer_chain.chain[1].clone()).unwrap(), Outcome::Fork);
+
+        let mut forged = peer_chain.chain[1].clone();
+        forged.header.seal = Seal::Work { proof: forged.seal().proof().unwrap() + 1 };
+        match stupid_chain.receive_block(forged) {
+            Err(BlockError::InvalidSeal { index: 2 }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        let mut easy = peer_chain.chain[1].clone();
+        easy.header.difficulty = INITIAL_DIFFICULTY - 1;
+        reseal(&mut easy);
+        match stupid_chain.receive_block(easy) {
+            Err(BlockError::BadDifficulty { index: 2, .. }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+
+        mine(&mut fork, &miner, 12346);
+        mine(&mut fork, &miner, 12347);
+        assert_eq!(fork.receive_block(peer_chain.chain[1].clone()).unwrap(), Outcome::Stale);
+    }
+
+    #[test]
+    fn receive_block_rejects_invalid_block() {
+        let miner = Keypair::generate();
+        let mut stupid_chain = create_funded_ledger(&miner);
+        let mut peer_chain = StupidLedger::new(stupid_chain.chain.clone()).unwrap();
+        peer_chain.add_transaction(create_transaction(&miner)).unwrap();
+        peer_chain.new_block(Timestamp::new(Duration::new(12345, 0)), Seal::Work { proof: 1000 }).unwrap();
+
+        match stupid_chain.receive_block(peer_chain.chain[1].clone()) {
+            Err(BlockError::InvalidSeal { index: 2 }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        assert_eq!(stupid_chain.chain.len(), 1);
+    }
+
+    #[test]
+    fn is_better_chain_prefers_work_over_length() {
+        let longer = create_chain(2, 1, INITIAL_DIFFICULTY);
+        let heavier = create_chain(1, 1, INITIAL_DIFFICULTY + 2);
+        assert!(StupidLedger::is_better_chain(&heavier, &longer));
+        assert!(! StupidLedger::is_better_chain(&longer, &heavier));
+        assert!(StupidLedger::is_better_chain(&longer, &[]));
+    }
+
+    #[test]
+    fn is_better_chain_breaks_ties_on_tip_hash() {
+        let chain = create_chain(2, 1, INITIAL_DIFFICULTY);
+        let other = create_chain(2, 2, INITIAL_DIFFICULTY);
+        assert_ne!(StupidLedger::is_better_chain(&chain, &other),
+            StupidLedger::is_better_chain(&other, &chain));
+        assert!(! StupidLedger::is_better_chain(&chain, &chain));
+    }
+
+    #[test]
+    fn reorganize_reports_and_returns_transactions() {
+        let miner = Keypair::generate();
+        let mut stupid_chain = create_funded_ledger(&miner);
+        let recipient = Keypair::generate().address();
+        let tx = StupidTransaction::transfer(&miner, recipient.clone(), MINING_REWARD, 0, 0);
+        stupid_chain.add_transaction(tx.clone()).unwrap();
+        stupid_chain.new_block(Timestamp::new(Duration::new(12345, 0)), Seal::Work { proof: 1000 }).unwrap();
+
+        let mut fork = StupidLedger::new(stupid_chain.chain[..1].to_vec()).unwrap();
+        fork.reward(Keypair::generate().address());
+        fork.new_block(Timestamp::new(Duration::new(12346, 0)), Seal::Work { proof: 1000 }).unwrap();
+
+        assert_eq!(stupid_chain.confirmed_balances().balance(&recipient), MINING_REWARD);
+
+        let rolled_back = stupid_chain.chain[1..].to_vec();
+        let reorg = stupid_chain.reorganize(fork.chain.clone()).unwrap();
+        assert_eq!(reorg, Reorg { rolled_back, applied: fork.chain[1..].to_vec(), returned: vec![tx.clone()] });
+        assert_eq!(stupid_chain.chain, fork.chain);
+        assert_eq!(stupid_chain.mempool().iter().map(|entry| entry.tx.clone()).collect::<Vec<_>>(), vec![tx]);
+        assert_eq!(stupid_chain.confirmed_balances().balance(&recipient), 0);
+        assert_eq!(stupid_chain.confirmed_balances().balance(&miner.address()), MINING_REWARD);
+        assert_eq!(stupid_chain.counters().reorgs, 1);
+    }
+
+    #[test]
+    fn write_metrics_reports_chain_and_peers() {
+        let miner = Keypair::generate();
+        let mut stupid_chain = create_funded_ledger(&miner);
+        stupid_chain.add_transaction(create_transaction(&miner)).unwrap();
+        stupid_chain.register_peer("http://127.0.0.1:18100").unwrap();
+        stupid_chain.register_peer("http://127.0.0.1:18101").unwrap();
+        let now = Timestamp::new(Duration::new(12350, 0));
+        stupid_chain.peers.record_success("http://127.0.0.1:18100/", &now);
+        stupid_chain.peers.record_failure("http://127.0.0.1:18101/", &now);
+        stupid_chain.new_block(Timestamp::new(Duration::new(12345, 0)), Seal::Work { proof: 1000 }).unwrap();
+
+        let mut fork = StupidLedger::new(stupid_chain.chain[..1].to_vec()).unwrap();
+        fork.reward(Keypair::generate().address());
+        fork.new_block(Timestamp::new(Duration::new(12346, 0)), Seal::Work { proof: 1000 }).unwrap();
+        stupid_chain.reorganize(fork.chain.clone()).unwrap();
+        assert_eq!(stupid_chain.counters().reorgs, 1);
+        stupid_chain.record_hash_rate(1500.0);
+
+        let mut out = Exposition::new();
+        stupid_chain.write_metrics(&now, &mut out);
+        let text = out.into_string();
+        for line in &[
+            "blockchain_height 2\n",
+            "blockchain_last_block_timestamp_seconds 12346\n",
+            "blockchain_mempool_transactions 1\n",
+            "# TYPE blockchain_reorgs_total counter\n",
+            "blockchain_reorgs_total 1\n",
+            "blockchain_hash_rate 1500\n",
+            "blockchain_peers 2\n",
+            "blockchain_peers_available 1\n",
+            "blockchain_peer_failures{peer=\"http://127.0.0.1:18101/\"} 1\n",
+            "blockchain_peer_last_seen_seconds{peer=\"http://127.0.0.1:18100/\"} 12350\n"
+        ] {
+            assert!(text.contains(line), "missing {:?} in {}", line, text);
+        }
+    }
+
+    #[test]
+    fn index_follows_new_blocks_and_reorganizations() {
+        let miner = Keypair::generate();
+        let mut stupid_chain = create_funded_ledger(&miner);
+        let tx = create_transaction(&miner);
+        stupid_chain.add_transaction(tx.clone()).unwrap();
+        stupid_chain.new_block(Timestamp::new(Duration::new(12350, 0)), Seal::Work { proof: 1000 }).unwrap();
+
+        let block_hash = StupidLedger::hash(&stupid_chain.chain[1]);
+        assert_eq!(stupid_chain.block_by_hash(&block_hash), Some(&stupid_chain.chain[1]));
+        let (location, found) = stupid_chain.transaction(&tx.hash()).unwrap();
+        assert_eq!((location, found), (Location { block: 2, position: 0 }, &tx));
+        assert_eq!(stupid_chain.confirmations(&location), 1);
+        assert_eq!(stupid_chain.transactions_of(&miner.address()).len(), 2);
+        assert_eq!(stupid_chain.stats(), ChainStats {
+            height: 2, transactions: 2, average_block_time: Some(10.0), difficulty: INITIAL_DIFFICULTY
+        });
+
+        let mut fork = StupidLedger::new(stupid_chain.chain[..1].to_vec()).unwrap();
+        fork.reward(Keypair::generate().address());
+        fork.new_block(Timestamp::new(Duration::new(12346, 0)), Seal::Work { proof: 1000 }).unwrap();
+        stupid_chain.reorganize(fork.chain.clone()).unwrap();
+        assert_eq!(stupid_chain.block_by_hash(&block_hash), None);
+        assert_eq!(stupid_chain.transaction(&tx.hash()), None);
+        assert_eq!(stupid_chain.transactions_of(&miner.address()).len(), 1);
+        assert_eq!(stupid_chain.stats().transactions, 2);
+    }
+
+    #[test]
+    fn sync_from_fetches_missing_blocks_only() {
+        let miner = Keypair::generate();
+        let mut peer_chain = StupidLedger::new(Vec::new()).unwrap();
+        for i in 0..3 {
+            mine(&mut peer_chain, &miner, 12340 + i);
+        }
+        let mut stupid_chain = StupidLedger::new(peer_chain.chain[..2].to_vec()).unwrap();
+
+        let sources = [Source { chain: peer_chain.chain.clone(), requested: RefCell::new(Vec::new()) }];
+        let reorg = stupid_chain.sync_from(&sources).unwrap().reorg.unwrap();
+        assert_eq!(stupid_chain.chain, peer_chain.chain);
+        assert!(reorg.rolled_back.is_empty());
+        assert_eq!(reorg.applied, peer_chain.chain[2..].to_vec());
+        assert_eq!(*sources[0].requested.borrow(), vec![(3, 1)]);
+        assert_eq!(stupid_chain.counters().reorgs, 0);
+
+        let source = Source { chain: peer_chain.chain.clone(), requested: RefCell::new(Vec::new()) };
+        assert!(stupid_chain.sync_from(&[source]).unwrap().reorg.is_none());
+    }
+
+    #[test]
+    fn sync_from_keeps_going_past_failing_sources() {
+        let mut peer_chain = StupidLedger::new(Vec::new()).unwrap();
+        mine(&mut peer_chain, &Keypair::generate(), 12340);
+        let mut stupid_chain = StupidLedger::new(Vec::new()).unwrap();
+
+        let sources = [
+            Source { chain: Vec::new(), requested: RefCell::new(Vec::new()) },
+            Source { chain: peer_chain.chain.clone(), requested: RefCell::new(Vec::new()) }
+        ];
+        let synced = stupid_chain.sync_from(&sources).unwrap();
+        assert!(synced.reorg.is_some());
+        assert_eq!(synced.failures.iter().map(|&(position, _)| position).collect::<Vec<_>>(), vec![0]);
+        assert_eq!(stupid_chain.chain, peer_chain.chain);
+        assert_eq!(stupid_chain.counters().reorgs, 0);
+    }
+
+    #[test]
+    fn sync_from_ignores_invalid_blocks() {
+        let miner = Keypair::generate();
+        let mut peer_chain = StupidLedger::new(Vec::new()).unwrap();
+        mine(&mut peer_chain, &miner, 12340);
+        mine(&mut peer_chain, &miner, 12341);
+        let proof = peer_chain.chain[1].seal().proof().unwrap();
+        peer_chain.chain[1].header.seal = Seal::Work { proof: proof + 1 };
+        let mut stupid_chain = StupidLedger::new(peer_chain.chain[..1].to_vec()).unwrap();
+
+        let source = Source { chain: peer_chain.chain.clone(), requested: RefCell::new(Vec::new()) };
+        assert!(stupid_chain.sync_from(&[source]).unwrap().reorg.is_none());
+        assert_eq!(stupid_chain.chain.len(), 1);
+        assert_eq!(stupid_chain.counters().reorgs, 0);
+    }
+
+    #[test]
+    fn sync_from_ignores_other_genesis() {
+        let miner = Keypair::generate();
+        let mut peer_chain = StupidLedger::with_genesis(Genesis::new("testnet"), Vec::new()).unwrap();
+        mine(&mut peer_chain, &miner, 12340);
+        mine(&mut peer_chain, &miner, 12341);
+        let mut stupid_chain = StupidLedger::new(Vec::new()).unwrap();
+
+        let sources = [Source { chain: peer_chain.chain.clone(), requested: RefCell::new(Vec::new()) }];
+        assert!(stupid_chain.sync_from(&sources).unwrap().reorg.is_none());
+        assert!(stupid_chain.chain.is_empty());
+        assert!(sources[0].requested.borrow().is_empty());
+    }
+
+    #[test]
+    fn genesis_allocations_are_spendable() {
+        let sender = Keypair::generate();
+        let mut genesis = Genesis::new("testnet");
+        genesis.allocations.push(Allocation { address: sender.address(), amount: 5 });
+        let mut stupid_chain = StupidLedger::with_genesis(genesis.clone(), Vec::new()).unwrap();
+        assert_eq!(stupid_chain.confirmed_balances().balance(&sender.address()), 5);
+
+        stupid_chain.add_transaction(StupidTransaction::transfer(&sender, Keypair::generate().address(), 3, 0, 0))
+            .unwrap();
+        mine(&mut stupid_chain, &sender, 12340);
+        assert_eq!(stupid_chain.chain[0].header.previous_hash, genesis.hash());
+        assert_eq!(stupid_chain.confirmed_balances().balance(&sender.address()), 2 + MINING_REWARD);
+
+        match StupidLedger::new(Vec::new()).unwrap().validate_chain(&stupid_chain.chain) {
+            Err(BlockError::BadPreviousHash { index: 1 }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+
+    #[test]
+    fn seal_block_on_several_threads() {
+        let miner = Keypair::generate();
+        let mut stupid_chain = create_funded_ledger(&miner);
+        let engine = ProofOfWork { initial_difficulty: INITIAL_DIFFICULTY, search: Search { threads: 3, lowest: true } };
+        let mut threaded = StupidLedger::with_consensus(Genesis::default(), Arc::new(engine), stupid_chain.chain.clone()).unwrap();
+
+        for ledger in vec![&mut stupid_chain, &mut threaded] {
+            mine(ledger, &miner, 12345);
+        }
+        assert_eq!(threaded.chain, stupid_chain.chain);
+    }
+
+    #[test]
+    fn proof_of_authority_takes_turns() {
+        let (alice, bob) = (Keypair::generate(), Keypair::generate());
+        let mut genesis = Genesis::new("poa");
+        genesis.consensus = ConsensusConfig::ProofOfAuthority { validators: vec![alice.address(), bob.address()] };
+        let engine = |keypair: &Keypair| {
+            genesis.consensus.engine(genesis.difficulty, Some(Keypair::from_pkcs8(keypair.pkcs8()).unwrap()))
+        };
+        let mut alice_chain = StupidLedger::with_consensus(genesis.clone(), engine(&alice), Vec::new()).unwrap();
+        let mut bob_chain = StupidLedger::with_consensus(genesis.clone(), engine(&bob), Vec::new()).unwrap();
+
+        mine(&mut alice_chain, &alice, 12340);
+        bob_chain.reward(bob.address());
+        match bob_chain.seal_block(Timestamp::new(Duration::new(12340, 0))) {
+            Err(BlockError::CannotSeal { index: 1 }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        assert_eq!(bob_chain.receive_block(alice_chain.chain[0].clone()).unwrap(), Outcome::Appended);
+        mine(&mut bob_chain, &bob, 12341);
+        assert_eq!(alice_chain.receive_block(bob_chain.chain[1].clone()).unwrap(), Outcome::Appended);
+        assert_eq!(alice_chain.chain[1].header().difficulty, 0);
+
+        let observer = StupidLedger::with_genesis(genesis, Vec::new()).unwrap();
+        assert!(observer.is_valid_chain(&alice_chain.chain));
+        let json = serde_json::to_string(&alice_chain.chain).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<StupidBlock>>(&json).unwrap(), alice_chain.chain);
+
+        let mut out_of_turn = alice_chain.chain.clone();
+        let signature = alice.sign(out_of_turn[1].header.digest().as_bytes());
+        out_of_turn[1].header.seal = Seal::Authority { signature };
+        match observer.validate_chain(&out_of_turn) {
+            Err(BlockError::InvalidSeal { index: 2 }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+
+    #[test]
+    fn binary_encoding_round_trips() {
+        let keypair = Keypair::generate();
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for round in 0..64 {
+            let transactions: Vec<StupidTransaction> = (0..next() % 5).map(|i| {
+                let amount = next() as usize >> (next() % 64);
+                match i % 4 {
+                    0 => StupidTransaction::transfer(&keypair, Keypair::generate().address(), amount, next() as usize % 100, next()),
+                    1 => StupidTransaction::reward(keypair.address(), amount, round),
+                    2 => {
+                        let script = Script::multisig(next() % 3, &[keypair.address(), Keypair::generate().address()]);
+                        StupidTransaction::lock(&keypair, script, amount, next() as usize % 100, next())
+                    },
+                    _ => StupidTransaction::unlock(Hash::from_slice(&[round as u8; 32]).unwrap(), keypair.address(),
+                        next() as usize % 100, &[&keypair])
+                }
+            }).collect();
+            let seal = if round % 2 == 0 {
+                Seal::Work { proof: next() as usize }
+            } else {
+                Seal::Authority { signature: keypair.sign(&[round as u8]) }
+            };
+            let timestamp = Timestamp::new(Duration::new(next() % 4_000_000_000, next() as u32 % 1_000_000_000));
+            let block = StupidBlock::new(round, timestamp, transactions, seal, next() as u32, Hash::from_slice(&[round as u8; 32]).unwrap());
+
+            let bytes = codec::to_bytes(&block);
+            let decoded: StupidBlock = codec::from_bytes(&bytes).unwrap();
+            assert_eq!(decoded, block);
+            assert_eq!(StupidLedger::hash(&decoded), StupidLedger::hash(&block));
+            assert_eq!(codec::to_bytes(&decoded), bytes);
+            assert!(codec::from_bytes::<StupidBlock>(&bytes[..bytes.len() - 1]).is_err());
+        }
+    }
+
+    #[test]
+    fn binary_encoding_rejects_unknown_tags() {
+        let tx = StupidTransaction::reward(Keypair::generate().address(), MINING_REWARD, 1);
+        let mut bytes = codec::to_bytes(&tx);
+        bytes[0] = 5;
+        assert_eq!(codec::from_bytes::<StupidTransaction>(&bytes), Err(DecodeError::UnknownTag { tag: 5 }));
+    }
+
+    #[test]
+    fn unlock_needs_script_to_accept() {
+        let alice = Keypair::generate();
+        let bob = Keypair::generate();
+        let carol = Keypair::generate();
+        let dave = Keypair::generate().address();
+        let mut genesis = Genesis::new("testnet");
+        genesis.allocations.push(Allocation { address: alice.address(), amount: 10 });
+        let mut stupid_chain = StupidLedger::with_genesis(genesis, Vec::new()).unwrap();
+
+        // Any two of Alice, Bob and Carol, from block 3 on.
+        let mut script = Script::multisig(2, &[alice.address(), bob.address(), carol.address()]);
+        script.0.extend(vec![Op::PushNumber(3), Op::CheckIndex, Op::And]);
+        let lock = StupidTransaction::lock(&alice, script, 6, 1, 0);
+        stupid_chain.add_transaction(lock.clone()).unwrap();
+        mine(&mut stupid_chain, &alice, 12340);
+        assert_eq!(stupid_chain.confirmed_balances().balance(&alice.address()), 3 + MINING_REWARD + 1);
+        assert_eq!(stupid_chain.confirmed_balances().locked(&lock.hash()), Some(6));
+
+        let unlock = StupidTransaction::unlock(lock.hash(), dave.clone(), 1, &[&alice, &bob]);
+        assert_eq!(stupid_chain.add_transaction(unlock.clone()),
+            Err(TransactionError::ScriptFailed { error: ScriptError::Rejected }));
+        mine(&mut stupid_chain, &alice, 12350);
+
+        let forged = StupidTransaction::unlock(lock.hash(), dave.clone(), 1, &[&alice, &alice]);
+        assert_eq!(stupid_chain.add_transaction(forged),
+            Err(TransactionError::ScriptFailed { error: ScriptError::Rejected }));
+        assert_eq!(stupid_chain.add_transaction(unlock.clone()), Ok(3));
+        mine(&mut stupid_chain, &carol, 12360);
+        assert_eq!(stupid_chain.confirmed_balances().balance(&dave), 5);
+        assert_eq!(stupid_chain.confirmed_balances().balance(&carol.address()), MINING_REWARD + 1);
+        assert_eq!(stupid_chain.confirmed_balances().locked(&lock.hash()), None);
+        assert!(stupid_chain.is_valid());
+
+        assert_eq!(stupid_chain.add_transaction(unlock), Err(TransactionError::UnknownLock { hash: lock.hash() }));
+    }
+
+    #[test]
+    fn is_valid_bounds_scripts_and_witnesses() {
+        let keypair = Keypair::generate();
+        let script = Script(vec![Op::PushNumber(1); script::MAX_SCRIPT_LEN + 1]);
+        assert!(! StupidTransaction::lock(&keypair, script, 1, 0, 0).is_valid());
+
+        let signers = vec![&keypair; script::MAX_KEYS as usize + 1];
+        assert!(StupidTransaction::unlock(Hash::default(), keypair.address(), 0, &signers[1..]).is_valid());
+        assert!(! StupidTransaction::unlock(Hash::default(), keypair.address(), 0, &signers).is_valid());
+    }
+
+    #[test]
+    fn target_snapshots_tip() {
+        let stupid_chain = create_funded_ledger(&Keypair::generate());
+        let target = stupid_chain.target();
+        assert_eq!(target, Target {
+            height: 1,
+            last_block_hash: StupidLedger::hash(&stupid_chain.chain[0]),
+            difficulty: INITIAL_DIFFICULTY
+        });
+    }
+
+    #[test]
+    fn blocks_pages_through_chain() {
+        let mut stupid_chain = StupidLedger::new(Vec::new()).unwrap();
+        let miner = Keypair::generate();
+        for i in 0..5 {
+            mine(&mut stupid_chain, &miner, 12340 + i);
+        }
+        let chain = stupid_chain.chain.clone();
+        assert_eq!(stupid_chain.blocks(2, 2), &chain[1..3]);
+        assert_eq!(stupid_chain.blocks(0, 1), &chain[..1]);
+        assert_eq!(stupid_chain.blocks(4, 10), &chain[3..]);
+        assert!(stupid_chain.blocks(6, 10).is_empty());
+    }
+
+    #[test]
+    fn new_rejects_invalid_chain() {
+        let miner = Keypair::generate();
+        let mut stupid_chain = create_funded_ledger(&miner);
+        mine(&mut stupid_chain, &miner, 12345);
+
+        let mut overspending = stupid_chain.chain.clone();
+        overspending.truncate(1);
+        overspending[0].transactions.as_mut().unwrap().push(StupidTransaction::transfer(&miner, miner.address(), 2, 0, 0));
+        reseal(&mut overspending[0]);
+        match StupidLedger::new(overspending) {
+            Err(BlockError::InvalidTransaction { index: 1, error: TransactionError::InsufficientFunds { .. } }) => (),
+            result => panic!("unexpected result: {:?}", result.map(|ledger| ledger.chain))
+        }
+
+        let pruned = vec![stupid_chain.chain[0].pruned(), stupid_chain.chain[1].clone()];
+        match StupidLedger::new(pruned) {
+            Err(BlockError::Pruned { index: 1 }) => (),
+            result => panic!("unexpected result: {:?}", result.map(|ledger| ledger.chain))
+        }
+    }
+
+    #[test]
+    fn validate_header_checks_index_and_timestamps() {
+        let miner = Keypair::generate();
+        let mut stupid_chain = StupidLedger::new(Vec::new()).unwrap();
+        for i in 0..3 {
+            mine(&mut stupid_chain, &miner, 12340 + i);
+        }
+        let (chain, header) = (&stupid_chain.chain[..2], stupid_chain.chain[2].header());
+        assert!(stupid_chain.validate_header(chain, header).is_ok());
+
+        let skipping = StupidHeader { index: 4, ..header.clone() };
+        match stupid_chain.validate_header(chain, &skipping) {
+            Err(BlockError::BadIndex { index: 4, expected: 3 }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+
+        let early = StupidHeader { timestamp: Timestamp::new(Duration::new(12339, 0)), ..header.clone() };
+        match stupid_chain.validate_header(chain, &early) {
+            Err(BlockError::TimestampTooEarly { index: 3 }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+
+        let future = Timestamp::current_nanos().0 + Duration::from_secs(MAX_FUTURE_DRIFT + 60);
+        let late = StupidHeader { timestamp: Timestamp::new(future), ..header.clone() };
+        match stupid_chain.validate_header(chain, &late) {
+            Err(BlockError::TimestampTooLate { index: 3 }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+
+    #[test]
+    fn snapshot_blocks_commit_to_balances() {
+        let alice = Keypair::generate();
+        let bob = Keypair::generate().address();
+        let mut stupid_chain = StupidLedger::with_genesis(create_snapshot_genesis(&alice), Vec::new()).unwrap();
+        stupid_chain.add_transaction(StupidTransaction::transfer(&alice, bob.clone(), 3, 1, 0)).unwrap();
+        mine(&mut stupid_chain, &alice, 12340);
+        assert!(stupid_chain.chain[0].state_root().is_none());
+        assert!(stupid_chain.snapshot().is_none());
+
+        mine(&mut stupid_chain, &alice, 12341);
+        let snapshot = stupid_chain.snapshot().unwrap().clone();
+        assert_eq!(snapshot.height, 2);
+        assert_eq!(stupid_chain.chain[1].state_root(), Some(&snapshot.hash()));
+        assert_eq!(snapshot, stupid_chain.confirmed_balances().snapshot(2));
+        assert_eq!(Balances::from_snapshot(&snapshot).balance(&bob), 3);
+        assert_eq!(codec::from_bytes::<Snapshot>(&codec::to_bytes(&snapshot)), Ok(snapshot.clone()));
+        assert!(stupid_chain.is_valid());
+
+        let mut tampered = stupid_chain.chain.clone();
+        tampered[1].transactions.as_mut().unwrap().pop();
+        tampered[1].transactions.as_mut().unwrap().push(StupidTransaction::snapshot(Hash::default()));
+        reseal(&mut tampered[1]);
+        match stupid_chain.validate_chain(&tampered) {
+            Err(BlockError::InvalidSnapshot { index: 2 }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+
+        let mut missing = stupid_chain.chain.clone();
+        missing[1].transactions.as_mut().unwrap().pop();
+        reseal(&mut missing[1]);
+        match stupid_chain.validate_chain(&missing) {
+            Err(BlockError::InvalidSnapshot { index: 2 }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+
+        let snapshot_tx = StupidTransaction::snapshot(snapshot.hash());
+        assert_eq!(stupid_chain.add_transaction(snapshot_tx), Err(TransactionError::UnexpectedSnapshot));
+    }
+
+    #[test]
+    fn prune_drops_transactions_before_snapshot() {
+        let path = env::temp_dir().join("blockchain-stupid-prune.dat");
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("snapshot"));
+        let genesis = create_snapshot_genesis(&Keypair::generate());
+        let engine = || Arc::new(ProofOfWork::new(INITIAL_DIFFICULTY));
+
+        let miner = Keypair::generate();
+        let mut stupid_chain = StupidLedger::with_storage(genesis.clone(), engine(), Box::new(FileStorage::new(path.clone())))
+            .unwrap();
+        for i in 0..5 {
+            mine(&mut stupid_chain, &miner, 12340 + i);
+        }
+        let balance = stupid_chain.confirmed_balances().balance(&miner.address());
+        let transactions = stupid_chain.stats().transactions;
+        let reward = stupid_chain.chain[0].transactions()[0].hash();
+        assert_eq!(stupid_chain.prune().unwrap(), 3);
+        assert_eq!(stupid_chain.stats().transactions, transactions);
+        assert!(stupid_chain.transaction(&reward).is_none());
+        assert_eq!(stupid_chain.prune().unwrap(), 0);
+        assert!(stupid_chain.chain[..3].iter().all(StupidBlock::is_pruned));
+        assert!(! stupid_chain.chain[3].is_pruned());
+        assert!(stupid_chain.chain[0].transaction_proof(0).is_none());
+        assert_eq!(stupid_chain.confirmed_balances().balance(&miner.address()), balance);
+        assert!(stupid_chain.is_valid());
+
+        stupid_chain.pruning = true;
+        mine(&mut stupid_chain, &miner, 12345);
+        assert_eq!(stupid_chain.snapshot().unwrap().height, 6);
+        assert!(stupid_chain.chain[..5].iter().all(StupidBlock::is_pruned));
+        assert!(stupid_chain.is_valid());
+
+        let transactions = transactions + stupid_chain.chain[5].transactions().len();
+        assert_eq!(stupid_chain.stats().transactions, transactions);
+
+        let reloaded = StupidLedger::with_storage(genesis.clone(), engine(), Box::new(FileStorage::new(path))).unwrap();
+        assert_eq!(reloaded.chain, stupid_chain.chain);
+        assert_eq!(reloaded.snapshot(), stupid_chain.snapshot());
+        assert_eq!(reloaded.stats().transactions, transactions);
+        assert_eq!(reloaded.confirmed_balances().balance(&miner.address()), balance + MINING_REWARD);
+
+        match StupidLedger::with_genesis(genesis, Vec::new()).unwrap().validate_chain(&stupid_chain.chain) {
+            Err(BlockError::Pruned { index: 5 }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+
+    #[test]
+    fn bootstrap_starts_from_snapshot() {
+        let alice = Keypair::generate();
+        let mut peer_chain = StupidLedger::with_genesis(create_snapshot_genesis(&alice), Vec::new()).unwrap();
+        peer_chain.add_transaction(StupidTransaction::transfer(&alice, Keypair::generate().address(), 3, 1, 0))
+            .unwrap();
+        for i in 0..5 {
+            mine(&mut peer_chain, &alice, 12340 + i);
+        }
+
+        let mut stupid_chain = StupidLedger::with_genesis(create_snapshot_genesis(&alice), Vec::new()).unwrap();
+        stupid_chain.bootstrap(&peer_chain).unwrap();
+        assert_eq!(stupid_chain.chain, peer_chain.chain);
+        assert!(stupid_chain.chain[..3].iter().all(StupidBlock::is_pruned));
+        assert!(stupid_chain.chain[3..].iter().all(|block| ! block.is_pruned()));
+        assert_eq!(stupid_chain.snapshot(), peer_chain.snapshot());
+        assert_eq!(stupid_chain.confirmed_balances().balance(&alice.address()),
+            peer_chain.confirmed_balances().balance(&alice.address()));
+
+        mine(&mut stupid_chain, &alice, 12345);
+        assert_eq!(stupid_chain.snapshot().unwrap().height, 6);
+        assert!(stupid_chain.is_valid());
+        assert!(stupid_chain.bootstrap(&peer_chain).is_err());
+
+        let mut other_chain = StupidLedger::with_genesis(Genesis::new("other"), Vec::new()).unwrap();
+        assert!(other_chain.bootstrap(&peer_chain).is_err());
+        assert!(other_chain.chain.is_empty());
+    }
+
+    #[test]
+    fn add_transaction_rejects_invalid_signature() {
+        let tx = match create_transaction(&Keypair::generate()) {
+            StupidTransaction::Transfer { sender, recipient, fee, nonce, signature, .. } => {
+                StupidTransaction::Transfer { sender, recipient, amount: 2, fee, nonce, signature }
+            },
+            _ => unreachable!()
+        };
+        assert!(! tx.is_valid());
+
+        let mut stupid_chain = StupidLedger::new(Vec::new()).unwrap();
+        assert_eq!(stupid_chain.add_transaction(tx), Err(TransactionError::InvalidSignature));
+    }
+
+    #[test]
+    fn add_transaction_rejects_reward() {
+        let tx = StupidTransaction::reward(Keypair::generate().address(), MINING_REWARD, 1);
+        let mut stupid_chain = StupidLedger::new(Vec::new()).unwrap();
+        assert_eq!(stupid_chain.add_transaction(tx), Err(TransactionError::UnexpectedReward));
+    }
+
+    #[test]
+    fn reward_goes_into_next_block() {
+        let miner = Keypair::generate().address();
+        let mut stupid_chain = StupidLedger::new(Vec::new()).unwrap();
+        stupid_chain.reward(miner.clone());
+
+        let timestamp = Timestamp::new(Duration::new(12345, 0));
+        assert_eq!(stupid_chain.new_block(timestamp, Seal::Work { proof: 1000 }).unwrap()[0].transactions(),
+            &[StupidTransaction::reward(miner, MINING_REWARD, 1)][..]);
+    }
+
+    #[test]
+    fn new_block_rejects_empty_mempool() {
+        let mut stupid_chain = StupidLedger::new(Vec::new()).unwrap();
+        match stupid_chain.new_block(Timestamp::new(Duration::new(12345, 0)), Seal::Work { proof: 1000 }) {
+            Err(BlockError::EmptyMempool) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+
+    #[test]
+    fn new_block_picks_fee_rates_and_rewards_fees() {
+        let sender = Keypair::generate();
+        let miner = Keypair::generate().address();
+        let mut stupid_chain = StupidLedger::new(Vec::new()).unwrap();
+        for secs in 0..3 {
+            stupid_chain.reward(sender.address());
+            stupid_chain.new_block(Timestamp::new(Duration::new(12340 + secs, 0)), Seal::Work { proof: 1000 }).unwrap();
+        }
+
+        let low = StupidTransaction::transfer(&sender, Keypair::generate().address(), 1, 0, 0);
+        let high = StupidTransaction::transfer(&sender, Keypair::generate().address(), 1, 1, 1);
+        stupid_chain.add_transaction(low.clone()).unwrap();
+        stupid_chain.add_transaction(high.clone()).unwrap();
+        stupid_chain.reward(miner.clone());
+
+        let block = stupid_chain.new_block(Timestamp::current_nanos(), Seal::Work { proof: 1000 }).unwrap()[3].clone();
+        assert_eq!(block.transactions(), &[high, low, StupidTransaction::reward(miner, MINING_REWARD + 1, 4)][..]);
+        assert!(stupid_chain.mempool().is_empty());
+        assert_eq!(stupid_chain.confirmed_balances().balance(&sender.address()), 0);
+    }
+
+    #[test]
+    fn new_block_expires_old_transactions() {
+        let sender = Keypair::generate();
+        let mut stupid_chain = create_funded_ledger(&sender);
+        stupid_chain.add_transaction(create_transaction(&sender)).unwrap();
+
+        let later = Timestamp::current_nanos().0 + Duration::from_secs(EXPIRY + 1);
+        match stupid_chain.new_block(Timestamp::new(later), Seal::Work { proof: 1000 }) {
+            Err(BlockError::EmptyMempool) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        assert!(stupid_chain.mempool().is_empty());
+    }
+
+    #[test]
+    fn validate_chain_caps_block_transactions() {
+        let sender = Keypair::generate();
+        let transactions = (0..MAX_BLOCK_TRANSACTIONS as u64 + 1)
+            .map(|nonce| StupidTransaction::transfer(&sender, sender.address(), 1, 0, nonce))
+            .collect();
+        let mut block = StupidBlock::new(1, Timestamp::new(Duration::new(12345, 0)), transactions,
+            Seal::Work { proof: 0 }, INITIAL_DIFFICULTY, Genesis::default().hash());
+        reseal(&mut block);
+        match StupidLedger::new(Vec::new()).unwrap().validate_chain(&[block]) {
+            Err(BlockError::TooManyTransactions { index: 1, count, .. }) => {
+                assert_eq!(count, MAX_BLOCK_TRANSACTIONS + 1);
+            },
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+
+    #[test]
+    fn involves_sender_and_recipient() {
+        let (sender, recipient) = (Keypair::generate(), Keypair::generate());
+        let tx = StupidTransaction::transfer(&sender, recipient.address(), 1, 0, 0);
+        assert!(tx.involves(&sender.address()));
+        assert!(tx.involves(&recipient.address()));
+        assert!(! tx.involves(&Keypair::generate().address()));
+        assert!(StupidTransaction::reward(recipient.address(), 1, 1).involves(&recipient.address()));
+    }
+
+    #[test]
+    fn timestamp_hash_wraps_around() {
+        let far = Timestamp::new(Duration::new(u64::max_value(), 999_999_999));
+        assert_ne!(Hash::digest(&far), Hash::digest(&Timestamp::new(Duration::new(0, 0))));
+    }
+
+    #[test]
+    fn transactions_root() {
+        let sender = Keypair::generate();
+        let mut stupid_chain = create_funded_ledger(&sender);
+        stupid_chain.add_transaction(create_transaction(&sender)).unwrap();
+        stupid_chain.reward(sender.address());
+        stupid_chain.new_block(Timestamp::new(Duration::new(12345, 0)), Seal::Work { proof: 1000 }).unwrap();
+
+        let mut block = stupid_chain.chain[1].clone();
+        assert!(block.is_valid());
+        for (index, tx) in block.transactions().iter().enumerate() {
+            let proof = block.transaction_proof(index).unwrap();
+            assert!(proof.verify(block.transactions_root(), &tx.hash()));
+        }
+        assert!(block.transaction_proof(2).is_none());
+
+        let hash = StupidLedger::hash(&block);
+        block.transactions.as_mut().unwrap().pop();
+        assert!(! block.is_valid());
+        assert_eq!(StupidLedger::hash(&block), hash);
+    }
+
+    /// Mines a block holding a reward only.
+    fn mine(stupid_chain: &mut StupidLedger, miner: &Keypair, secs: u64) {
+        stupid_chain.reward(miner.address());
+        stupid_chain.seal_block(Timestamp::new(Duration::new(secs, 0))).unwrap();
+    }
+
+    /// Chain served from memory, recording the ranges of full blocks requested from it.
+    ///
+    /// A source without blocks stands for an unreachable peer.
+    struct Source {
+        chain: Vec<StupidBlock>,
+        requested: RefCell<Vec<(usize, usize)>>
+    }
+
+    impl BlockSource<StupidBlock> for Source {
+        fn tip(&self) -> Result<Tip, Box<Error>> {
+            let last_block = self.chain.last().ok_or("Source is unreachable")?;
+            Ok(Tip { height: self.chain.len(), hash: StupidLedger::hash(last_block),
+                genesis: self.chain[0].header.previous_hash.clone() })
+        }
+
+        fn blocks(&self, from: usize, limit: usize) -> Result<Vec<StupidBlock>, Box<Error>> {
+            self.requested.borrow_mut().push((from, limit));
+            Ok(self.chain.iter().skip(from - 1).take(limit).cloned().collect())
+        }
+
+        fn headers(&self, from: usize, limit: usize) -> Result<Vec<StupidBlock>, Box<Error>> {
+            Ok(self.chain.iter().skip(from - 1).take(limit).map(StupidBlock::pruned).collect())
+        }
+    }
+
+    /// Serves a ledger from memory, like a peer would.
+    impl BlockSource<StupidBlock> for StupidLedger {
+        fn tip(&self) -> Result<Tip, Box<Error>> {
+            Ok(StupidLedger::tip(self))
+        }
+
+        fn blocks(&self, from: usize, limit: usize) -> Result<Vec<StupidBlock>, Box<Error>> {
+            Ok(StupidLedger::blocks(self, from, limit).to_vec())
+        }
+
+        fn headers(&self, from: usize, limit: usize) -> Result<Vec<StupidBlock>, Box<Error>> {
+            Ok(StupidLedger::headers(self, from, limit))
+        }
+    }
+
+    impl SnapshotSource<StupidBlock, Snapshot> for StupidLedger {
+        fn snapshot(&self) -> Result<Snapshot, Box<Error>> {
+            Ok(StupidLedger::snapshot(self).ok_or("No snapshot yet")?.clone())
+        }
+    }
+
+    /// Creates a genesis funding `owner` that takes a snapshot every other block.
+    fn create_snapshot_genesis(owner: &Keypair) -> Genesis {
+        let mut genesis = Genesis::new("testnet");
+        genesis.snapshot_interval = 2;
+        genesis.allocations.push(Allocation { address: owner.address(), amount: 10 });
+        genesis
+    }
+
+    /// Recomputes the transactions root of a tampered block, then seals it again.
+    fn reseal(block: &mut StupidBlock) {
+        block.header.transactions_root = StupidBlock::compute_transactions_root(block.transactions());
+        block.header.seal = Seal::Work { proof: find_proof(&block.header.digest(), block.header.difficulty) };
+    }
+
+    fn create_chain(len: usize, spacing: u64, difficulty: u32) -> Vec<StupidBlock> {
+        (0..len).map(|index| {
+            let timestamp = Timestamp::new(Duration::from_secs(index as u64 * spacing));
+            StupidBlock::new(index + 1, timestamp, vec![], Seal::Work { proof: 0 }, difficulty, Hash::default())
+        }).collect()
+    }
+
+    fn create_funded_ledger(owner: &Keypair) -> StupidLedger {
+        let mut stupid_chain = StupidLedger::new(Vec::new()).unwrap();
+        stupid_chain.reward(owner.address());
+        stupid_chain.seal_block(Timestamp::new(Duration::new(12340, 0))).unwrap();
+        stupid_chain
     }
 
-    fn create_transaction() -> StupidTransaction {
-        let sender = String::from("0x0001");
-        let recipient = String::from("0x0002");
-        let amount = 10000;
-        StupidTransaction::new(sender, recipient, amount)
+    fn create_transaction(sender: &Keypair) -> StupidTransaction {
+        let recipient = Keypair::generate().address();
+        let amount = MINING_REWARD;
+        StupidTransaction::transfer(sender, recipient, amount, 0, 0)
     }
 }
@@ -0,0 +1,744 @@
+//! # UTXO Ledger
+//!
+//! UTXO Ledger is a distributed ledger whose transactions spend unspent transaction outputs,
+//! instead of moving amounts between account balances.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use objecthash::{ObjectHash, ObjectHasher};
+
+use reqwest;
+use reqwest::Url;
+
+use ledger::*;
+use ledger::crypto::{Address, Keypair, Signature};
+use ledger::pow;
+use ledger::util::{Hash, Timestamp};
+
+/// Amount rewarded to the miner of a block, on top of the block fees.
+pub const MINING_REWARD: usize = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UtxoLedger {
+    pub chain: Vec<UtxoBlock>,
+    pub peers: Vec<String>,
+    block_txs: Vec<UtxoTransaction>,
+    miner: Option<Address>
+}
+
+impl UtxoLedger {
+    pub fn new(chain: Vec<UtxoBlock>) -> UtxoLedger {
+        UtxoLedger { chain, peers: Vec::new(), block_txs: Vec::new(), miner: None }
+    }
+
+    /// Computes the difficulty required from the block that follows `chain`.
+    pub fn next_difficulty(chain: &[UtxoBlock]) -> u32 {
+        pow::next_difficulty(chain, pow::INITIAL_DIFFICULTY)
+    }
+
+    pub fn is_valid_proof(last_block_hash: Hash, last_proof: usize, proof: usize, difficulty: u32) -> bool {
+        pow::is_valid_proof(&Self::proof_digest(&last_block_hash, last_proof), proof, difficulty)
+    }
+
+    /// Searches for the proof of the next block, which follows the proof `last_proof`.
+    pub fn find_proof(&self, last_proof: usize) -> usize {
+        let last_block_hash = if let Some(last_block) = self.last_block() {
+            Self::hash(last_block)
+        } else {
+            Hash::default()
+        };
+        pow::find_proof(&Self::proof_digest(&last_block_hash, last_proof), Self::next_difficulty(&self.chain))
+    }
+
+    /// Digest the proof of a block commits to, as the block is mined before it is built.
+    fn proof_digest(last_block_hash: &Hash, last_proof: usize) -> Hash {
+        Hash::digest(&format!("{}{}", last_block_hash, last_proof))
+    }
+
+    pub fn is_valid_chain(chain: &Vec<UtxoBlock>) -> bool {
+        let mut utxos = UtxoSet::default();
+        let mut last_block: Option<&UtxoBlock> = None;
+
+        for index in 0..chain.len() {
+            let block = &chain[index];
+            if block.difficulty != Self::next_difficulty(&chain[..index]) {
+                return false;
+            }
+            if let Some(last_block) = last_block {
+                let last_block_hash = Self::hash(last_block);
+                if block.previous_hash != last_block_hash {
+                    return false;
+                }
+                if ! Self::is_valid_proof(last_block_hash, last_block.proof, block.proof, block.difficulty) {
+                    return false;
+                }
+            }
+            if utxos.apply_block(block).is_err() {
+                return false;
+            }
+            last_block = Some(block);
+        }
+        true
+    }
+
+    /// Computes the unspent outputs once every pending transaction is in the chain.
+    pub fn utxos(&self) -> UtxoSet {
+        let mut utxos = self.confirmed_utxos();
+        for tx in &self.block_txs {
+            utxos.apply(tx).expect("Pending transactions are valid");
+        }
+        utxos
+    }
+
+    /// Computes the unspent outputs of the transactions in the chain only.
+    pub fn confirmed_utxos(&self) -> UtxoSet {
+        let mut utxos = UtxoSet::default();
+        for block in &self.chain {
+            utxos.apply_block(block).expect("Ledger chain is valid");
+        }
+        utxos
+    }
+
+    /// Rewards `recipient` for mining the next block.
+    pub fn reward(&mut self, recipient: Address) {
+        self.miner = Some(recipient);
+    }
+
+    /// Registers a peer, e.g. `http://localhost:8000/utxo/`.
+    ///
+    /// Peer chains are fetched from `blocks`, relative to the registered address.
+    pub fn register_peer(&mut self, address: String) {
+        self.peers.push(address);
+    }
+
+    /// Checks whether `candidate` carries more work than `current`, ties going to the lowest
+    /// tip hash.
+    pub fn is_better_chain(candidate: &[UtxoBlock], current: &[UtxoBlock]) -> bool {
+        let tip_hash = |chain: &[UtxoBlock]| chain.last().map(Self::hash).unwrap_or_default();
+        pow::is_better_chain(candidate, &tip_hash(candidate), current, &tip_hash(current))
+    }
+
+    /// Replaces the chain with the valid peer chain carrying the most work, if it carries
+    /// more than the chain itself.
+    pub fn resolve_conflicts(&mut self) -> Result<bool, Box<Error>> {
+        let mut new_chain: Option<Vec<UtxoBlock>> = None;
+
+        for peer in &self.peers {
+            let url = Url::parse(peer)?.join("blocks")?;
+            let mut resp = reqwest::get(url.as_str())?;
+
+            if resp.status().is_success() {
+                let chain: Vec<UtxoBlock> = resp.json()?;
+
+                let is_better = match new_chain {
+                    Some(ref new_chain) => Self::is_better_chain(&chain, new_chain),
+                    None => Self::is_better_chain(&chain, &self.chain)
+                };
+                if is_better && Self::is_valid_chain(&chain) {
+                    new_chain = Some(chain);
+                }
+            }
+        }
+
+        if let Some(chain) = new_chain {
+            self.chain = chain;
+            self.block_txs.clear();
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+impl DistributedLedger<UtxoBlock, UtxoTransaction> for UtxoLedger {
+    type LedgerRepr = Vec<UtxoBlock>;
+    type Proof = usize;
+
+    fn hash(obj: &UtxoBlock) -> Hash {
+        Hash::digest(obj)
+    }
+
+    fn new_block(&mut self, timestamp: Timestamp, proof: Self::Proof) -> Result<&Self::LedgerRepr, BlockError> {
+        let miner = self.miner.take().ok_or(BlockError::MissingReward)?;
+        let utxos = self.confirmed_utxos();
+        let index = self.chain.len() + 1;
+        let reward = self.block_txs.iter()
+            .map(|tx| utxos.fee(tx).expect("Pending transactions are valid"))
+            .try_fold(MINING_REWARD, |reward, fee| reward.checked_add(fee))
+            .ok_or(BlockError::InvalidReward { index })?;
+
+        let block = UtxoBlock {
+            index,
+            timestamp,
+            coinbase: UtxoTransaction::coinbase(miner, reward, index),
+            transactions: self.block_txs.to_vec(),
+            proof,
+            difficulty: Self::next_difficulty(&self.chain),
+            previous_hash: if let Some(last_block) = self.last_block() {
+                Self::hash(last_block)
+            } else {
+                Hash::default()
+            }
+        };
+        self.block_txs.clear();
+        self.chain.push(block);
+        Ok(&self.chain)
+    }
+
+    fn add_transaction(&mut self, tx: UtxoTransaction) -> Result<usize, TransactionError> {
+        if tx.is_coinbase() {
+            return Err(TransactionError::UnexpectedReward);
+        }
+        tx.check()?;
+        self.utxos().apply(&tx)?;
+        self.block_txs.push(tx);
+        Ok(self.chain.len() + 1)
+    }
+
+    fn last_block(&self) -> Option<&UtxoBlock> {
+        self.chain.last()
+    }
+
+    fn is_valid(&self) -> bool {
+        Self::is_valid_chain(&self.chain)
+    }
+}
+
+/// Defines a block of UTXO transactions, led by the coinbase transaction rewarding its miner.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct UtxoBlock {
+    index: usize,
+    timestamp: Timestamp,
+    coinbase: UtxoTransaction,
+    transactions: Vec<UtxoTransaction>,
+    pub proof: usize,
+    difficulty: u32,
+    previous_hash: Hash
+}
+
+/// Makes UtxoBlock hashable.
+impl ObjectHash for UtxoBlock {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        objecthash_struct!(
+            hasher,
+            "index" => &self.index,
+            "timestamp" => &self.timestamp,
+            "coinbase" => &self.coinbase,
+            "transactions" => &self.transactions,
+            "proof" => &self.proof,
+            "difficulty" => &self.difficulty,
+            "previous_hash" => &self.previous_hash
+        )
+    }
+}
+
+impl Block for UtxoBlock {
+    fn timestamp(&self) -> &Timestamp {
+        &self.timestamp
+    }
+
+    fn difficulty(&self) -> u32 {
+        self.difficulty
+    }
+
+    fn is_valid(&self) -> bool {
+        match self.coinbase {
+            UtxoTransaction::Coinbase { index, .. } => index == self.index,
+            UtxoTransaction::Spend { .. } => false
+        }
+    }
+}
+
+/// Points to an output of a previous transaction.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+pub struct OutPoint {
+    pub tx_hash: Hash,
+    pub index: usize
+}
+
+/// Makes OutPoint hashable.
+impl ObjectHash for OutPoint {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        objecthash_struct!(
+            hasher,
+            "tx_hash" => &self.tx_hash,
+            "index" => &self.index
+        )
+    }
+}
+
+/// Spends a previous output, authorized by the signature of its owner.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TxInput {
+    pub previous_output: OutPoint,
+    pub signature: Signature
+}
+
+/// Makes TxInput hashable.
+impl ObjectHash for TxInput {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        objecthash_struct!(
+            hasher,
+            "previous_output" => &self.previous_output,
+            "signature" => &self.signature
+        )
+    }
+}
+
+/// Assigns an amount to its owner, until a later input spends it.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TxOutput {
+    pub amount: usize,
+    pub owner: Address
+}
+
+impl TxOutput {
+    pub fn new(amount: usize, owner: Address) -> TxOutput {
+        TxOutput { amount, owner }
+    }
+}
+
+/// Makes TxOutput hashable.
+impl ObjectHash for TxOutput {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        objecthash_struct!(
+            hasher,
+            "amount" => &self.amount,
+            "owner" => &self.owner
+        )
+    }
+}
+
+/// Defines a UTXO transaction in the blockchain.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UtxoTransaction {
+    /// Spends previous outputs into new ones. Whatever the outputs leave out is the fee.
+    Spend {
+        inputs: Vec<TxInput>,
+        outputs: Vec<TxOutput>
+    },
+    /// Creates the reward of the miner of the block at `index`.
+    Coinbase {
+        output: TxOutput,
+        index: usize
+    }
+}
+
+impl UtxoTransaction {
+    /// Creates a transaction spending `previous_outputs`, all owned by `keypair`.
+    pub fn spend(keypair: &Keypair, previous_outputs: Vec<OutPoint>, outputs: Vec<TxOutput>) -> UtxoTransaction {
+        let digest = Self::spend_digest(&previous_outputs, &outputs);
+        let signature = keypair.sign(digest.as_bytes());
+        let inputs = previous_outputs.into_iter()
+            .map(|previous_output| TxInput { previous_output, signature: signature.clone() })
+            .collect();
+        UtxoTransaction::Spend { inputs, outputs }
+    }
+
+    pub fn coinbase(recipient: Address, amount: usize, index: usize) -> UtxoTransaction {
+        UtxoTransaction::Coinbase { output: TxOutput::new(amount, recipient), index }
+    }
+
+    pub fn hash(&self) -> Hash {
+        Hash::digest(self)
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        match *self {
+            UtxoTransaction::Coinbase { .. } => true,
+            UtxoTransaction::Spend { .. } => false
+        }
+    }
+
+    pub fn outputs(&self) -> &[TxOutput] {
+        match *self {
+            UtxoTransaction::Spend { ref outputs, .. } => outputs,
+            UtxoTransaction::Coinbase { ref output, .. } => ::std::slice::from_ref(output)
+        }
+    }
+
+    /// Checks the shape of the transaction. Signatures are checked against the owners
+    /// of the spent outputs, when applying the transaction to a `UtxoSet`.
+    pub fn check(&self) -> Result<(), TransactionError> {
+        let outputs = match *self {
+            UtxoTransaction::Spend { ref inputs, ref outputs } => {
+                let previous_outputs: HashSet<&OutPoint> = inputs.iter()
+                    .map(|input| &input.previous_output)
+                    .collect();
+                if inputs.is_empty() {
+                    return Err(TransactionError::Malformed { reason: String::from("No inputs") });
+                }
+                if previous_outputs.len() != inputs.len() {
+                    return Err(TransactionError::Malformed {
+                        reason: String::from("Inputs spend the same output twice")
+                    });
+                }
+                outputs
+            },
+            UtxoTransaction::Coinbase { .. } => self.outputs()
+        };
+        if outputs.is_empty() || outputs.iter().any(|output| output.amount == 0) {
+            return Err(TransactionError::ZeroAmount);
+        }
+        Ok(())
+    }
+
+    /// Computes the digest covered by the input signatures of a spend.
+    fn spend_digest(previous_outputs: &Vec<OutPoint>, outputs: &Vec<TxOutput>) -> Hash {
+        Hash::digest(&UnsignedSpend { previous_outputs, outputs })
+    }
+}
+
+/// Makes UtxoTransaction hashable.
+impl ObjectHash for UtxoTransaction {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        match *self {
+            UtxoTransaction::Spend { ref inputs, ref outputs } => {
+                objecthash_struct!(
+                    hasher,
+                    "type" => &String::from("spend"),
+                    "inputs" => inputs,
+                    "outputs" => outputs
+                )
+            },
+            UtxoTransaction::Coinbase { ref output, ref index } => {
+                objecthash_struct!(
+                    hasher,
+                    "type" => &String::from("coinbase"),
+                    "output" => output,
+                    "index" => index
+                )
+            }
+        }
+    }
+}
+
+impl Transaction for UtxoTransaction {
+    fn is_valid(&self) -> bool {
+        self.check().is_ok()
+    }
+}
+
+/// Spend fields covered by the input signatures.
+struct UnsignedSpend<'a> {
+    previous_outputs: &'a Vec<OutPoint>,
+    outputs: &'a Vec<TxOutput>
+}
+
+/// Makes UnsignedSpend hashable.
+impl<'a> ObjectHash for UnsignedSpend<'a> {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        objecthash_struct!(
+            hasher,
+            "type" => &String::from("spend"),
+            "previous_outputs" => self.previous_outputs,
+            "outputs" => self.outputs
+        )
+    }
+}
+
+/// Unspent transaction outputs, with the outputs already spent.
+#[derive(Debug, Clone, Default)]
+pub struct UtxoSet {
+    unspent: HashMap<OutPoint, TxOutput>,
+    spent: HashSet<OutPoint>
+}
+
+impl UtxoSet {
+    /// Sums the unspent outputs owned by `address`.
+    pub fn balance(&self, address: &Address) -> usize {
+        self.unspent.values()
+            .filter(|output| output.owner == *address)
+            .map(|output| output.amount)
+            .sum()
+    }
+
+    /// Lists the unspent outputs owned by `address`.
+    pub fn owned_by(&self, address: &Address) -> Vec<(OutPoint, TxOutput)> {
+        self.unspent.iter()
+            .filter(|&(_, output)| output.owner == *address)
+            .map(|(out_point, output)| (out_point.clone(), output.clone()))
+            .collect()
+    }
+
+    /// Computes the fee left by a spend, i.e. what its outputs do not claim from its inputs.
+    pub fn fee(&self, tx: &UtxoTransaction) -> Result<usize, TransactionError> {
+        match *tx {
+            UtxoTransaction::Spend { ref inputs, ref outputs } => {
+                let mut input_amount = 0;
+                for input in inputs {
+                    let previous_output = &input.previous_output;
+                    match self.unspent.get(previous_output) {
+                        Some(output) => {
+                            input_amount = checked_sum(input_amount, output.amount)?;
+                        },
+                        None if self.spent.contains(previous_output) => {
+                            return Err(TransactionError::DoubleSpend {
+                                tx_hash: previous_output.tx_hash.clone(), index: previous_output.index
+                            });
+                        },
+                        None => {
+                            return Err(TransactionError::UnknownOutput {
+                                tx_hash: previous_output.tx_hash.clone(), index: previous_output.index
+                            });
+                        }
+                    }
+                }
+                let output_amount = total_amount(outputs)?;
+                if input_amount < output_amount {
+                    return Err(TransactionError::OutputsExceedInputs {
+                        inputs: input_amount, outputs: output_amount
+                    });
+                }
+                Ok(input_amount - output_amount)
+            },
+            UtxoTransaction::Coinbase { .. } => Ok(0)
+        }
+    }
+
+    /// Applies a transaction, rejecting unknown or already spent inputs, inputs not signed
+    /// by the owner of the output they spend, and outputs worth more than the inputs.
+    pub fn apply(&mut self, tx: &UtxoTransaction) -> Result<(), TransactionError> {
+        tx.check()?;
+        self.fee(tx)?;
+
+        if let UtxoTransaction::Spend { ref inputs, ref outputs } = *tx {
+            let previous_outputs = inputs.iter().map(|input| input.previous_output.clone()).collect();
+            let digest = UtxoTransaction::spend_digest(&previous_outputs, outputs);
+            for input in inputs {
+                let owner = &self.unspent[&input.previous_output].owner;
+                if ! owner.verify(digest.as_bytes(), &input.signature) {
+                    return Err(TransactionError::InvalidSignature);
+                }
+            }
+            for input in inputs {
+                self.unspent.remove(&input.previous_output);
+                self.spent.insert(input.previous_output.clone());
+            }
+        }
+
+        let tx_hash = tx.hash();
+        for (index, output) in tx.outputs().iter().enumerate() {
+            let out_point = OutPoint { tx_hash: tx_hash.clone(), index };
+            if self.unspent.contains_key(&out_point) || self.spent.contains(&out_point) {
+                return Err(TransactionError::Duplicate { hash: tx_hash });
+            }
+            self.unspent.insert(out_point, output.clone());
+        }
+        Ok(())
+    }
+
+    /// Applies every transaction of a block, checking that its coinbase claims no more
+    /// than `MINING_REWARD` plus the fees of the block.
+    pub fn apply_block(&mut self, block: &UtxoBlock) -> Result<(), TransactionError> {
+        if ! block.is_valid() {
+            return Err(TransactionError::UnexpectedReward);
+        }
+
+        let mut allowed = MINING_REWARD;
+        for tx in &block.transactions {
+            if tx.is_coinbase() {
+                return Err(TransactionError::UnexpectedReward);
+            }
+            allowed = checked_sum(allowed, self.fee(tx)?)?;
+            self.apply(tx)?;
+        }
+
+        if total_amount(block.coinbase.outputs())? > allowed {
+            return Err(TransactionError::UnexpectedReward);
+        }
+        self.apply(&block.coinbase)
+    }
+}
+
+/// Sums the amounts of `outputs`, rejecting a sum that overflows.
+fn total_amount(outputs: &[TxOutput]) -> Result<usize, TransactionError> {
+    outputs.iter().try_fold(0, |total, output| checked_sum(total, output.amount))
+}
+
+fn checked_sum(a: usize, b: usize) -> Result<usize, TransactionError> {
+    a.checked_add(b).ok_or_else(|| TransactionError::Malformed { reason: String::from("Amounts overflow") })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn spend_coinbase_output() {
+        let miner = Keypair::generate();
+        let recipient = Keypair::generate().address();
+        let mut utxo_chain = create_funded_ledger(&miner);
+
+        let tx = spend_coinbase(&utxo_chain, &miner, vec![TxOutput::new(MINING_REWARD, recipient.clone())]);
+        assert_eq!(utxo_chain.add_transaction(tx).unwrap(), 2);
+        assert_eq!(utxo_chain.confirmed_utxos().balance(&miner.address()), MINING_REWARD);
+        assert_eq!(utxo_chain.utxos().balance(&miner.address()), 0);
+        assert_eq!(utxo_chain.utxos().balance(&recipient), MINING_REWARD);
+    }
+
+    #[test]
+    fn double_spend_in_mempool() {
+        let miner = Keypair::generate();
+        let mut utxo_chain = create_funded_ledger(&miner);
+        let first = spend_coinbase(&utxo_chain, &miner,
+            vec![TxOutput::new(MINING_REWARD, Keypair::generate().address())]);
+        let second = spend_coinbase(&utxo_chain, &miner,
+            vec![TxOutput::new(MINING_REWARD, Keypair::generate().address())]);
+
+        utxo_chain.add_transaction(first).unwrap();
+        assert_eq!(utxo_chain.add_transaction(second), Err(TransactionError::DoubleSpend {
+            tx_hash: utxo_chain.chain[0].coinbase.hash(), index: 0
+        }));
+    }
+
+    #[test]
+    fn double_spend_across_chain() {
+        let miner = Keypair::generate();
+        let mut utxo_chain = create_funded_ledger(&miner);
+        let first = spend_coinbase(&utxo_chain, &miner,
+            vec![TxOutput::new(MINING_REWARD, Keypair::generate().address())]);
+        let second = spend_coinbase(&utxo_chain, &miner,
+            vec![TxOutput::new(MINING_REWARD, Keypair::generate().address())]);
+
+        utxo_chain.add_transaction(first).unwrap();
+        utxo_chain.reward(miner.address());
+        utxo_chain.new_block(Timestamp::new(Duration::new(12345, 0)), 1000).unwrap();
+        assert_eq!(utxo_chain.add_transaction(second), Err(TransactionError::DoubleSpend {
+            tx_hash: utxo_chain.chain[0].coinbase.hash(), index: 0
+        }));
+    }
+
+    #[test]
+    fn add_transaction_rejects_foreign_output() {
+        let miner = Keypair::generate();
+        let thief = Keypair::generate();
+        let mut utxo_chain = create_funded_ledger(&miner);
+
+        let tx = spend_coinbase(&utxo_chain, &thief, vec![TxOutput::new(MINING_REWARD, thief.address())]);
+        assert_eq!(utxo_chain.add_transaction(tx), Err(TransactionError::InvalidSignature));
+    }
+
+    #[test]
+    fn add_transaction_rejects_overspending() {
+        let miner = Keypair::generate();
+        let mut utxo_chain = create_funded_ledger(&miner);
+
+        let tx = spend_coinbase(&utxo_chain, &miner, vec![TxOutput::new(2, miner.address())]);
+        assert_eq!(utxo_chain.add_transaction(tx), Err(TransactionError::OutputsExceedInputs {
+            inputs: MINING_REWARD, outputs: 2
+        }));
+    }
+
+    #[test]
+    fn add_transaction_rejects_overflowing_outputs() {
+        let miner = Keypair::generate();
+        let mut utxo_chain = create_funded_ledger(&miner);
+
+        let outputs = vec![TxOutput::new(usize::max_value(), miner.address()), TxOutput::new(2, miner.address())];
+        let tx = spend_coinbase(&utxo_chain, &miner, outputs);
+        match utxo_chain.add_transaction(tx) {
+            Err(TransactionError::Malformed { .. }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        assert_eq!(utxo_chain.utxos().balance(&miner.address()), MINING_REWARD);
+    }
+
+    #[test]
+    fn add_transaction_rejects_unknown_output() {
+        let miner = Keypair::generate();
+        let mut utxo_chain = create_funded_ledger(&miner);
+        let out_point = OutPoint { tx_hash: Hash::default(), index: 0 };
+
+        let tx = UtxoTransaction::spend(&miner, vec![out_point], vec![TxOutput::new(1, miner.address())]);
+        assert_eq!(utxo_chain.add_transaction(tx), Err(TransactionError::UnknownOutput {
+            tx_hash: Hash::default(), index: 0
+        }));
+    }
+
+    #[test]
+    fn add_transaction_rejects_empty_outputs() {
+        let miner = Keypair::generate();
+        let mut utxo_chain = create_funded_ledger(&miner);
+        let tx = spend_coinbase(&utxo_chain, &miner, vec![]);
+        assert_eq!(utxo_chain.add_transaction(tx), Err(TransactionError::ZeroAmount));
+    }
+
+    #[test]
+    fn add_transaction_rejects_repeated_inputs() {
+        let miner = Keypair::generate();
+        let mut utxo_chain = create_funded_ledger(&miner);
+        let out_point = OutPoint { tx_hash: utxo_chain.chain[0].coinbase.hash(), index: 0 };
+
+        let tx = UtxoTransaction::spend(&miner, vec![out_point.clone(), out_point],
+            vec![TxOutput::new(2, miner.address())]);
+        match utxo_chain.add_transaction(tx) {
+            Err(TransactionError::Malformed { .. }) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+
+    #[test]
+    fn new_block_pays_fees_to_miner() {
+        let miner = Keypair::generate();
+        let recipient = Keypair::generate().address();
+        let mut utxo_chain = create_funded_ledger(&miner);
+        utxo_chain.reward(miner.address());
+        utxo_chain.new_block(Timestamp::new(Duration::new(12345, 0)), 1000).unwrap();
+
+        let out_points = utxo_chain.confirmed_utxos().owned_by(&miner.address()).into_iter()
+            .map(|(out_point, _)| out_point)
+            .collect();
+        let tx = UtxoTransaction::spend(&miner, out_points, vec![TxOutput::new(1, recipient.clone())]);
+        utxo_chain.add_transaction(tx).unwrap();
+        utxo_chain.reward(miner.address());
+        utxo_chain.new_block(Timestamp::new(Duration::new(12350, 0)), 1000).unwrap();
+
+        assert_eq!(utxo_chain.confirmed_utxos().balance(&recipient), 1);
+        assert_eq!(utxo_chain.confirmed_utxos().balance(&miner.address()), MINING_REWARD + 1);
+    }
+
+    #[test]
+    fn new_block_requires_miner() {
+        let mut utxo_chain = UtxoLedger::new(Vec::new());
+        match utxo_chain.new_block(Timestamp::new(Duration::new(12345, 0)), 1000) {
+            Err(BlockError::MissingReward) => (),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+
+    #[test]
+    fn is_valid_chain() {
+        let miner = Keypair::generate();
+        let mut utxo_chain = UtxoLedger::new(Vec::new());
+        let proof = utxo_chain.find_proof(0);
+        utxo_chain.reward(miner.address());
+        utxo_chain.new_block(Timestamp::new(Duration::new(12345, 0)), proof).unwrap();
+        assert!(utxo_chain.is_valid());
+
+        let mut greedy = utxo_chain.chain.clone();
+        greedy[0].coinbase = UtxoTransaction::coinbase(miner.address(), MINING_REWARD + 1, 1);
+        assert!(! UtxoLedger::is_valid_chain(&greedy));
+    }
+
+    fn create_funded_ledger(owner: &Keypair) -> UtxoLedger {
+        let mut utxo_chain = UtxoLedger::new(Vec::new());
+        utxo_chain.reward(owner.address());
+        utxo_chain.new_block(Timestamp::new(Duration::new(12340, 0)), 1000).unwrap();
+        utxo_chain
+    }
+
+    fn spend_coinbase(utxo_chain: &UtxoLedger, keypair: &Keypair, outputs: Vec<TxOutput>) -> UtxoTransaction {
+        let out_point = OutPoint { tx_hash: utxo_chain.chain[0].coinbase.hash(), index: 0 };
+        UtxoTransaction::spend(keypair, vec![out_point], outputs)
+    }
+}
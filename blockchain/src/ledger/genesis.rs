@@ -0,0 +1,194 @@
+//! # Genesis
+//!
+//! Defines what a chain starts from. The genesis of a network names it, funds its first
+//! accounts, picks its consensus engine and sets the difficulty of its first block. Its
+//! hash stands for the block before the first block, so chains of different networks never
+//! share a block. It also sets how many blocks separate two snapshots of the ledger state,
+//! 0 meaning none.
+//!
+//! A genesis file is JSON:
+//!
+//! ```json
+//! {
+//!     "chain_id": "testnet",
+//!     "allocations": [{ "address": "<hex public key>", "amount": 100 }],
+//!     "difficulty": 16,
+//!     "snapshot_interval": 1000
+//! }
+//! ```
+//!
+//! Chains run on proof of work unless the genesis names validators:
+//!
+//! ```json
+//! "consensus": { "type": "proof_of_authority", "validators": ["<hex public key>"] }
+//! ```
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use objecthash::{ObjectHash, ObjectHasher};
+use serde_json;
+
+use ledger::codec::{Decode, Encode, Reader};
+use ledger::consensus::ConsensusConfig;
+use ledger::crypto::Address;
+use ledger::error::DecodeError;
+use ledger::pow::INITIAL_DIFFICULTY;
+use ledger::util::Hash;
+
+/// Chain id of the default genesis, meant for local development.
+pub const DEV_CHAIN_ID: &str = "dev";
+/// Blocks between two snapshots of the ledger state, unless the genesis sets otherwise.
+pub const SNAPSHOT_INTERVAL: usize = 1000;
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Genesis {
+    pub chain_id: String,
+    /// Balances of the accounts before the first block.
+    #[serde(default)]
+    pub allocations: Vec<Allocation>,
+    #[serde(default)]
+    pub consensus: ConsensusConfig,
+    /// Difficulty of the first block.
+    #[serde(default = "initial_difficulty")]
+    pub difficulty: u32,
+    /// Blocks between two snapshots of the ledger state, taken at every multiple of it.
+    #[serde(default = "snapshot_interval")]
+    pub snapshot_interval: usize
+}
+
+impl Genesis {
+    pub fn new(chain_id: &str) -> Genesis {
+        Genesis {
+            chain_id: chain_id.to_string(),
+            allocations: Vec::new(),
+            consensus: ConsensusConfig::default(),
+            difficulty: INITIAL_DIFFICULTY,
+            snapshot_interval: SNAPSHOT_INTERVAL
+        }
+    }
+
+    /// Reads a genesis from a JSON file.
+    pub fn read_from<P: AsRef<Path>>(path: P) -> io::Result<Genesis> {
+        let file = File::open(path)?;
+        serde_json::from_reader(file).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn hash(&self) -> Hash {
+        Hash::digest(self)
+    }
+
+    /// Checks whether the block at `index` commits to a snapshot of the ledger state.
+    pub fn is_snapshot_height(&self, index: usize) -> bool {
+        self.snapshot_interval > 0 && index % self.snapshot_interval == 0
+    }
+}
+
+impl Default for Genesis {
+    fn default() -> Genesis {
+        Genesis::new(DEV_CHAIN_ID)
+    }
+}
+
+/// Makes Genesis hashable.
+impl ObjectHash for Genesis {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        objecthash_struct!(
+            hasher,
+            "chain_id" => &self.chain_id,
+            "allocations" => &self.allocations,
+            "consensus" => &self.consensus,
+            "difficulty" => &self.difficulty,
+            "snapshot_interval" => &self.snapshot_interval
+        )
+    }
+}
+
+/// Amount held by an account before the first block.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Allocation {
+    pub address: Address,
+    pub amount: usize
+}
+
+/// Makes Allocation hashable.
+impl ObjectHash for Allocation {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        objecthash_struct!(
+            hasher,
+            "address" => &self.address,
+            "amount" => &self.amount
+        )
+    }
+}
+
+impl Encode for Allocation {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.address.encode(out);
+        self.amount.encode(out);
+    }
+}
+
+impl Decode for Allocation {
+    fn decode(reader: &mut Reader) -> Result<Allocation, DecodeError> {
+        Ok(Allocation { address: Address::decode(reader)?, amount: usize::decode(reader)? })
+    }
+}
+
+fn initial_difficulty() -> u32 {
+    INITIAL_DIFFICULTY
+}
+
+fn snapshot_interval() -> usize {
+    SNAPSHOT_INTERVAL
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use ledger::crypto::Keypair;
+    use super::*;
+
+    #[test]
+    fn read_from_file() {
+        let address = Keypair::generate().address();
+        let path = env::temp_dir().join("blockchain-genesis-read-from.json");
+        let json = format!(r#"{{ "chain_id": "testnet", "allocations": [{{ "address": "{}", "amount": 100 }}] }}"#, address);
+        fs::write(&path, json).unwrap();
+
+        let genesis = Genesis::read_from(&path).unwrap();
+        assert_eq!(genesis, Genesis {
+            chain_id: String::from("testnet"),
+            allocations: vec![Allocation { address, amount: 100 }],
+            consensus: ConsensusConfig::ProofOfWork,
+            difficulty: INITIAL_DIFFICULTY,
+            snapshot_interval: SNAPSHOT_INTERVAL
+        });
+
+        fs::write(&path, "{}").unwrap();
+        assert!(Genesis::read_from(&path).is_err());
+    }
+
+    #[test]
+    fn read_proof_of_authority() {
+        let validator = Keypair::generate().address();
+        let path = env::temp_dir().join("blockchain-genesis-read-poa.json");
+        let json = format!(r#"{{ "chain_id": "poa", "consensus": {{ "type": "proof_of_authority", "validators": ["{}"] }} }}"#,
+            validator);
+        fs::write(&path, json).unwrap();
+
+        let genesis = Genesis::read_from(&path).unwrap();
+        assert_eq!(genesis.consensus, ConsensusConfig::ProofOfAuthority { validators: vec![validator] });
+        assert_ne!(genesis.hash(), Genesis::new("poa").hash());
+    }
+
+    #[test]
+    fn hash_depends_on_chain_id() {
+        assert_eq!(Genesis::default().hash(), Genesis::new(DEV_CHAIN_ID).hash());
+        assert_ne!(Genesis::default().hash(), Genesis::new("testnet").hash());
+    }
+}
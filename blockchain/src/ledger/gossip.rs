@@ -0,0 +1,173 @@
+//! # Gossip
+//!
+//! Spreads blocks and transactions to peers as soon as a node learns about them. Every
+//! node relays what it accepts to its own peers, and remembers what it has seen so that
+//! messages do not loop around the network.
+
+use std::collections::{HashSet, VecDeque};
+use std::thread;
+use std::time::Duration;
+
+use reqwest::{self, Url};
+use serde::Serialize;
+
+use ledger::util::{Hash, Timestamp};
+
+/// Number of message ids remembered by default.
+pub const SEEN_CAPACITY: usize = 10000;
+/// Time a fork block has to wait after the sync that the last fork block triggered, by
+/// default, in seconds.
+pub const FORK_SYNC_INTERVAL: u64 = 10;
+
+/// Outcome of receiving a block from a peer.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// The block extended the chain.
+    Appended,
+    /// The block is already in the chain.
+    Known,
+    /// The block does not extend the chain: the peer follows another fork, which may
+    /// carry more work.
+    Fork,
+    /// The block is on another fork, but too far behind the tip of the chain to be worth
+    /// syncing for.
+    Stale
+}
+
+/// Ids of the most recently seen messages.
+#[derive(Debug)]
+pub struct Seen {
+    ids: HashSet<Hash>,
+    order: VecDeque<Hash>,
+    capacity: usize
+}
+
+impl Seen {
+    pub fn new(capacity: usize) -> Seen {
+        Seen { ids: HashSet::new(), order: VecDeque::new(), capacity }
+    }
+
+    /// Remembers `id`, forgetting the oldest id when full.
+    ///
+    /// Returns whether `id` is new.
+    pub fn insert(&mut self, id: Hash) -> bool {
+        if self.ids.contains(&id) {
+            return false;
+        }
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.ids.insert(id.clone());
+        self.order.push_back(id);
+        true
+    }
+
+    pub fn contains(&self, id: &Hash) -> bool {
+        self.ids.contains(id)
+    }
+}
+
+impl Default for Seen {
+    fn default() -> Seen {
+        Seen::new(SEEN_CAPACITY)
+    }
+}
+
+/// Lets an action through at most once per interval, e.g. the syncs that fork blocks
+/// trigger, which a peer could otherwise make the node run over and over.
+#[derive(Debug)]
+pub struct Throttle {
+    interval: Duration,
+    last: Option<Timestamp>
+}
+
+impl Throttle {
+    pub fn new(interval: Duration) -> Throttle {
+        Throttle { interval, last: None }
+    }
+
+    /// Checks whether the action may run at `now`, and records that it ran if so.
+    ///
+    /// The action may run when the clock went back since it last ran.
+    pub fn allow(&mut self, now: &Timestamp) -> bool {
+        let allowed = match self.last {
+            Some(ref last) => now.0.checked_sub(last.0).is_none_or(|elapsed| elapsed >= self.interval),
+            None => true
+        };
+        if allowed {
+            self.last = Some(now.clone());
+        }
+        allowed
+    }
+}
+
+impl Default for Throttle {
+    fn default() -> Throttle {
+        Throttle::new(Duration::from_secs(FORK_SYNC_INTERVAL))
+    }
+}
+
+/// Posts `message` as JSON to `path` on every peer.
+///
+/// Peers that cannot be reached are skipped. Returns the number of peers that accepted
+/// the message.
+pub fn broadcast<M: Serialize>(peers: &[String], path: &str, message: &M) -> usize {
+    let client = reqwest::Client::new();
+    peers.iter()
+        .filter_map(|peer| Url::parse(peer).and_then(|url| url.join(path)).ok())
+        .filter_map(|url| client.post(url).json(message).send().ok())
+        .filter(|resp| resp.status().is_success())
+        .count()
+}
+
+/// Broadcasts `message` in the background, leaving the caller free to answer its own request.
+pub fn relay<M: Serialize + Send + 'static>(peers: Vec<String>, path: &'static str, message: M) {
+    thread::spawn(move || broadcast(&peers, path, &message));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seen_rejects_repeated_ids() {
+        let mut seen = Seen::default();
+        assert!(seen.insert(hash(1)));
+        assert!(seen.insert(hash(2)));
+        assert!(! seen.insert(hash(1)));
+        assert!(seen.contains(&hash(2)));
+    }
+
+    #[test]
+    fn seen_forgets_oldest_ids() {
+        let mut seen = Seen::new(2);
+        seen.insert(hash(1));
+        seen.insert(hash(2));
+        seen.insert(hash(3));
+        assert!(! seen.contains(&hash(1)));
+        assert!(seen.contains(&hash(2)));
+        assert!(seen.contains(&hash(3)));
+        assert!(seen.insert(hash(1)));
+    }
+
+    #[test]
+    fn throttle_allows_once_per_interval() {
+        let mut throttle = Throttle::new(Duration::from_secs(10));
+        assert!(throttle.allow(&at(100)));
+        assert!(! throttle.allow(&at(105)));
+        assert!(throttle.allow(&at(110)));
+        assert!(throttle.allow(&at(50)));
+        assert!(! throttle.allow(&at(55)));
+    }
+
+    fn at(secs: u64) -> Timestamp {
+        Timestamp::new(Duration::from_secs(secs))
+    }
+
+    fn hash(byte: u8) -> Hash {
+        Hash::from_slice(&[byte; 32]).unwrap()
+    }
+}
@@ -0,0 +1,135 @@
+//! # Index
+//!
+//! Looks blocks and transactions up without scanning the chain. The index follows the
+//! chain block by block, and drops the blocks a reorganization rolls back.
+
+use std::collections::HashMap;
+
+use ledger::crypto::Address;
+use ledger::util::Hash;
+
+/// Place of a transaction in the chain.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+pub struct Location {
+    /// Height of the block holding the transaction, counting from 1.
+    pub block: usize,
+    /// Position of the transaction in its block.
+    pub position: usize
+}
+
+/// Transaction of an indexed block, given by its hash and the addresses it involves.
+pub type Indexed = (Hash, Vec<Address>);
+
+#[derive(Debug, Default)]
+pub struct Index {
+    /// Transactions of every indexed block, by height.
+    blocks: Vec<(Hash, Vec<Indexed>)>,
+    heights: HashMap<Hash, usize>,
+    transactions: HashMap<Hash, Location>,
+    addresses: HashMap<Address, Vec<Location>>,
+    transaction_count: usize
+}
+
+impl Index {
+    /// Indexes the block that follows the indexed ones.
+    pub fn push(&mut self, hash: Hash, transactions: Vec<Indexed>) {
+        let block = self.blocks.len() + 1;
+        for (position, &(ref tx_hash, ref addresses)) in transactions.iter().enumerate() {
+            let location = Location { block, position };
+            self.transactions.insert(tx_hash.clone(), location);
+            for address in addresses {
+                self.addresses.entry(address.clone()).or_default().push(location);
+            }
+        }
+        self.transaction_count += transactions.len();
+        self.heights.insert(hash.clone(), block);
+        self.blocks.push((hash, transactions));
+    }
+
+    /// Drops the blocks above `height`.
+    pub fn truncate(&mut self, height: usize) {
+        while self.blocks.len() > height {
+            let block = self.blocks.len();
+            let (hash, transactions) = self.blocks.pop().expect("Index holds the block");
+            for (tx_hash, addresses) in transactions {
+                if self.transactions.get(&tx_hash).is_some_and(|location| location.block == block) {
+                    self.transactions.remove(&tx_hash);
+                }
+                for address in addresses {
+                    if let Some(locations) = self.addresses.get_mut(&address) {
+                        locations.retain(|location| location.block != block);
+                    }
+                }
+                self.transaction_count -= 1;
+            }
+            self.addresses.retain(|_, locations| ! locations.is_empty());
+            self.heights.remove(&hash);
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn transaction_count(&self) -> usize {
+        self.transaction_count
+    }
+
+    /// Gets the height of the block with `hash`.
+    pub fn block_height(&self, hash: &Hash) -> Option<usize> {
+        self.heights.get(hash).cloned()
+    }
+
+    /// Locates the transaction with `hash`.
+    pub fn transaction(&self, hash: &Hash) -> Option<Location> {
+        self.transactions.get(hash).cloned()
+    }
+
+    /// Locates the transactions involving `address`, oldest first.
+    pub fn transactions_of(&self, address: &Address) -> &[Location] {
+        self.addresses.get(address).map_or(&[][..], |locations| &locations[..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ledger::crypto::Keypair;
+    use super::*;
+
+    #[test]
+    fn push_indexes_transactions() {
+        let (alice, bob) = (Keypair::generate().address(), Keypair::generate().address());
+        let mut index = Index::default();
+        index.push(hash(1), vec![(hash(10), vec![alice.clone()])]);
+        index.push(hash(2), vec![(hash(20), vec![bob.clone()]), (hash(21), vec![alice.clone(), bob.clone()])]);
+
+        assert_eq!(index.height(), 2);
+        assert_eq!(index.transaction_count(), 3);
+        assert_eq!(index.block_height(&hash(2)), Some(2));
+        assert_eq!(index.transaction(&hash(21)), Some(Location { block: 2, position: 1 }));
+        assert_eq!(index.transactions_of(&alice), &[Location { block: 1, position: 0 }, Location { block: 2, position: 1 }]);
+    }
+
+    #[test]
+    fn truncate_drops_rolled_back_blocks() {
+        let alice = Keypair::generate().address();
+        let mut index = Index::default();
+        index.push(hash(1), vec![(hash(10), vec![alice.clone()])]);
+        index.push(hash(2), vec![(hash(20), vec![alice.clone()])]);
+        index.push(hash(3), vec![(hash(30), vec![alice.clone()])]);
+
+        index.truncate(1);
+        assert_eq!(index.height(), 1);
+        assert_eq!(index.transaction_count(), 1);
+        assert_eq!(index.block_height(&hash(2)), None);
+        assert_eq!(index.transaction(&hash(20)), None);
+        assert_eq!(index.transactions_of(&alice), &[Location { block: 1, position: 0 }]);
+
+        index.truncate(0);
+        assert!(index.transactions_of(&alice).is_empty());
+    }
+
+    fn hash(byte: u8) -> Hash {
+        Hash::from_slice(&[byte; 32]).unwrap()
+    }
+}
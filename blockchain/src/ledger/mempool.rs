@@ -0,0 +1,197 @@
+//! # Mempool
+//!
+//! Holds the transactions waiting for a block. Miners pick them by fee rate, and once the
+//! mempool is full, the transactions paying the lowest fee rate make room for better ones.
+
+use std::cmp::Reverse;
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json;
+
+use ledger::error::TransactionError;
+use ledger::util::{Hash, Timestamp};
+
+/// Number of transactions a mempool holds by default.
+pub const CAPACITY: usize = 5000;
+/// Time after which a pending transaction is dropped by default, in seconds.
+pub const EXPIRY: u64 = 60 * 60;
+
+/// Pending transaction, along with what the mempool orders it by.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Entry<T> {
+    pub tx: T,
+    pub hash: Hash,
+    pub fee: usize,
+    /// Size of the JSON transaction, in bytes.
+    pub size: usize,
+    pub received: Timestamp
+}
+
+impl<T: Serialize> Entry<T> {
+    pub fn new(tx: T, hash: Hash, fee: usize, received: Timestamp) -> Entry<T> {
+        let size = serde_json::to_vec(&tx).map(|json| json.len()).unwrap_or(0);
+        Entry { tx, hash, fee, size, received }
+    }
+}
+
+impl<T> Entry<T> {
+    /// Fee paid per kilobyte.
+    pub fn fee_rate(&self) -> usize {
+        self.fee.saturating_mul(1000) / self.size.max(1)
+    }
+}
+
+/// Pending transactions, kept in the order they were received.
+#[derive(Debug)]
+pub struct Mempool<T> {
+    entries: Vec<Entry<T>>,
+    capacity: usize,
+    expiry: Duration
+}
+
+impl<T> Mempool<T> {
+    pub fn new(capacity: usize, expiry: Duration) -> Mempool<T> {
+        Mempool { entries: Vec::new(), capacity, expiry }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.entries.iter().any(|entry| entry.hash == *hash)
+    }
+
+    /// Iterates over the entries in the order they were received.
+    pub fn iter(&self) -> ::std::slice::Iter<Entry<T>> {
+        self.entries.iter()
+    }
+
+    /// Lists the entries from the highest fee rate down, the oldest first among equals.
+    pub fn by_fee_rate(&self) -> Vec<&Entry<T>> {
+        let mut entries: Vec<&Entry<T>> = self.entries.iter().collect();
+        entries.sort_by_key(|entry| Reverse(entry.fee_rate()));
+        entries
+    }
+
+    /// Adds `entry`, evicting the entry with the lowest fee rate when the mempool is full.
+    ///
+    /// Fails when `entry` does not pay more than the entry it would evict. Returns the
+    /// evicted entry, if any.
+    pub fn insert(&mut self, entry: Entry<T>) -> Result<Option<Entry<T>>, TransactionError> {
+        if self.entries.len() < self.capacity {
+            self.entries.push(entry);
+            return Ok(None);
+        }
+
+        let lowest = match self.lowest() {
+            Some(lowest) => lowest,
+            None => return Err(TransactionError::FeeTooLow { fee_rate: entry.fee_rate(), minimum: 0 })
+        };
+        let minimum = self.entries[lowest].fee_rate().saturating_add(1);
+        if entry.fee_rate() < minimum {
+            return Err(TransactionError::FeeTooLow { fee_rate: entry.fee_rate(), minimum });
+        }
+        let evicted = self.entries.remove(lowest);
+        self.entries.push(entry);
+        Ok(Some(evicted))
+    }
+
+    /// Keeps only the entries for which `f` returns true, visiting them in the order they
+    /// were received.
+    pub fn retain<F: FnMut(&Entry<T>) -> bool>(&mut self, f: F) {
+        self.entries.retain(f);
+    }
+
+    /// Drops the entries received longer than the expiry before `now`.
+    pub fn expire(&mut self, now: &Timestamp) -> Vec<Entry<T>> {
+        let expiry = self.expiry;
+        let (expired, entries) = self.entries.drain(..)
+            .partition(|entry| now.0.checked_sub(entry.received.0).is_some_and(|age| age > expiry));
+        self.entries = entries;
+        expired
+    }
+
+    /// Takes every entry out, in the order they were received.
+    pub fn drain(&mut self) -> Vec<Entry<T>> {
+        self.entries.drain(..).collect()
+    }
+
+    /// Finds the entry with the lowest fee rate, the newest among equals.
+    fn lowest(&self) -> Option<usize> {
+        (0..self.entries.len()).rev().min_by_key(|&i| self.entries[i].fee_rate())
+    }
+}
+
+impl<T> Default for Mempool<T> {
+    fn default() -> Mempool<T> {
+        Mempool::new(CAPACITY, Duration::from_secs(EXPIRY))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn by_fee_rate_orders_entries() {
+        let mut mempool = Mempool::default();
+        mempool.insert(create_entry(1, 10, 0)).unwrap();
+        mempool.insert(create_entry(2, 30, 0)).unwrap();
+        mempool.insert(create_entry(3, 10, 0)).unwrap();
+
+        let hashes: Vec<Hash> = mempool.by_fee_rate().iter().map(|entry| entry.hash.clone()).collect();
+        assert_eq!(hashes, vec![hash(2), hash(1), hash(3)]);
+    }
+
+    #[test]
+    fn insert_evicts_lowest_fee_rate() {
+        let mut mempool = Mempool::new(2, Duration::from_secs(EXPIRY));
+        mempool.insert(create_entry(1, 20, 0)).unwrap();
+        mempool.insert(create_entry(2, 10, 0)).unwrap();
+
+        let low = create_entry(3, 10, 0);
+        let minimum = low.fee_rate() + 1;
+        assert_eq!(mempool.insert(low.clone()),
+            Err(TransactionError::FeeTooLow { fee_rate: low.fee_rate(), minimum }));
+
+        assert_eq!(mempool.insert(create_entry(4, 30, 0)).unwrap(), Some(create_entry(2, 10, 0)));
+        assert!(mempool.contains(&hash(1)));
+        assert!(mempool.contains(&hash(4)));
+    }
+
+    #[test]
+    fn fee_rate_saturates_on_large_fees() {
+        let entry = create_entry(1, usize::max_value(), 0);
+        assert_eq!(entry.fee_rate(), usize::max_value() / entry.size);
+
+        let mut mempool = Mempool::new(1, Duration::from_secs(EXPIRY));
+        mempool.insert(entry).unwrap();
+        assert!(mempool.insert(create_entry(2, usize::max_value(), 0)).is_err());
+    }
+
+    #[test]
+    fn expire_drops_old_entries() {
+        let mut mempool = Mempool::new(10, Duration::from_secs(60));
+        mempool.insert(create_entry(1, 10, 0)).unwrap();
+        mempool.insert(create_entry(2, 10, 30)).unwrap();
+
+        let expired = mempool.expire(&Timestamp::new(Duration::from_secs(70)));
+        assert_eq!(expired, vec![create_entry(1, 10, 0)]);
+        assert_eq!(mempool.len(), 1);
+        assert!(mempool.contains(&hash(2)));
+    }
+
+    fn create_entry(id: u8, fee: usize, secs: u64) -> Entry<String> {
+        Entry::new(String::from("transaction"), hash(id), fee, Timestamp::new(Duration::from_secs(secs)))
+    }
+
+    fn hash(byte: u8) -> Hash {
+        Hash::from_slice(&[byte; 32]).unwrap()
+    }
+}
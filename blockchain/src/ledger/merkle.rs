@@ -0,0 +1,144 @@
+//! # Merkle
+//!
+//! Computes Merkle roots over transaction hashes, and proves that a transaction hash is
+//! part of a root without the other transactions.
+
+use ring::digest::{self, SHA256};
+
+use ledger::util::Hash;
+
+/// Prefix of hashed leaves, so that a leaf cannot be passed off as an inner node.
+const LEAF_PREFIX: u8 = 0;
+/// Prefix of hashed inner nodes.
+const NODE_PREFIX: u8 = 1;
+
+/// Computes the Merkle root of `leaves`.
+///
+/// A node without a sibling is promoted to the next level as is. The root of no leaves
+/// is all zeros.
+pub fn root(leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return Hash::default();
+    }
+
+    let mut level: Vec<Hash> = leaves.iter().map(|leaf| hash_leaf(leaf)).collect();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level.remove(0)
+}
+
+/// Proves that the leaf at `index` is part of the root of `leaves`.
+pub fn prove(leaves: &[Hash], index: usize) -> Option<MerkleProof> {
+    if index >= leaves.len() {
+        return None;
+    }
+
+    let mut steps = Vec::new();
+    let mut index = index;
+    let mut level: Vec<Hash> = leaves.iter().map(|leaf| hash_leaf(leaf)).collect();
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        if sibling < level.len() {
+            steps.push(ProofStep { hash: level[sibling].clone(), is_left: sibling < index });
+        }
+        level = next_level(&level);
+        index /= 2;
+    }
+    Some(MerkleProof { steps })
+}
+
+/// Sibling hashes on the path from a leaf up to the root.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub steps: Vec<ProofStep>
+}
+
+impl MerkleProof {
+    /// Checks that `leaf` is part of `root`.
+    pub fn verify(&self, root: &Hash, leaf: &Hash) -> bool {
+        let mut hash = hash_leaf(leaf);
+        for step in &self.steps {
+            hash = if step.is_left {
+                hash_node(&step.hash, &hash)
+            } else {
+                hash_node(&hash, &step.hash)
+            };
+        }
+        hash == *root
+    }
+}
+
+/// Sibling hash, and whether it sits left of the path.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ProofStep {
+    pub hash: Hash,
+    pub is_left: bool
+}
+
+/// Hashes every pair of nodes together, promoting a last node without a sibling as is.
+fn next_level(level: &[Hash]) -> Vec<Hash> {
+    level.chunks(2)
+        .map(|pair| if pair.len() == 2 { hash_node(&pair[0], &pair[1]) } else { pair[0].clone() })
+        .collect()
+}
+
+fn hash_leaf(leaf: &Hash) -> Hash {
+    let mut data = vec![LEAF_PREFIX];
+    data.extend_from_slice(leaf.as_bytes());
+    sha256(&data)
+}
+
+fn hash_node(left: &Hash, right: &Hash) -> Hash {
+    let mut data = vec![NODE_PREFIX];
+    data.extend_from_slice(left.as_bytes());
+    data.extend_from_slice(right.as_bytes());
+    sha256(&data)
+}
+
+fn sha256(data: &[u8]) -> Hash {
+    Hash::from_slice(digest::digest(&SHA256, data).as_ref()).expect("SHA-256 digest is a hash")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn root_of_no_leaves() {
+        assert_eq!(root(&[]), Hash::default());
+    }
+
+    #[test]
+    fn root_depends_on_order() {
+        let leaves = create_leaves(3);
+        let mut swapped = leaves.clone();
+        swapped.swap(0, 1);
+        assert_ne!(root(&leaves), root(&swapped));
+    }
+
+    #[test]
+    fn prove_and_verify() {
+        for len in 1..10 {
+            let leaves = create_leaves(len);
+            let root = root(&leaves);
+            for index in 0..len {
+                let proof = prove(&leaves, index).unwrap();
+                assert!(proof.verify(&root, &leaves[index]));
+                assert!(! proof.verify(&root, &Hash::from_slice(&[0xff; 32]).unwrap()));
+            }
+            assert!(prove(&leaves, len).is_none());
+        }
+    }
+
+    #[test]
+    fn verify_rejects_other_root() {
+        let leaves = create_leaves(4);
+        let proof = prove(&leaves, 2).unwrap();
+        assert!(! proof.verify(&root(&leaves[..3]), &leaves[2]));
+    }
+
+    fn create_leaves(len: usize) -> Vec<Hash> {
+        (0..len).map(|i| Hash::from_slice(&[i as u8; 32]).unwrap()).collect()
+    }
+}
@@ -0,0 +1,175 @@
+//! # Metrics
+//!
+//! Renders what operators watch on a node in the Prometheus text format, and keeps the
+//! latencies of the requests the node serves.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Media type of the Prometheus text format.
+pub const MEDIA_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Upper bounds of the latency buckets, in seconds.
+pub const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Kind of a metric, as told to Prometheus.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Kind {
+    Counter,
+    Gauge,
+    Histogram
+}
+
+/// Counts what happens to a ledger, where its state alone does not tell.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Counters {
+    /// Switches to another fork since the node started.
+    pub reorgs: u64,
+    /// Proofs tried per second by the latest seal found by searching for proofs.
+    pub hash_rate: Option<f64>
+}
+
+/// Metrics in the Prometheus text format, written one family at a time.
+#[derive(Debug, Default)]
+pub struct Exposition {
+    text: String
+}
+
+impl Exposition {
+    pub fn new() -> Exposition {
+        Exposition::default()
+    }
+
+    /// Starts the family of metrics `name`, which the samples that follow belong to.
+    pub fn family(&mut self, name: &str, kind: Kind, help: &str) {
+        let kind = match kind {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Histogram => "histogram"
+        };
+        let help = help.replace('\\', "\\\\").replace('\n', "\\n");
+        self.text.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.text.push_str(name);
+        if ! labels.is_empty() {
+            let labels: Vec<String> = labels.iter()
+                .map(|&(label, value)| format!("{}=\"{}\"", label, escape_label(value)))
+                .collect();
+            self.text.push_str(&format!("{{{}}}", labels.join(",")));
+        }
+        self.text.push_str(&format!(" {}\n", format_value(value)));
+    }
+
+    /// Writes a family holding a single sample without labels.
+    pub fn single(&mut self, name: &str, kind: Kind, help: &str, value: f64) {
+        self.family(name, kind, help);
+        self.sample(name, &[], value);
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn into_string(self) -> String {
+        self.text
+    }
+}
+
+/// Histograms of the time taken to serve the requests to each route.
+#[derive(Debug, Default)]
+pub struct Latencies {
+    routes: BTreeMap<String, Histogram>
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Number of requests within each of `LATENCY_BUCKETS`.
+    buckets: [u64; 11],
+    count: u64,
+    sum: f64
+}
+
+impl Latencies {
+    /// Records that a request to `route` took `elapsed`.
+    pub fn record(&mut self, route: &str, elapsed: Duration) {
+        let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+        let histogram = self.routes.entry(route.to_string()).or_default();
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(histogram.buckets.iter_mut()) {
+            if secs <= *bound {
+                *bucket += 1;
+            }
+        }
+        histogram.count += 1;
+        histogram.sum += secs;
+    }
+
+    /// Writes the histograms of every route that served a request, labelled by route.
+    pub fn write_to(&self, out: &mut Exposition) {
+        let name = "blockchain_request_duration_seconds";
+        out.family(name, Kind::Histogram, "Time taken to serve requests, in seconds, by route.");
+        for (route, histogram) in &self.routes {
+            let bucket = format!("{}_bucket", name);
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                out.sample(&bucket, &[("route", route), ("le", &format_value(*bound))], *count as f64);
+            }
+            out.sample(&bucket, &[("route", route), ("le", "+Inf")], histogram.count as f64);
+            out.sample(&format!("{}_sum", name), &[("route", route)], histogram.sum);
+            out.sample(&format!("{}_count", name), &[("route", route)], histogram.count as f64);
+        }
+    }
+}
+
+/// Formats a sample value the way Prometheus reads it.
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        String::from("NaN")
+    } else if value.is_infinite() {
+        String::from(if value > 0.0 { "+Inf" } else { "-Inf" })
+    } else {
+        value.to_string()
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_families_and_samples() {
+        let mut out = Exposition::new();
+        out.single("blockchain_height", Kind::Gauge, "Number of blocks.", 3.0);
+        out.family("blockchain_peer_failures", Kind::Gauge, "Failures in a row.");
+        out.sample("blockchain_peer_failures", &[("peer", "http://a/\"b\"")], 0.5);
+        out.sample("blockchain_peer_failures", &[], ::std::f64::INFINITY);
+        assert_eq!(out.as_str(), "# HELP blockchain_height Number of blocks.\n\
+            # TYPE blockchain_height gauge\n\
+            blockchain_height 3\n\
+            # HELP blockchain_peer_failures Failures in a row.\n\
+            # TYPE blockchain_peer_failures gauge\n\
+            blockchain_peer_failures{peer=\"http://a/\\\"b\\\"\"} 0.5\n\
+            blockchain_peer_failures +Inf\n");
+    }
+
+    #[test]
+    fn latencies_fill_cumulative_buckets() {
+        let mut latencies = Latencies::default();
+        latencies.record("GET /tip", Duration::from_millis(20));
+        latencies.record("GET /tip", Duration::from_secs(20));
+
+        let mut out = Exposition::new();
+        latencies.write_to(&mut out);
+        let text = out.into_string();
+        assert!(text.contains("blockchain_request_duration_seconds_bucket{route=\"GET /tip\",le=\"0.01\"} 0\n"));
+        assert!(text.contains("blockchain_request_duration_seconds_bucket{route=\"GET /tip\",le=\"0.025\"} 1\n"));
+        assert!(text.contains("blockchain_request_duration_seconds_bucket{route=\"GET /tip\",le=\"10\"} 1\n"));
+        assert!(text.contains("blockchain_request_duration_seconds_bucket{route=\"GET /tip\",le=\"+Inf\"} 2\n"));
+        assert!(text.contains("blockchain_request_duration_seconds_sum{route=\"GET /tip\"} 20.02\n"));
+        assert!(text.contains("blockchain_request_duration_seconds_count{route=\"GET /tip\"} 2\n"));
+    }
+}
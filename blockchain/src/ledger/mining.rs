@@ -0,0 +1,112 @@
+//! # Mining
+//!
+//! Keeps track of the mining jobs of a node. A job seals a block on a snapshot of the chain
+//! tip, away from the ledger, and gets aborted when the tip moves under it.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use ledger::util::Hash;
+
+/// Number of finished jobs whose status is remembered.
+const HISTORY: u64 = 100;
+
+/// Snapshot of what the next block has to build upon.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Target {
+    pub height: usize,
+    pub last_block_hash: Hash,
+    pub difficulty: u32
+}
+
+/// Status of a mining job.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    /// The job mined the block at `index`, trying `hash_rate` proofs per second when the
+    /// consensus engine searches for proofs.
+    Mined { index: usize, hash: Hash, hash_rate: Option<f64> },
+    /// The chain tip moved before the job sealed the block.
+    Aborted,
+    Failed { reason: String }
+}
+
+/// Mining jobs of a node, which runs one job at a time.
+#[derive(Debug, Default)]
+pub struct Jobs {
+    next_id: u64,
+    statuses: HashMap<u64, JobStatus>,
+    running: Option<(u64, Arc<AtomicBool>)>
+}
+
+impl Jobs {
+    /// Starts a job, giving its id along with the flag that aborts it.
+    ///
+    /// Fails with the id of the running job, if any.
+    pub fn start(&mut self) -> Result<(u64, Arc<AtomicBool>), u64> {
+        if let Some((id, _)) = self.running {
+            return Err(id);
+        }
+
+        let id = self.next_id;
+        let abort = Arc::new(AtomicBool::new(false));
+        self.next_id += 1;
+        self.statuses.insert(id, JobStatus::Running);
+        self.running = Some((id, abort.clone()));
+        Ok((id, abort))
+    }
+
+    /// Records how job `id` ended.
+    pub fn finish(&mut self, id: u64, status: JobStatus) {
+        if self.running.as_ref().map(|&(running, _)| running) == Some(id) {
+            self.running = None;
+        }
+        self.statuses.insert(id, status);
+
+        let next_id = self.next_id;
+        self.statuses.retain(|&id, _| id + HISTORY >= next_id);
+    }
+
+    /// Tells the running job, if any, to give up.
+    pub fn abort(&self) {
+        if let Some((_, ref abort)) = self.running {
+            abort.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn status(&self, id: u64) -> Option<&JobStatus> {
+        self.statuses.get(&id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_one_job_at_a_time() {
+        let mut jobs = Jobs::default();
+        let (id, abort) = jobs.start().unwrap();
+        assert_eq!(jobs.start().unwrap_err(), id);
+        assert_eq!(jobs.status(id), Some(&JobStatus::Running));
+
+        jobs.abort();
+        assert!(abort.load(Ordering::Relaxed));
+        jobs.finish(id, JobStatus::Aborted);
+        assert_eq!(jobs.status(id), Some(&JobStatus::Aborted));
+        assert_eq!(jobs.start().unwrap().0, id + 1);
+    }
+
+    #[test]
+    fn forgets_old_jobs() {
+        let mut jobs = Jobs::default();
+        for _ in 0..HISTORY + 2 {
+            let (id, _) = jobs.start().unwrap();
+            jobs.finish(id, JobStatus::Aborted);
+        }
+        assert_eq!(jobs.status(0), None);
+        assert_eq!(jobs.status(HISTORY + 1), Some(&JobStatus::Aborted));
+    }
+}
@@ -4,28 +4,43 @@
 
 pub mod error;
 pub mod util;
+pub mod crypto;
+pub mod codec;
+pub mod genesis;
+pub mod merkle;
+pub mod script;
+pub mod pow;
+pub mod consensus;
+pub mod storage;
+pub mod mempool;
+pub mod gossip;
+pub mod peers;
+pub mod sync;
+pub mod mining;
+pub mod index;
+pub mod metrics;
 pub mod example;
 
 use self::error::*;
-use self::util::Timestamp;
+use self::util::{Hash, Timestamp};
 
 pub trait DistributedLedger<B: Block, T: Transaction> {
     /// Ledger representation
     type LedgerRepr;
-    /// Proof used for block validations
+    /// Seal letting a block into the chain, as checked by the consensus engine
     type Proof;
 
-    fn hash(obj: &B) -> Vec<u8>;
-    fn is_valid_proof(last_block_hash: Vec<u8>, last_proof: Self::Proof, proof: Self::Proof) -> bool;
+    fn hash(obj: &B) -> Hash;
 
     fn new_block(&mut self, timestamp: Timestamp, proof: Self::Proof) -> Result<&Self::LedgerRepr, BlockError>;
     fn add_transaction(&mut self, tx: T) -> Result<usize, TransactionError>;
-    fn find_proof(&self, last_proof: Self::Proof) -> Self::Proof;
     fn last_block(&self) -> Option<&B>;
     fn is_valid(&self) -> bool;
 }
 
 pub trait Block {
+    fn timestamp(&self) -> &Timestamp;
+    fn difficulty(&self) -> u32;
     fn is_valid(&self) -> bool;
 }
 
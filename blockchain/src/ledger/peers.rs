@@ -0,0 +1,207 @@
+//! # Peers
+//!
+//! Keeps track of the peers of a node. Peers are known by their base URL, and a peer that
+//! keeps failing is contacted less and less often, until it gets evicted.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use reqwest::Url;
+use serde_json;
+
+use ledger::error::PeerError;
+use ledger::util::Timestamp;
+
+/// Consecutive failures after which a peer is evicted.
+pub const MAX_FAILURES: u32 = 5;
+/// Time a peer is left alone after its first failure, in seconds. The time doubles with
+/// each further failure.
+pub const BACKOFF: u64 = 10;
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Peer {
+    pub url: String,
+    /// Last time the peer answered.
+    pub last_seen: Option<Timestamp>,
+    /// Number of failures since the peer last answered.
+    pub failures: u32,
+    /// Time before which the peer is not contacted.
+    pub retry_at: Option<Timestamp>
+}
+
+/// Peers of a node, without duplicates and without the node itself.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PeerTable {
+    peers: Vec<Peer>,
+    #[serde(skip)]
+    own_url: Option<String>,
+    #[serde(skip)]
+    path: Option<PathBuf>
+}
+
+impl PeerTable {
+    /// Loads the table kept in the JSON file at `path`, and keeps it there on `save`.
+    ///
+    /// A missing file gives an empty table.
+    pub fn load<P: Into<PathBuf>>(path: P) -> io::Result<PeerTable> {
+        let path = path.into();
+        let mut table = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(file).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => PeerTable::default(),
+            Err(e) => return Err(e)
+        };
+        table.path = Some(path);
+        Ok(table)
+    }
+
+    /// Writes the table to the file it was loaded from, if any.
+    pub fn save(&self) -> io::Result<()> {
+        match self.path {
+            Some(ref path) => {
+                let json = serde_json::to_vec(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                fs::write(path, json)
+            },
+            None => Ok(())
+        }
+    }
+
+    /// Sets the URL of the node itself, which is never added as a peer.
+    pub fn set_own_url(&mut self, url: &str) -> Result<(), PeerError> {
+        let url = normalize(url)?;
+        self.peers.retain(|peer| peer.url != url);
+        self.own_url = Some(url);
+        Ok(())
+    }
+
+    /// Adds the peer at `url`, unless it is known already.
+    ///
+    /// Returns whether the peer was added.
+    pub fn add(&mut self, url: &str) -> Result<bool, PeerError> {
+        let url = normalize(url)?;
+        if self.own_url.as_ref() == Some(&url) {
+            return Err(PeerError::OwnUrl { url });
+        }
+        if self.peers.iter().any(|peer| peer.url == url) {
+            return Ok(false);
+        }
+        self.peers.push(Peer { url, last_seen: None, failures: 0, retry_at: None });
+        Ok(true)
+    }
+
+    /// Removes the peer at `url`, returning whether it was known.
+    pub fn remove(&mut self, url: &str) -> bool {
+        let url = match normalize(url) {
+            Ok(url) => url,
+            Err(_) => return false
+        };
+        let len = self.peers.len();
+        self.peers.retain(|peer| peer.url != url);
+        self.peers.len() < len
+    }
+
+    pub fn peers(&self) -> &[Peer] {
+        &self.peers
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Lists the URLs of the peers that are not backing off at `now`.
+    pub fn available(&self, now: &Timestamp) -> Vec<String> {
+        self.peers.iter()
+            .filter(|peer| peer.retry_at.as_ref().is_none_or(|retry_at| retry_at.0 <= now.0))
+            .map(|peer| peer.url.to_string())
+            .collect()
+    }
+
+    /// Records that the peer at `url` answered at `now`.
+    pub fn record_success(&mut self, url: &str, now: &Timestamp) {
+        if let Some(peer) = self.peers.iter_mut().find(|peer| peer.url == url) {
+            peer.last_seen = Some(now.clone());
+            peer.failures = 0;
+            peer.retry_at = None;
+        }
+    }
+
+    /// Records that the peer at `url` failed at `now`, evicting it after `MAX_FAILURES`
+    /// failures in a row.
+    pub fn record_failure(&mut self, url: &str, now: &Timestamp) {
+        if let Some(peer) = self.peers.iter_mut().find(|peer| peer.url == url) {
+            peer.failures += 1;
+            let backoff = Duration::from_secs(BACKOFF << (peer.failures - 1).min(16));
+            peer.retry_at = Some(Timestamp::new(now.0 + backoff));
+        }
+        self.peers.retain(|peer| peer.failures < MAX_FAILURES);
+    }
+}
+
+/// Parses `url` as the base URL of a peer, giving its canonical form.
+fn normalize(url: &str) -> Result<String, PeerError> {
+    match Url::parse(url) {
+        Ok(ref parsed) if (parsed.scheme() == "http" || parsed.scheme() == "https") && parsed.has_host() => {
+            Ok(parsed.as_str().to_string())
+        },
+        _ => Err(PeerError::InvalidUrl { url: url.to_string() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use super::*;
+
+    #[test]
+    fn add_validates_and_dedupes() {
+        let mut table = PeerTable::default();
+        table.set_own_url("http://127.0.0.1:8000").unwrap();
+
+        assert_eq!(table.add("http://127.0.0.1:8001"), Ok(true));
+        assert_eq!(table.add("http://127.0.0.1:8001/"), Ok(false));
+        assert_eq!(table.add("127.0.0.1:8002"), Err(PeerError::InvalidUrl { url: String::from("127.0.0.1:8002") }));
+        assert_eq!(table.add("http://127.0.0.1:8000/"),
+            Err(PeerError::OwnUrl { url: String::from("http://127.0.0.1:8000/") }));
+        assert_eq!(table.available(&Timestamp::new(Duration::from_secs(0))), vec!["http://127.0.0.1:8001/"]);
+
+        assert!(table.remove("http://127.0.0.1:8001"));
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn failures_back_off_then_evict() {
+        let mut table = PeerTable::default();
+        table.add("http://127.0.0.1:8001").unwrap();
+        let url = "http://127.0.0.1:8001/";
+        let at = |secs| Timestamp::new(Duration::from_secs(secs));
+
+        table.record_failure(url, &at(100));
+        assert!(table.available(&at(100 + BACKOFF - 1)).is_empty());
+        assert_eq!(table.available(&at(100 + BACKOFF)).len(), 1);
+
+        table.record_success(url, &at(200));
+        assert_eq!(table.peers()[0].last_seen, Some(at(200)));
+        assert_eq!(table.peers()[0].failures, 0);
+
+        for _ in 0..MAX_FAILURES {
+            table.record_failure(url, &at(300));
+        }
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = env::temp_dir().join("blockchain-peers-save-and-load.json");
+        let _ = fs::remove_file(&path);
+
+        let mut table = PeerTable::load(&path).unwrap();
+        table.add("http://127.0.0.1:8001").unwrap();
+        table.save().unwrap();
+        assert_eq!(PeerTable::load(&path).unwrap().peers(), table.peers());
+    }
+}
@@ -0,0 +1,288 @@
+//! # Proof of Work
+//!
+//! Contains the proof-of-work rules shared by the example ledgers.
+
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use objecthash;
+
+use ledger::Block;
+use ledger::util::Hash;
+
+/// Number of leading zero bits required by default from the proofs of the first blocks.
+pub const INITIAL_DIFFICULTY: u32 = 16;
+/// Lowest difficulty a retarget can fall to.
+pub const MIN_DIFFICULTY: u32 = 1;
+/// Highest difficulty a retarget can climb to.
+pub const MAX_DIFFICULTY: u32 = 255;
+/// Number of blocks between two difficulty retargets.
+pub const RETARGET_INTERVAL: usize = 10;
+/// Desired spacing between two consecutive blocks, in seconds.
+pub const TARGET_BLOCK_TIME: u64 = 10;
+/// Number of 64-bit limbs in `Work`, enough to add up blocks of `MAX_DIFFICULTY`.
+const WORK_LIMBS: usize = 5;
+
+/// Expected number of hashes behind a chain, i.e. the sum of 2^difficulty over its blocks.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+pub struct Work([u64; WORK_LIMBS]);
+
+impl Work {
+    /// Adds the work of a block of `difficulty`.
+    pub fn add_difficulty(&mut self, difficulty: u32) {
+        let mut limb = WORK_LIMBS - 1 - difficulty as usize / 64;
+        let mut carry: u64 = 1 << (difficulty % 64);
+        loop {
+            let (sum, overflow) = self.0[limb].overflowing_add(carry);
+            self.0[limb] = sum;
+            if ! overflow || limb == 0 {
+                break;
+            }
+            limb -= 1;
+            carry = 1;
+        }
+    }
+}
+
+impl fmt::Display for Work {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0.iter().position(|limb| *limb != 0) {
+            Some(first) => {
+                write!(f, "{:x}", self.0[first])?;
+                self.0[first + 1..].iter().try_for_each(|limb| write!(f, "{:016x}", limb))
+            },
+            None => write!(f, "0")
+        }
+    }
+}
+
+/// Sums up the work of every block of `chain`.
+pub fn chain_work<B: Block>(chain: &[B]) -> Work {
+    let mut work = Work::default();
+    for block in chain {
+        work.add_difficulty(block.difficulty());
+    }
+    work
+}
+
+/// Checks whether `candidate` should replace `current`, given the hashes of their tips.
+///
+/// The chain with more accumulated work wins. Ties go to the lowest tip hash, so that
+/// every node makes the same choice.
+pub fn is_better_chain<B: Block>(candidate: &[B], candidate_tip: &Hash, current: &[B], current_tip: &Hash) -> bool {
+    let (candidate_work, current_work) = (chain_work(candidate), chain_work(current));
+    candidate_work > current_work || (candidate_work == current_work && candidate_tip < current_tip)
+}
+
+/// Computes the difficulty required from the block that follows `chain`, which starts
+/// at `initial`.
+///
+/// Every `RETARGET_INTERVAL` blocks, the time spent on the last interval is compared to
+/// the expected one. The difficulty goes up by one bit when the blocks came in more than
+/// twice as fast as expected, and down by one bit when they came in more than twice as slow.
+pub fn next_difficulty<B: Block>(chain: &[B], initial: u32) -> u32 {
+    let last_block = match chain.last() {
+        Some(last_block) => last_block,
+        None => return initial
+    };
+    if chain.len() % RETARGET_INTERVAL != 0 {
+        return last_block.difficulty();
+    }
+
+    let first_block = &chain[chain.len() - RETARGET_INTERVAL];
+    let actual = last_block.timestamp().0.checked_sub(first_block.timestamp().0)
+        .unwrap_or(Duration::new(0, 0));
+    let expected = Duration::from_secs(TARGET_BLOCK_TIME * (RETARGET_INTERVAL as u64 - 1));
+
+    if actual < expected / 2 {
+        (last_block.difficulty() + 1).min(MAX_DIFFICULTY)
+    } else if actual > expected * 2 {
+        last_block.difficulty().saturating_sub(1).max(MIN_DIFFICULTY)
+    } else {
+        last_block.difficulty()
+    }
+}
+
+/// Checks that hashing `proof` together with `digest`, which the proof commits to, yields
+/// at least `difficulty` leading zero bits.
+pub fn is_valid_proof(digest: &Hash, proof: usize, difficulty: u32) -> bool {
+    let s = format!("{}{}", digest, proof);
+    leading_zero_bits(objecthash::digest(&s).as_ref()) >= difficulty
+}
+
+/// Finds the lowest valid proof on `digest` by trying every proof in turn.
+pub fn find_proof(digest: &Hash, difficulty: u32) -> usize {
+    search(digest, difficulty, Search::default(), Arc::new(AtomicBool::new(false)))
+        .expect("Proof search is never aborted")
+        .proof
+}
+
+/// How to search for a proof.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Search {
+    /// Number of threads splitting the proofs between them.
+    pub threads: usize,
+    /// Whether to keep searching until the lowest valid proof is known, which makes the
+    /// result independent of thread scheduling. Otherwise, the first valid proof wins.
+    pub lowest: bool
+}
+
+impl Default for Search {
+    fn default() -> Search {
+        Search { threads: 1, lowest: true }
+    }
+}
+
+/// Proof found by a search, and how long it took to find it.
+#[derive(Debug, Clone)]
+pub struct Found {
+    pub proof: usize,
+    /// Number of proofs tried by all threads together.
+    pub hashes: usize,
+    pub elapsed: Duration
+}
+
+impl Found {
+    /// Gets the number of proofs tried per second.
+    pub fn hash_rate(&self) -> f64 {
+        let secs = self.elapsed.as_secs() as f64 + self.elapsed.subsec_nanos() as f64 / 1e9;
+        if secs > 0.0 { self.hashes as f64 / secs } else { 0.0 }
+    }
+}
+
+/// Searches for a valid proof on `digest` on `search.threads` threads, thread `i` trying the proofs
+/// `i`, `i + threads`, `i + 2 * threads` and so on.
+///
+/// Every thread stops once a proof is found, unless the lowest proof is wanted, in which
+/// case threads stop once they pass the lowest proof found so far. Gives up once `abort`
+/// is set.
+pub fn search(digest: &Hash, difficulty: u32, search: Search, abort: Arc<AtomicBool>) -> Option<Found> {
+    let start = Instant::now();
+    let threads = search.threads.max(1);
+    let best = Arc::new(AtomicUsize::new(usize::max_value()));
+    let hashes = Arc::new(AtomicUsize::new(0));
+
+    let workers: Vec<_> = (0..threads).map(|first| {
+        let digest = digest.clone();
+        let (best, hashes, abort) = (best.clone(), hashes.clone(), abort.clone());
+        thread::spawn(move || {
+            let mut proof = first;
+            let mut tried = 0;
+            loop {
+                let bound = best.load(Ordering::SeqCst);
+                if abort.load(Ordering::Relaxed) || proof >= bound || (! search.lowest && bound != usize::max_value()) {
+                    break;
+                }
+                tried += 1;
+                if is_valid_proof(&digest, proof, difficulty) {
+                    lower(&best, proof);
+                    break;
+                }
+                proof = match proof.checked_add(threads) {
+                    Some(proof) => proof,
+                    None => break
+                };
+            }
+            hashes.fetch_add(tried, Ordering::Relaxed);
+        })
+    }).collect();
+    for worker in workers {
+        worker.join().expect("Proof search thread panicked");
+    }
+
+    let proof = best.load(Ordering::SeqCst);
+    if abort.load(Ordering::Relaxed) || proof == usize::max_value() {
+        return None;
+    }
+    Some(Found { proof, hashes: hashes.load(Ordering::Relaxed), elapsed: start.elapsed() })
+}
+
+/// Lowers `best` to `proof`, unless another thread found a lower proof already.
+fn lower(best: &AtomicUsize, proof: usize) {
+    let mut current = best.load(Ordering::SeqCst);
+    while proof < current {
+        match best.compare_exchange(current, proof, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => break,
+            Err(actual) => current = actual
+        }
+    }
+}
+
+/// Counts the leading zero bits of a hash.
+pub fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in bytes {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_proof_respects_difficulty() {
+        let proof = find_proof(&Hash::default(), 8);
+        assert!(is_valid_proof(&Hash::default(), proof, 8));
+
+        let s = format!("{}{}", "0".repeat(64), proof);
+        let bits = leading_zero_bits(objecthash::digest(&s).as_ref());
+        assert!(! is_valid_proof(&Hash::default(), proof, bits + 1));
+    }
+
+    #[test]
+    fn search_gives_up_on_abort() {
+        let search = Search { threads: 2, lowest: false };
+        assert!(super::search(&Hash::default(), MAX_DIFFICULTY, search, Arc::new(AtomicBool::new(true))).is_none());
+    }
+
+    #[test]
+    fn search_finds_lowest_proof_on_every_thread_count() {
+        let ones = Hash::from_slice(&[1; 32]).unwrap();
+        let lowest = find_proof(&ones, 10);
+        for threads in 1..5 {
+            let search = Search { threads, lowest: true };
+            let found = super::search(&ones, 10, search, Arc::new(AtomicBool::new(false))).unwrap();
+            assert_eq!(found.proof, lowest);
+            assert!(found.hashes > lowest);
+        }
+    }
+
+    #[test]
+    fn search_finds_any_valid_proof() {
+        let ones = Hash::from_slice(&[1; 32]).unwrap();
+        let search = Search { threads: 4, lowest: false };
+        let found = super::search(&ones, 10, search, Arc::new(AtomicBool::new(false))).unwrap();
+        assert!(is_valid_proof(&ones, found.proof, 10));
+        assert!(found.hash_rate() >= 0.0);
+    }
+
+    #[test]
+    fn work_adds_up_with_carry() {
+        let mut work = Work::default();
+        work.add_difficulty(0);
+        work.add_difficulty(4);
+        assert_eq!(work.to_string(), "11");
+
+        let mut work = Work::default();
+        work.add_difficulty(63);
+        work.add_difficulty(63);
+        assert_eq!(work.to_string(), "10000000000000000");
+        work.add_difficulty(MAX_DIFFICULTY);
+        assert!(work > Work([0, 0, 0, 0, u64::max_value()]));
+    }
+
+    #[test]
+    fn leading_zero_bits_counts_across_bytes() {
+        assert_eq!(leading_zero_bits(&[0, 0, 0xff]), 16);
+        assert_eq!(leading_zero_bits(&[0, 0x10, 0]), 11);
+        assert_eq!(leading_zero_bits(&[0, 0]), 16);
+    }
+}
@@ -0,0 +1,391 @@
+//! # Script
+//!
+//! Runs the small stack-based scripts that lock funds until some conditions hold. A script
+//! can check signatures, multi-signature thresholds, and time locks on the index or the
+//! timestamp of the block that spends the funds.
+//!
+//! Scripts have no loops, and every operation costs steps out of a fixed budget, signature
+//! checks costing one step per signature tried. A script running out of steps fails, so
+//! that no script can hold up block validation.
+//!
+//! A script succeeds when it ends with `true` alone on the stack. For instance, funds that
+//! Alice can take at any time, and Bob from block 100 on, are locked by:
+//!
+//! ```json
+//! [
+//!   { "push_address": "<alice>" }, "check_sig",
+//!   { "push_address": "<bob>" }, "check_sig",
+//!   { "push_number": 100 }, "check_index", "and",
+//!   "or"
+//! ]
+//! ```
+
+use objecthash::{ObjectHash, ObjectHasher};
+
+use ledger::codec::{Decode, Encode, Reader};
+use ledger::crypto::{Address, Signature};
+use ledger::error::{DecodeError, ScriptError};
+use ledger::util::{Hash, Timestamp};
+
+/// Steps a script may take.
+pub const MAX_STEPS: usize = 1000;
+/// Highest number of operations in a script.
+pub const MAX_SCRIPT_LEN: usize = 100;
+/// Highest number of keys a multi-signature check takes.
+pub const MAX_KEYS: u64 = 20;
+
+/// Operation of a script.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Op {
+    PushAddress(Address),
+    PushNumber(u64),
+    /// Pops an address, and pushes whether one of the witness signatures is by it.
+    CheckSig,
+    /// Pops a number of keys `n`, then `n` addresses, then a threshold, and pushes whether
+    /// at least threshold of the addresses signed the witness.
+    CheckMultiSig,
+    /// Pops a block index, and pushes whether the spending block comes at or after it.
+    CheckIndex,
+    /// Pops a time in seconds since the epoch, and pushes whether the spending block is
+    /// timestamped at or after it.
+    CheckTime,
+    /// Pops a boolean, and fails the script unless it is `true`.
+    Verify,
+    Not,
+    And,
+    Or
+}
+
+/// Script locking funds.
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+pub struct Script(pub Vec<Op>);
+
+impl Script {
+    /// Locks funds to the owner of `address`.
+    pub fn pay_to(address: Address) -> Script {
+        Script(vec![Op::PushAddress(address), Op::CheckSig])
+    }
+
+    /// Locks funds to any `threshold` of the owners of `addresses`.
+    pub fn multisig(threshold: u64, addresses: &[Address]) -> Script {
+        let mut ops = vec![Op::PushNumber(threshold)];
+        ops.extend(addresses.iter().cloned().map(Op::PushAddress));
+        ops.push(Op::PushNumber(addresses.len() as u64));
+        ops.push(Op::CheckMultiSig);
+        Script(ops)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Runs the script for the spending transaction with `digest`, signed by `witness`, in
+    /// the block at `clock`.
+    pub fn eval(&self, digest: &Hash, witness: &[Signature], clock: &Clock) -> Result<(), ScriptError> {
+        let mut machine = Machine { stack: Vec::new(), steps: 0, digest, witness, clock };
+        for op in &self.0 {
+            machine.step(op)?;
+        }
+        match machine.stack.pop() {
+            Some(Value::Bool(true)) if machine.stack.is_empty() => Ok(()),
+            _ => Err(ScriptError::Rejected)
+        }
+    }
+}
+
+/// Block that spends locked funds, which time locks compare against.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Clock {
+    pub index: usize,
+    pub timestamp: Timestamp
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Value {
+    Bool(bool),
+    Number(u64),
+    Address(Address)
+}
+
+struct Machine<'a> {
+    stack: Vec<Value>,
+    steps: usize,
+    digest: &'a Hash,
+    witness: &'a [Signature],
+    clock: &'a Clock
+}
+
+impl<'a> Machine<'a> {
+    fn step(&mut self, op: &Op) -> Result<(), ScriptError> {
+        self.spend(1)?;
+        match *op {
+            Op::PushAddress(ref address) => self.stack.push(Value::Address(address.clone())),
+            Op::PushNumber(number) => self.stack.push(Value::Number(number)),
+            Op::CheckSig => {
+                let address = self.pop_address()?;
+                self.spend(self.witness.len())?;
+                let signed = self.is_signed_by(&address);
+                self.stack.push(Value::Bool(signed));
+            },
+            Op::CheckMultiSig => {
+                let n = self.pop_number()?;
+                if n > MAX_KEYS {
+                    return Err(ScriptError::TooManyKeys);
+                }
+                self.spend(n as usize * self.witness.len())?;
+                let mut addresses = Vec::new();
+                for _ in 0..n {
+                    addresses.push(self.pop_address()?);
+                }
+                let threshold = self.pop_number()?;
+                addresses.sort();
+                addresses.dedup();
+                let signers = addresses.iter().filter(|address| self.is_signed_by(address)).count();
+                self.stack.push(Value::Bool(signers as u64 >= threshold));
+            },
+            Op::CheckIndex => {
+                let index = self.pop_number()?;
+                self.stack.push(Value::Bool(self.clock.index as u64 >= index));
+            },
+            Op::CheckTime => {
+                let secs = self.pop_number()?;
+                self.stack.push(Value::Bool(self.clock.timestamp.0.as_secs() >= secs));
+            },
+            Op::Verify => {
+                if ! self.pop_bool()? {
+                    return Err(ScriptError::VerifyFailed);
+                }
+            },
+            Op::Not => {
+                let value = self.pop_bool()?;
+                self.stack.push(Value::Bool(! value));
+            },
+            Op::And | Op::Or => {
+                let (right, left) = (self.pop_bool()?, self.pop_bool()?);
+                let value = if *op == Op::And { left && right } else { left || right };
+                self.stack.push(Value::Bool(value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Charges `steps`, failing once the budget is spent.
+    fn spend(&mut self, steps: usize) -> Result<(), ScriptError> {
+        self.steps += steps;
+        if self.steps > MAX_STEPS {
+            return Err(ScriptError::OutOfSteps);
+        }
+        Ok(())
+    }
+
+    fn is_signed_by(&self, address: &Address) -> bool {
+        self.witness.iter().any(|signature| address.verify(self.digest.as_bytes(), signature))
+    }
+
+    fn pop(&mut self) -> Result<Value, ScriptError> {
+        self.stack.pop().ok_or(ScriptError::StackUnderflow)
+    }
+
+    fn pop_bool(&mut self) -> Result<bool, ScriptError> {
+        match self.pop()? {
+            Value::Bool(value) => Ok(value),
+            _ => Err(ScriptError::TypeMismatch)
+        }
+    }
+
+    fn pop_number(&mut self) -> Result<u64, ScriptError> {
+        match self.pop()? {
+            Value::Number(number) => Ok(number),
+            _ => Err(ScriptError::TypeMismatch)
+        }
+    }
+
+    fn pop_address(&mut self) -> Result<Address, ScriptError> {
+        match self.pop()? {
+            Value::Address(address) => Ok(address),
+            _ => Err(ScriptError::TypeMismatch)
+        }
+    }
+}
+
+/// Makes Op hashable.
+impl ObjectHash for Op {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        match *self {
+            Op::PushAddress(ref address) => {
+                objecthash_struct!(hasher, "push_address" => address)
+            },
+            Op::PushNumber(ref number) => {
+                objecthash_struct!(hasher, "push_number" => number)
+            },
+            _ => String::from(op_name(self)).objecthash(hasher)
+        }
+    }
+}
+
+/// Makes Script hashable.
+impl ObjectHash for Script {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        self.0.objecthash(hasher)
+    }
+}
+
+/// Names an operation without operand, as in JSON.
+fn op_name(op: &Op) -> &'static str {
+    match *op {
+        Op::PushAddress(_) => "push_address",
+        Op::PushNumber(_) => "push_number",
+        Op::CheckSig => "check_sig",
+        Op::CheckMultiSig => "check_multi_sig",
+        Op::CheckIndex => "check_index",
+        Op::CheckTime => "check_time",
+        Op::Verify => "verify",
+        Op::Not => "not",
+        Op::And => "and",
+        Op::Or => "or"
+    }
+}
+
+/// Encodes an operation as a tag, in the order of the variants, followed by its operand.
+impl Encode for Op {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            Op::PushAddress(ref address) => {
+                out.push(0);
+                address.encode(out);
+            },
+            Op::PushNumber(number) => {
+                out.push(1);
+                number.encode(out);
+            },
+            Op::CheckSig => out.push(2),
+            Op::CheckMultiSig => out.push(3),
+            Op::CheckIndex => out.push(4),
+            Op::CheckTime => out.push(5),
+            Op::Verify => out.push(6),
+            Op::Not => out.push(7),
+            Op::And => out.push(8),
+            Op::Or => out.push(9)
+        }
+    }
+}
+
+impl Decode for Op {
+    fn decode(reader: &mut Reader) -> Result<Op, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(Op::PushAddress(Address::decode(reader)?)),
+            1 => Ok(Op::PushNumber(u64::decode(reader)?)),
+            2 => Ok(Op::CheckSig),
+            3 => Ok(Op::CheckMultiSig),
+            4 => Ok(Op::CheckIndex),
+            5 => Ok(Op::CheckTime),
+            6 => Ok(Op::Verify),
+            7 => Ok(Op::Not),
+            8 => Ok(Op::And),
+            9 => Ok(Op::Or),
+            tag => Err(DecodeError::UnknownTag { tag })
+        }
+    }
+}
+
+impl Encode for Script {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+    }
+}
+
+impl Decode for Script {
+    fn decode(reader: &mut Reader) -> Result<Script, DecodeError> {
+        Vec::decode(reader).map(Script)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use serde_json;
+    use ledger::codec;
+    use ledger::crypto::Keypair;
+    use super::*;
+
+    #[test]
+    fn pay_to_checks_signature() {
+        let (owner, other) = (Keypair::generate(), Keypair::generate());
+        let script = Script::pay_to(owner.address());
+        let digest = Hash::digest(&String::from("spend"));
+
+        assert_eq!(script.eval(&digest, &[owner.sign(digest.as_bytes())], &clock(1, 0)), Ok(()));
+        assert_eq!(script.eval(&digest, &[other.sign(digest.as_bytes())], &clock(1, 0)), Err(ScriptError::Rejected));
+        assert_eq!(script.eval(&digest, &[], &clock(1, 0)), Err(ScriptError::Rejected));
+    }
+
+    #[test]
+    fn multisig_counts_distinct_signers() {
+        let keypairs: Vec<Keypair> = (0..3).map(|_| Keypair::generate()).collect();
+        let addresses: Vec<Address> = keypairs.iter().map(|keypair| keypair.address()).collect();
+        let script = Script::multisig(2, &addresses);
+        let digest = Hash::digest(&String::from("spend"));
+        let sign = |i: usize| keypairs[i].sign(digest.as_bytes());
+
+        assert_eq!(script.eval(&digest, &[sign(0), sign(2)], &clock(1, 0)), Ok(()));
+        assert_eq!(script.eval(&digest, &[sign(1), sign(1)], &clock(1, 0)), Err(ScriptError::Rejected));
+
+        let repeated = Script::multisig(2, &[addresses[0].clone(), addresses[0].clone()]);
+        assert_eq!(repeated.eval(&digest, &[sign(0)], &clock(1, 0)), Err(ScriptError::Rejected));
+    }
+
+    #[test]
+    fn time_locks_compare_with_spending_block() {
+        let by_index = Script(vec![Op::PushNumber(10), Op::CheckIndex]);
+        let by_time = Script(vec![Op::PushNumber(1000), Op::CheckTime]);
+        let digest = Hash::default();
+
+        assert_eq!(by_index.eval(&digest, &[], &clock(9, 0)), Err(ScriptError::Rejected));
+        assert_eq!(by_index.eval(&digest, &[], &clock(10, 0)), Ok(()));
+        assert_eq!(by_time.eval(&digest, &[], &clock(1, 999)), Err(ScriptError::Rejected));
+        assert_eq!(by_time.eval(&digest, &[], &clock(1, 1000)), Ok(()));
+    }
+
+    #[test]
+    fn malformed_scripts_fail() {
+        let digest = Hash::default();
+        let verify = Script(vec![Op::PushNumber(10), Op::CheckIndex, Op::Verify]);
+        assert_eq!(verify.eval(&digest, &[], &clock(9, 0)), Err(ScriptError::VerifyFailed));
+        assert_eq!(verify.eval(&digest, &[], &clock(10, 0)), Err(ScriptError::Rejected));
+        assert_eq!(Script(vec![Op::PushNumber(0), Op::Not]).eval(&digest, &[], &clock(1, 0)),
+            Err(ScriptError::TypeMismatch));
+        assert_eq!(Script(vec![Op::And]).eval(&digest, &[], &clock(1, 0)), Err(ScriptError::StackUnderflow));
+        assert_eq!(Script(vec![Op::PushNumber(MAX_KEYS + 1), Op::CheckMultiSig]).eval(&digest, &[], &clock(1, 0)),
+            Err(ScriptError::TooManyKeys));
+    }
+
+    #[test]
+    fn signature_checks_run_out_of_steps() {
+        let keypair = Keypair::generate();
+        let digest = Hash::default();
+        let witness: Vec<Signature> = (0..MAX_KEYS).map(|_| keypair.sign(digest.as_bytes())).collect();
+        let addresses = vec![keypair.address(); MAX_KEYS as usize];
+        let mut ops = Vec::new();
+        for _ in 0..3 {
+            ops.extend(Script::multisig(1, &addresses).0);
+            ops.push(Op::Verify);
+        }
+        let script = Script(ops);
+
+        assert_eq!(script.eval(&digest, &witness, &clock(1, 0)), Err(ScriptError::OutOfSteps));
+        let json = serde_json::to_string(&script).unwrap();
+        assert_eq!(serde_json::from_str::<Script>(&json).unwrap(), script);
+        assert_eq!(codec::from_bytes::<Script>(&codec::to_bytes(&script)), Ok(script));
+    }
+
+    fn clock(index: usize, secs: u64) -> Clock {
+        Clock { index, timestamp: Timestamp::new(Duration::from_secs(secs)) }
+    }
+}
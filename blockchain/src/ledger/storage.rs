@@ -0,0 +1,331 @@
+//! # Storage
+//!
+//! Defines how a ledger persists its blocks and its latest state snapshot, and an
+//! append-only file backend.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+/// Size of a record header: payload length, checksum of the length, then checksum of the
+/// length and payload together.
+const HEADER_LEN: usize = 12;
+
+/// Persists the blocks of a ledger.
+pub trait Storage<B>: fmt::Debug {
+    /// Loads every stored block, in chain order.
+    fn load(&mut self) -> io::Result<Vec<B>>;
+    /// Appends a newly added block.
+    fn append(&mut self, block: &B) -> io::Result<()>;
+    /// Replaces the whole stored chain, e.g. after resolving conflicts.
+    fn replace(&mut self, chain: &[B]) -> io::Result<()>;
+    /// Loads the encoded state snapshot, if one is stored.
+    fn load_snapshot(&mut self) -> io::Result<Option<Vec<u8>>>;
+    /// Replaces the stored state snapshot with `snapshot`, already encoded.
+    fn save_snapshot(&mut self, snapshot: &[u8]) -> io::Result<()>;
+}
+
+/// Stores blocks as length-prefixed, checksummed JSON records in a single file.
+///
+/// A torn record at the end of the file (e.g. left by a crash in the middle of a write)
+/// is truncated away on load. Corruption anywhere else is reported as an error, as is an
+/// intact record that does not hold a block. The
+/// length of a record has a checksum of its own, so that a corrupted length is not taken
+/// for a record torn at the end of the file.
+///
+/// The state snapshot is a single record in a file next to it, with the `snapshot`
+/// extension.
+#[derive(Debug)]
+pub struct FileStorage {
+    path: PathBuf
+}
+
+impl FileStorage {
+    pub fn new<P: Into<PathBuf>>(path: P) -> FileStorage {
+        FileStorage { path: path.into() }
+    }
+
+    fn encode<B: Serialize>(block: &B) -> io::Result<Vec<u8>> {
+        let payload = serde_json::to_vec(block)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(record(&payload))
+    }
+
+    fn snapshot_path(&self) -> PathBuf {
+        self.path.with_extension("snapshot")
+    }
+
+    /// Writes `bytes` to a temporary file, then moves it over `path` at once.
+    fn write_atomically(path: &PathBuf, bytes: &[u8]) -> io::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(bytes)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, path)
+    }
+}
+
+impl<B: Serialize + DeserializeOwned> Storage<B> for FileStorage {
+    fn load(&mut self) -> io::Result<Vec<B>> {
+        let mut bytes = Vec::new();
+        match File::open(&self.path) {
+            Ok(mut file) => { file.read_to_end(&mut bytes)?; },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e)
+        }
+
+        let mut blocks = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            match read_record(&bytes[offset..]) {
+                Record::Intact { payload, len } => {
+                    let block = serde_json::from_slice(payload).map_err(|e| {
+                        io::Error::new(io::ErrorKind::InvalidData, format!("Invalid block at offset {}: {}", offset, e))
+                    })?;
+                    blocks.push(block);
+                    offset += len;
+                },
+                Record::Corrupted { len: Some(len) } if offset + len == bytes.len() => break,
+                Record::Corrupted { .. } => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                        format!("Corrupted record at offset {}", offset)));
+                },
+                Record::Truncated => break
+            }
+        }
+
+        if offset < bytes.len() {
+            let file = OpenOptions::new().write(true).open(&self.path)?;
+            file.set_len(offset as u64)?;
+            file.sync_all()?;
+        }
+        Ok(blocks)
+    }
+
+    fn append(&mut self, block: &B) -> io::Result<()> {
+        let record = Self::encode(block)?;
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(&record)?;
+        file.sync_data()
+    }
+
+    fn replace(&mut self, chain: &[B]) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        for block in chain {
+            bytes.extend(Self::encode(block)?);
+        }
+        Self::write_atomically(&self.path, &bytes)
+    }
+
+    fn load_snapshot(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let bytes = match fs::read(self.snapshot_path()) {
+            Ok(bytes) => bytes,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e)
+        };
+        match read_record(&bytes) {
+            Record::Intact { payload, len } if len == bytes.len() => Ok(Some(payload.to_vec())),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Corrupted snapshot"))
+        }
+    }
+
+    fn save_snapshot(&mut self, snapshot: &[u8]) -> io::Result<()> {
+        Self::write_atomically(&self.snapshot_path(), &record(snapshot))
+    }
+}
+
+/// Prefixes `payload` with its length and checksums.
+fn record(payload: &[u8]) -> Vec<u8> {
+    let len = u32_to_bytes(payload.len() as u32);
+    let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+    record.extend_from_slice(&len);
+    record.extend_from_slice(&u32_to_bytes(checksum(&[&len])));
+    record.extend_from_slice(&u32_to_bytes(checksum(&[&len, payload])));
+    record.extend_from_slice(payload);
+    record
+}
+
+/// State of the record at the start of some bytes.
+#[derive(Debug, PartialEq)]
+enum Record<'a> {
+    /// The record is whole and matches its checksums. `len` counts its header too.
+    Intact { payload: &'a [u8], len: usize },
+    /// The bytes end before the record does, as when a write was cut short.
+    Truncated,
+    /// A checksum does not match. `len` is known when the length matches its checksum.
+    Corrupted { len: Option<usize> }
+}
+
+fn read_record(bytes: &[u8]) -> Record {
+    if bytes.len() < HEADER_LEN {
+        return Record::Truncated;
+    }
+    let len_bytes = &bytes[..4];
+    if u32_from_bytes(&bytes[4..8]) != checksum(&[len_bytes]) {
+        return Record::Corrupted { len: None };
+    }
+    let len = u32_from_bytes(len_bytes) as usize;
+    if bytes.len() - HEADER_LEN < len {
+        return Record::Truncated;
+    }
+
+    let payload = &bytes[HEADER_LEN..HEADER_LEN + len];
+    if u32_from_bytes(&bytes[8..HEADER_LEN]) != checksum(&[len_bytes, payload]) {
+        return Record::Corrupted { len: Some(HEADER_LEN + len) };
+    }
+    Record::Intact { payload, len: HEADER_LEN + len }
+}
+
+fn u32_to_bytes(n: u32) -> [u8; 4] {
+    [n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8]
+}
+
+fn u32_from_bytes(bytes: &[u8]) -> u32 {
+    bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+/// Computes the 32-bit FNV-1a checksum of `parts`, one after the other.
+fn checksum(parts: &[&[u8]]) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in parts.iter().flat_map(|part| part.iter()) {
+        hash ^= *byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use super::*;
+
+    #[test]
+    fn append_and_load() {
+        let mut storage = create_storage("append_and_load");
+        storage.append(&String::from("first")).unwrap();
+        storage.append(&String::from("second")).unwrap();
+
+        let blocks: Vec<String> = storage.load().unwrap();
+        assert_eq!(blocks, vec![String::from("first"), String::from("second")]);
+    }
+
+    #[test]
+    fn load_missing_file() {
+        let mut storage = create_storage("load_missing_file");
+        let blocks: Vec<String> = storage.load().unwrap();
+        assert!(blocks.is_empty());
+    }
+
+    #[test]
+    fn load_truncates_torn_record() {
+        let mut storage = create_storage("load_truncates_torn_record");
+        storage.append(&String::from("first")).unwrap();
+        let good_len = fs::metadata(&storage.path).unwrap().len();
+        storage.append(&String::from("second")).unwrap();
+
+        let complete = fs::read(&storage.path).unwrap();
+
+        for &torn_len in &[4, HEADER_LEN + 2] {
+            fs::write(&storage.path, &complete[..good_len as usize + torn_len]).unwrap();
+            let blocks: Vec<String> = storage.load().unwrap();
+            assert_eq!(blocks, vec![String::from("first")]);
+            assert_eq!(fs::metadata(&storage.path).unwrap().len(), good_len);
+        }
+
+        storage.append(&String::from("third")).unwrap();
+        let blocks: Vec<String> = storage.load().unwrap();
+        assert_eq!(blocks, vec![String::from("first"), String::from("third")]);
+    }
+
+    #[test]
+    fn load_rejects_corrupted_record() {
+        let mut storage = create_storage("load_rejects_corrupted_record");
+        storage.append(&String::from("first")).unwrap();
+        storage.append(&String::from("second")).unwrap();
+
+        let mut bytes = fs::read(&storage.path).unwrap();
+        bytes[HEADER_LEN] ^= 0xff;
+        fs::write(&storage.path, bytes).unwrap();
+
+        let result: io::Result<Vec<String>> = storage.load();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn load_rejects_corrupted_length_before_last_record() {
+        let mut storage = create_storage("load_rejects_corrupted_length_before_last_record");
+        storage.append(&String::from("first")).unwrap();
+        storage.append(&String::from("second")).unwrap();
+        storage.append(&String::from("third")).unwrap();
+        let original = fs::read(&storage.path).unwrap();
+        let second = match read_record(&original) {
+            Record::Intact { len, .. } => len,
+            record => panic!("unexpected record: {:?}", record)
+        };
+
+        for &len in &[1, 0xffff] {
+            let mut bytes = original.clone();
+            bytes[second..second + 4].copy_from_slice(&u32_to_bytes(len));
+            fs::write(&storage.path, &bytes).unwrap();
+
+            let result: io::Result<Vec<String>> = storage.load();
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+            assert_eq!(fs::read(&storage.path).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn load_rejects_intact_record_of_another_format() {
+        let mut storage = create_storage("load_rejects_intact_record_of_another_format");
+        storage.append(&42).unwrap();
+        let only = fs::read(&storage.path).unwrap();
+        let result: io::Result<Vec<String>> = storage.load();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read(&storage.path).unwrap(), only);
+
+        storage.replace(&[String::from("first")]).unwrap();
+        storage.append(&42).unwrap();
+        let last = fs::read(&storage.path).unwrap();
+        let result: io::Result<Vec<String>> = storage.load();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read(&storage.path).unwrap(), last);
+    }
+
+    #[test]
+    fn replace_chain() {
+        let mut storage = create_storage("replace_chain");
+        storage.append(&String::from("first")).unwrap();
+        storage.replace(&[String::from("other"), String::from("chain")]).unwrap();
+
+        let blocks: Vec<String> = storage.load().unwrap();
+        assert_eq!(blocks, vec![String::from("other"), String::from("chain")]);
+    }
+
+    #[test]
+    fn save_and_load_snapshot() {
+        let mut storage = create_storage("save_and_load_snapshot");
+        assert_eq!(Storage::<String>::load_snapshot(&mut storage).unwrap(), None);
+        Storage::<String>::save_snapshot(&mut storage, b"first").unwrap();
+        Storage::<String>::save_snapshot(&mut storage, b"second").unwrap();
+        assert_eq!(Storage::<String>::load_snapshot(&mut storage).unwrap(), Some(b"second".to_vec()));
+
+        let mut bytes = fs::read(storage.snapshot_path()).unwrap();
+        bytes[HEADER_LEN] ^= 0xff;
+        fs::write(storage.snapshot_path(), bytes).unwrap();
+        assert_eq!(Storage::<String>::load_snapshot(&mut storage).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    fn create_storage(name: &str) -> FileStorage {
+        let path = env::temp_dir().join(format!("blockchain-storage-{}.dat", name));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("snapshot"));
+        FileStorage::new(path)
+    }
+}
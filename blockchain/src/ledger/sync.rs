@@ -0,0 +1,279 @@
+//! # Sync
+//!
+//! Catches up with the chain of a peer without downloading all of it. The peer first tells
+//! its tip, then the node looks for the last block both chains share by their headers, and
+//! only fetches the blocks that follow it, page by page, checking each page as it arrives.
+//!
+//! A new node can also start from a snapshot of the state of a peer instead of the first
+//! block: it then only needs the headers of the blocks before the snapshot.
+
+use std::error::Error;
+use std::io::Read;
+
+use reqwest::{self, Response, Url};
+use reqwest::header::Headers;
+use serde::de::DeserializeOwned;
+
+use ledger::codec::{self, Decode};
+use ledger::util::Hash;
+
+/// Highest number of blocks served in a page.
+pub const PAGE_LIMIT: usize = 500;
+
+/// Height and hash of the last block of a chain, along with the hash of its genesis.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Tip {
+    pub height: usize,
+    pub hash: Hash,
+    pub genesis: Hash
+}
+
+/// Chain that can be read from its tip and by ranges of blocks, with or without their
+/// transactions, e.g. the chain of a peer.
+pub trait BlockSource<B> {
+    fn tip(&self) -> Result<Tip, Box<Error>>;
+    /// Gets at most `limit` blocks, starting at height `from`, counting from 1.
+    fn blocks(&self, from: usize, limit: usize) -> Result<Vec<B>, Box<Error>>;
+    /// Gets at most `limit` blocks without their transactions, starting at height `from`,
+    /// counting from 1.
+    fn headers(&self, from: usize, limit: usize) -> Result<Vec<B>, Box<Error>>;
+}
+
+/// Chain that also serves a snapshot of its state, e.g. the chain of a peer.
+pub trait SnapshotSource<B, S>: BlockSource<B> {
+    /// Gets the latest snapshot of the state.
+    fn snapshot(&self) -> Result<S, Box<Error>>;
+}
+
+/// Peer serving `GET tip`, `GET blocks?from=&limit=`, `GET headers?from=&limit=` and
+/// `GET snapshot` relative to its base URL.
+///
+/// Blocks are asked for in the binary encoding, and read as JSON from peers that only
+/// serve JSON.
+#[derive(Debug)]
+pub struct HttpPeer {
+    base: Url
+}
+
+impl HttpPeer {
+    pub fn new(base: &str) -> Result<HttpPeer, Box<Error>> {
+        Ok(HttpPeer { base: Url::parse(base)? })
+    }
+
+    fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, Box<Error>> {
+        let url = self.base.join(path)?;
+        let mut resp = reqwest::get(url.as_str())?;
+        if ! resp.status().is_success() {
+            return Err(From::from(format!("Unexpected response from {}", url)));
+        }
+        Ok(resp.json()?)
+    }
+
+    /// Gets `path`, decoding the binary encoding if the peer answers with it.
+    fn get_encoded<T: DeserializeOwned + Decode>(&self, path: &str) -> Result<T, Box<Error>> {
+        let url = self.base.join(path)?;
+        let mut headers = Headers::new();
+        headers.set_raw("Accept", codec::MEDIA_TYPE);
+        let mut resp = reqwest::Client::new().get(url.as_str()).headers(headers).send()?;
+        if ! resp.status().is_success() {
+            return Err(From::from(format!("Unexpected response from {}", url)));
+        }
+        if ! is_binary(&resp) {
+            return Ok(resp.json()?);
+        }
+        let mut bytes = Vec::new();
+        resp.read_to_end(&mut bytes)?;
+        Ok(codec::from_bytes(&bytes)?)
+    }
+}
+
+impl<B: DeserializeOwned + Decode> BlockSource<B> for HttpPeer {
+    fn tip(&self) -> Result<Tip, Box<Error>> {
+        self.get("tip")
+    }
+
+    fn blocks(&self, from: usize, limit: usize) -> Result<Vec<B>, Box<Error>> {
+        self.get_encoded(&format!("blocks?from={}&limit={}", from, limit))
+    }
+
+    fn headers(&self, from: usize, limit: usize) -> Result<Vec<B>, Box<Error>> {
+        self.get_encoded(&format!("headers?from={}&limit={}", from, limit))
+    }
+}
+
+impl<B: DeserializeOwned + Decode, S: DeserializeOwned + Decode> SnapshotSource<B, S> for HttpPeer {
+    fn snapshot(&self) -> Result<S, Box<Error>> {
+        self.get_encoded("snapshot")
+    }
+}
+
+/// Checks whether `resp` holds the binary encoding.
+fn is_binary(resp: &Response) -> bool {
+    resp.headers().get_raw("Content-Type")
+        .and_then(|raw| raw.one())
+        .is_some_and(|value| value.starts_with(codec::MEDIA_TYPE.as_bytes()))
+}
+
+/// Finds the number of blocks that `chain` shares with the chain of `source`, which is
+/// `height` blocks long.
+///
+/// Blocks are compared by binary search, starting with the common case of a peer that
+/// merely extends `chain`. Only headers are fetched for the search, so `B` has to compare
+/// equal to the same block without its transactions.
+pub fn common_ancestor<B, S>(chain: &[B], height: usize, source: &S) -> Result<usize, Box<Error>>
+    where B: PartialEq, S: BlockSource<B>
+{
+    let (mut shared, mut diverged) = (0, chain.len().min(height));
+    if is_shared(chain, diverged, source)? {
+        return Ok(diverged);
+    }
+    while diverged - shared > 1 {
+        let middle = shared + (diverged - shared) / 2;
+        if is_shared(chain, middle, source)? {
+            shared = middle;
+        } else {
+            diverged = middle;
+        }
+    }
+    Ok(shared)
+}
+
+/// Fetches the blocks of `source` that follow `height`, up to the height of `tip`, handing
+/// every page to `accept` as it arrives.
+///
+/// Gives whether `accept` took every page: the height of `tip` is only a claim of
+/// `source`, so the fetch stops at the first page that `accept` turns down.
+pub fn fetch_after<B, S, A>(source: &S, height: usize, tip: &Tip, accept: A) -> Result<bool, Box<Error>>
+    where S: BlockSource<B>, A: FnMut(Vec<B>) -> bool
+{
+    fetch_pages(height.saturating_add(1), tip.height, |from, limit| source.blocks(from, limit), accept)
+}
+
+/// Fetches the blocks of `source` up to `height`, without their transactions, handing every
+/// page to `accept` like `fetch_after`.
+pub fn fetch_headers<B, S, A>(source: &S, height: usize, accept: A) -> Result<bool, Box<Error>>
+    where S: BlockSource<B>, A: FnMut(Vec<B>) -> bool
+{
+    fetch_pages(1, height, |from, limit| source.headers(from, limit), accept)
+}
+
+/// Fetches the blocks from height `from` up to `to` with `fetch`, page by page, stopping
+/// early at an empty page or at a page that `accept` turns down.
+fn fetch_pages<B, F, A>(mut from: usize, to: usize, fetch: F, mut accept: A) -> Result<bool, Box<Error>>
+    where F: Fn(usize, usize) -> Result<Vec<B>, Box<Error>>, A: FnMut(Vec<B>) -> bool
+{
+    while from <= to {
+        let limit = (to - from).saturating_add(1).min(PAGE_LIMIT);
+        let page = fetch(from, limit)?;
+        if page.is_empty() {
+            break;
+        }
+        if page.len() > limit {
+            return Err(From::from("Source served more blocks than asked for"));
+        }
+        let next = from.checked_add(page.len());
+        if ! accept(page) {
+            return Ok(false);
+        }
+        match next {
+            Some(next) => from = next,
+            None => break
+        }
+    }
+    Ok(true)
+}
+
+/// Checks that the block at `height` is the same in `chain` and in `source`.
+fn is_shared<B: PartialEq, S: BlockSource<B>>(chain: &[B], height: usize, source: &S) -> Result<bool, Box<Error>> {
+    if height == 0 {
+        return Ok(true);
+    }
+    let headers = source.headers(height, 1)?;
+    Ok(headers.first() == chain.get(height - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use super::*;
+
+    #[test]
+    fn common_ancestor_of_extended_chain() {
+        let source = Chain::new((1..100).collect());
+        let chain: Vec<usize> = (1..50).collect();
+        assert_eq!(common_ancestor(&chain, 99, &source).unwrap(), 49);
+        assert_eq!(source.requests.get(), 1);
+    }
+
+    #[test]
+    fn common_ancestor_of_forks() {
+        let source = Chain::new((1..100).collect());
+        for fork in 0..60 {
+            let chain: Vec<usize> = (1..fork + 1).chain(1000..1010).collect();
+            assert_eq!(common_ancestor(&chain, 99, &source).unwrap(), fork);
+        }
+        assert_eq!(common_ancestor(&[1, 2, 3], 0, &source).unwrap(), 0);
+    }
+
+    #[test]
+    fn fetch_after_pages_up_to_tip() {
+        let source = Chain::new((1..2 * PAGE_LIMIT + 10).collect());
+        let tip = Tip { height: 2 * PAGE_LIMIT, hash: Hash::default(), genesis: Hash::default() };
+        let mut blocks = Vec::new();
+        assert!(fetch_after(&source, 5, &tip, |page| { blocks.extend(page); true }).unwrap());
+        assert_eq!(blocks, (6..2 * PAGE_LIMIT + 1).collect::<Vec<usize>>());
+        assert_eq!(source.requests.get(), 2);
+    }
+
+    #[test]
+    fn fetch_after_stops_at_rejected_page() {
+        let source = Chain::new((1..3 * PAGE_LIMIT).collect());
+        let tip = Tip { height: usize::max_value(), hash: Hash::default(), genesis: Hash::default() };
+        let mut pages = 0;
+        assert!(! fetch_after(&source, 0, &tip, |_| { pages += 1; pages < 2 }).unwrap());
+        assert_eq!(source.requests.get(), 2);
+
+        let mut blocks = Vec::new();
+        assert!(fetch_after(&source, usize::max_value(), &tip, |page| { blocks.extend(page); true }).unwrap());
+        assert!(blocks.is_empty());
+        assert!(fetch_after(&source, 0, &tip, |page| { blocks.extend(page); true }).unwrap());
+        assert_eq!(blocks.len(), 3 * PAGE_LIMIT - 1);
+    }
+
+    #[test]
+    fn fetch_headers_pages_up_to_height() {
+        let source = Chain::new((1..2 * PAGE_LIMIT + 10).collect());
+        let mut headers = Vec::new();
+        assert!(fetch_headers(&source, PAGE_LIMIT + 1, |page| { headers.extend(page); true }).unwrap());
+        assert_eq!(headers, (1..PAGE_LIMIT + 2).collect::<Vec<usize>>());
+        assert_eq!(source.requests.get(), 2);
+        assert!(fetch_headers(&source, 0, |_: Vec<usize>| false).unwrap());
+    }
+
+    /// Chain of numbers that counts the requests made to it.
+    struct Chain {
+        blocks: Vec<usize>,
+        requests: Cell<usize>
+    }
+
+    impl Chain {
+        fn new(blocks: Vec<usize>) -> Chain {
+            Chain { blocks, requests: Cell::new(0) }
+        }
+    }
+
+    impl BlockSource<usize> for Chain {
+        fn tip(&self) -> Result<Tip, Box<Error>> {
+            Ok(Tip { height: self.blocks.len(), hash: Hash::default(), genesis: Hash::default() })
+        }
+
+        fn blocks(&self, from: usize, limit: usize) -> Result<Vec<usize>, Box<Error>> {
+            self.requests.set(self.requests.get() + 1);
+            Ok(self.blocks.iter().skip(from - 1).take(limit).cloned().collect())
+        }
+
+        fn headers(&self, from: usize, limit: usize) -> Result<Vec<usize>, Box<Error>> {
+            self.blocks(from, limit)
+        }
+    }
+}
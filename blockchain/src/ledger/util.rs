@@ -2,9 +2,19 @@
 //!
 //! Contains all utility functions.
 
-use std::fmt::Write;
+use std::fmt::{self, Write};
+use std::str::FromStr;
 use std::time::{SystemTime, UNIX_EPOCH, Duration};
 
+use objecthash::{self, ObjectHash, ObjectHasher};
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de;
+
+use ledger::error::HashError;
+
+/// Length of a hash, in bytes.
+pub const HASH_LEN: usize = 32;
+
 /// Performs hex-related manipulations.
 pub struct Hex;
 
@@ -16,16 +26,40 @@ impl Hex {
     /// ```
     /// use blockchain::ledger::util::Hex;
     ///
-    /// let bytes = [0xff, 0x12, 0xa3];
-    /// assert_eq!(&Hex::from_bytes(&bytes)[..], "ff12a3");
+    /// let bytes = [0xff, 0x02, 0xa3];
+    /// assert_eq!(&Hex::from_bytes(&bytes)[..], "ff02a3");
     /// ```
     pub fn from_bytes(bytes: &[u8]) -> String {
         let mut s = String::new();
         for byte in bytes {
-            write!(&mut s, "{:x}", byte).expect("Unable to write byte to string");
+            write!(&mut s, "{:02x}", byte).expect("Unable to write byte to string");
         }
         s
     }
+
+    /// Converts a hex string to bytes, if it is one.
+    ///
+    /// Only lowercase digits are accepted, as `from_bytes` writes them, so that bytes have a
+    /// single hex string.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use blockchain::ledger::util::Hex;
+    ///
+    /// assert_eq!(Hex::to_bytes("ff12a3"), Some(vec![0xff, 0x12, 0xa3]));
+    /// assert_eq!(Hex::to_bytes("ff1"), None);
+    /// assert_eq!(Hex::to_bytes("FF"), None);
+    /// ```
+    pub fn to_bytes(s: &str) -> Option<Vec<u8>> {
+        let is_digit = |c: char| c.is_ascii_hexdigit() && ! c.is_ascii_uppercase();
+        if s.len() % 2 != 0 || ! s.chars().all(is_digit) {
+            return None;
+        }
+        (0..s.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -37,6 +71,124 @@ mod hex_tests {
         let bytes = [0xff, 0x12, 0xa3];
         assert_eq!(&Hex::from_bytes(&bytes)[..], "ff12a3");
     }
+
+    #[test]
+    fn from_bytes_pads_bytes() {
+        assert_eq!(&Hex::from_bytes(&[0x05, 0x00, 0x10])[..], "050010");
+        assert_ne!(Hex::from_bytes(&[0x01, 0x23]), Hex::from_bytes(&[0x12, 0x03]));
+    }
+
+    #[test]
+    fn to_bytes() {
+        assert_eq!(Hex::to_bytes("ff12a3"), Some(vec![0xff, 0x12, 0xa3]));
+        assert_eq!(Hex::to_bytes("zz"), None);
+    }
+
+    #[test]
+    fn to_bytes_rejects_non_canonical_strings() {
+        assert_eq!(Hex::to_bytes("+f"), None);
+        assert_eq!(Hex::to_bytes("-f"), None);
+        assert_eq!(Hex::to_bytes("A3"), None);
+        assert_eq!(Hex::to_bytes(" f"), None);
+        assert!("+f".repeat(HASH_LEN).parse::<Hash>().is_err());
+    }
+}
+
+/// SHA-256 hash, e.g. of a block or a transaction.
+///
+/// Hashes are shown, parsed and serialized as hex strings of `2 * HASH_LEN` digits.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Default, Clone)]
+pub struct Hash([u8; HASH_LEN]);
+
+impl Hash {
+    /// Computes the object hash of `value`.
+    pub fn digest<T: ObjectHash + ?Sized>(value: &T) -> Hash {
+        Hash::from_slice(objecthash::digest(value).as_ref()).expect("Object hash is a SHA-256 hash")
+    }
+
+    /// Copies `bytes` into a hash, if they have the length of one.
+    pub fn from_slice(bytes: &[u8]) -> Option<Hash> {
+        if bytes.len() != HASH_LEN {
+            return None;
+        }
+        let mut hash = [0; HASH_LEN];
+        hash.copy_from_slice(bytes);
+        Some(Hash(hash))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", Hex::from_bytes(&self.0))
+    }
+}
+
+impl fmt::Debug for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Hash({})", self)
+    }
+}
+
+impl FromStr for Hash {
+    type Err = HashError;
+
+    fn from_str(s: &str) -> Result<Hash, HashError> {
+        Hex::to_bytes(s).and_then(|bytes| Hash::from_slice(&bytes)).ok_or(HashError)
+    }
+}
+
+impl Serialize for Hash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Hash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Hash, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|_| de::Error::custom("invalid hash"))
+    }
+}
+
+/// Makes Hash hashable, the same as its bytes.
+impl ObjectHash for Hash {
+    #[inline]
+    fn objecthash<H: ObjectHasher>(&self, hasher: &mut H) {
+        self.0.to_vec().objecthash(hasher)
+    }
+}
+
+#[cfg(test)]
+mod hash_tests {
+    use serde_json;
+    use super::*;
+
+    #[test]
+    fn display_and_parse() {
+        let mut bytes = [0; HASH_LEN];
+        bytes[0] = 0x05;
+        let hash = Hash::from_slice(&bytes).unwrap();
+        assert_eq!(hash.to_string(), format!("05{}", "0".repeat(2 * HASH_LEN - 2)));
+        assert_eq!(hash.to_string().parse::<Hash>(), Ok(hash.clone()));
+        assert_eq!("05".parse::<Hash>(), Err(HashError));
+        assert_eq!("zz".repeat(HASH_LEN).parse::<Hash>(), Err(HashError));
+        assert!(Hash::from_slice(&[0; HASH_LEN + 1]).is_none());
+
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(json, format!("\"{}\"", hash));
+        assert_eq!(serde_json::from_str::<Hash>(&json).unwrap(), hash);
+    }
+
+    #[test]
+    fn hashes_like_bytes() {
+        let hash = Hash::digest(&String::from("block"));
+        assert_eq!(hash.as_bytes(), objecthash::digest(&String::from("block")).as_ref());
+        assert_eq!(Hash::digest(&hash).as_bytes(), objecthash::digest(&hash.as_bytes().to_vec()).as_ref());
+    }
 }
 
 /// Newtype for std::time::Duration.
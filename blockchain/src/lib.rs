@@ -1,8 +1,12 @@
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 #[macro_use]
 extern crate objecthash;
 extern crate reqwest;
+extern crate ring;
+extern crate untrusted;
 
 pub mod ledger;
 
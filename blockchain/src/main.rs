@@ -4,95 +4,669 @@
 extern crate rocket;
 #[macro_use]
 extern crate rocket_contrib;
-extern crate uuid;
+extern crate serde;
 extern crate blockchain;
+#[cfg(test)]
+extern crate reqwest;
 
-use std::sync::Mutex;
-use std::error::Error;
+mod encoding;
+mod response;
+mod timing;
+mod utxo;
+
+use std::env;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
 
 use rocket::State;
+use rocket::http::{ContentType, Status};
+use rocket::response::{status, Content};
 use rocket_contrib::{Json, Value};
 
-use uuid::Uuid;
 use blockchain::ledger::DistributedLedger;
-use blockchain::ledger::util::Timestamp;
-use blockchain::ledger::example::stupid::{StupidLedger, StupidBlock, StupidTransaction};
+use blockchain::ledger::consensus::{Consensus, ConsensusConfig, ProofOfWork};
+use blockchain::ledger::crypto::{Address, Keypair};
+use blockchain::ledger::error::BlockError;
+use blockchain::ledger::genesis::Genesis;
+use blockchain::ledger::gossip::{self, Outcome, Seen, Throttle};
+use blockchain::ledger::metrics::{self, Exposition, Latencies};
+use blockchain::ledger::mining::{Jobs, JobStatus};
+use blockchain::ledger::peers::PeerTable;
+use blockchain::ledger::pow::Search;
+use blockchain::ledger::storage::FileStorage;
+use blockchain::ledger::sync::{self, Tip};
+use blockchain::ledger::util::{Hash, Timestamp};
+use blockchain::ledger::example::stupid::{ChainStats, Reorg, Snapshot, StupidLedger, StupidBlock, StupidTransaction};
+use blockchain::ledger::example::utxo::UtxoLedger;
+
+use encoding::{Binary, Encoded, Encoding};
+use response::{ErrorResponse, JsonResult};
+use timing::{RouteLatencies, Timed};
+
+type Ledger = Arc<Mutex<StupidLedger>>;
+type Gossip = Arc<Mutex<Seen>>;
+type Miner = Arc<Mutex<Jobs>>;
+type ForkSyncs = Arc<Mutex<Throttle>>;
+
+/// Range of blocks, starting at `from`, counting from 1.
+#[derive(FromForm)]
+struct Range {
+    from: Option<usize>,
+    limit: Option<usize>
+}
+
+#[get("/blocks", rank = 2)]
+fn get_full_chain(_timed: Timed, encoding: Encoding, ledger: State<Ledger>) -> Encoded<Vec<StupidBlock>> {
+    Encoded { encoding, value: ledger.lock().unwrap().chain.to_vec() }
+}
+
+#[get("/blocks?<range>")]
+fn get_blocks(_timed: Timed, range: Range, encoding: Encoding, ledger: State<Ledger>) -> Encoded<Vec<StupidBlock>> {
+    let limit = range.limit.unwrap_or(sync::PAGE_LIMIT).min(sync::PAGE_LIMIT);
+    Encoded { encoding, value: ledger.lock().unwrap().blocks(range.from.unwrap_or(1), limit).to_vec() }
+}
+
+#[get("/headers?<range>")]
+fn get_headers(_timed: Timed, range: Range, encoding: Encoding, ledger: State<Ledger>) -> Encoded<Vec<StupidBlock>> {
+    let limit = range.limit.unwrap_or(sync::PAGE_LIMIT).min(sync::PAGE_LIMIT);
+    Encoded { encoding, value: ledger.lock().unwrap().headers(range.from.unwrap_or(1), limit) }
+}
+
+#[get("/snapshot")]
+fn get_snapshot(_timed: Timed, encoding: Encoding, ledger: State<Ledger>) -> Result<Encoded<Snapshot>, ErrorResponse> {
+    match ledger.lock().unwrap().snapshot() {
+        Some(snapshot) => Ok(Encoded { encoding, value: snapshot.clone() }),
+        None => Err(response::not_found("No snapshot taken yet"))
+    }
+}
+
+#[get("/tip")]
+fn get_tip(_timed: Timed, ledger: State<Ledger>) -> Json<Tip> {
+    Json(ledger.lock().unwrap().tip())
+}
+
+#[get("/blocks/<index>")]
+fn get_block(_timed: Timed, index: usize, ledger: State<Ledger>) -> JsonResult {
+    let ledger = ledger.lock().unwrap();
+    match ledger.block(index) {
+        Some(block) => Ok(Json(json!({ "hash": StupidLedger::hash(block), "block": block }))),
+        None => Err(response::not_found("Block not found"))
+    }
+}
+
+#[get("/blocks/hash/<hash>")]
+fn get_block_by_hash(_timed: Timed, hash: String, ledger: State<Ledger>) -> JsonResult {
+    let hash: Hash = match hash.parse() {
+        Ok(hash) => hash,
+        Err(e) => return Err(response::error(Status::BadRequest, &e))
+    };
 
-type Ledger = Mutex<StupidLedger>;
+    let ledger = ledger.lock().unwrap();
+    match ledger.block_by_hash(&hash) {
+        Some(block) => Ok(Json(json!({ "hash": hash, "block": block }))),
+        None => Err(response::not_found("Block not found"))
+    }
+}
+
+#[get("/stats")]
+fn get_stats(_timed: Timed, ledger: State<Ledger>) -> Json<ChainStats> {
+    Json(ledger.lock().unwrap().stats())
+}
+
+/// Reports the chain, the peers and the route latencies in the Prometheus text format.
+#[get("/metrics")]
+fn get_metrics(_timed: Timed, ledger: State<Ledger>, latencies: State<RouteLatencies>) -> Content<String> {
+    let mut out = Exposition::new();
+    ledger.lock().unwrap().write_metrics(&Timestamp::current_nanos(), &mut out);
+    latencies.lock().unwrap().write_to(&mut out);
+    let content_type = ContentType::parse_flexible(metrics::MEDIA_TYPE).unwrap_or(ContentType::Plain);
+    Content(content_type, out.into_string())
+}
 
-#[get("/blocks")]
-fn get_full_chain(ledger: State<Ledger>) -> Json<Vec<StupidBlock>> {
-    Json(ledger.lock().unwrap().chain.to_vec())
+#[get("/genesis")]
+fn get_genesis(_timed: Timed, ledger: State<Ledger>) -> Json<Value> {
+    let ledger = ledger.lock().unwrap();
+    Json(json!({ "genesis": ledger.genesis(), "hash": ledger.genesis().hash() }))
 }
 
+/// Starts mining the next block in the background, unless a block is being mined already.
+///
+/// Answers with the id of the mining job, to poll at `GET /jobs/<id>`.
 #[post("/blocks")]
-fn mine_block(node_id: State<String>, ledger: State<Ledger>) -> Json<Value> {
+fn mine_block(_timed: Timed, node_address: State<Address>, miner: State<Miner>, seen: State<Gossip>, ledger: State<Ledger>)
+    -> status::Custom<Json<Value>>
+{
+    let (id, abort) = match miner.lock().unwrap().start() {
+        Ok(job) => job,
+        Err(id) => return status::Custom(Status::Accepted, Json(json!({ "job": id, "status": "running" })))
+    };
+    let (node_address, miner, seen, ledger) = (node_address.clone(), miner.clone(), seen.clone(), ledger.clone());
+    thread::spawn(move || {
+        let status = mine(abort, node_address, &seen, &ledger);
+        miner.lock().unwrap().finish(id, status);
+    });
+    status::Custom(Status::Accepted, Json(json!({ "job": id, "status": "running" })))
+}
+
+/// Prepares the next block, seals it with the consensus engine without holding the ledger,
+/// then adds it unless the chain tip moved in the meantime.
+fn mine(abort: Arc<AtomicBool>, node_address: Address, seen: &Gossip, ledger: &Ledger) -> JobStatus {
+    let (block, target, consensus) = {
+        let mut ledger = ledger.lock().unwrap();
+        ledger.reward(node_address);
+        match ledger.prepare_block(Timestamp::current_nanos()) {
+            Ok(block) => (block, ledger.target(), ledger.consensus()),
+            Err(e) => return JobStatus::Failed { reason: e.to_string() }
+        }
+    };
+    let sealed = match consensus.seal(&target, &block.header().digest(), abort.clone()) {
+        Some(sealed) => sealed,
+        None if abort.load(Ordering::Relaxed) => return JobStatus::Aborted,
+        None => return JobStatus::Failed { reason: BlockError::CannotSeal { index: block.index() }.to_string() }
+    };
+
+    let block = block.with_seal(sealed.seal);
     let mut ledger = ledger.lock().unwrap();
-    let last_proof = if let Some(last_block) = ledger.last_block() {
-        last_block.proof
+    if ledger.target() != target {
+        return JobStatus::Aborted;
+    }
+    if let Err(e) = ledger.receive_block(block.clone()) {
+        return JobStatus::Failed { reason: e.to_string() };
+    }
+    if let Some(hash_rate) = sealed.hash_rate {
+        ledger.record_hash_rate(hash_rate);
+    }
+
+    let hash = StupidLedger::hash(&block);
+    seen.lock().unwrap().insert(hash.clone());
+    gossip::relay(ledger.peers.available(&Timestamp::current_nanos()), "/gossip/blocks", block.clone());
+    JobStatus::Mined { index: block.index(), hash, hash_rate: sealed.hash_rate }
+}
+
+#[get("/jobs/<id>")]
+fn get_job(_timed: Timed, id: u64, miner: State<Miner>) -> JsonResult {
+    match miner.lock().unwrap().status(id) {
+        Some(status) => {
+            let mut job = json!(status);
+            job["job"] = json!(id);
+            Ok(Json(job))
+        },
+        None => Err(response::not_found("Job not found"))
+    }
+}
+
+#[post("/gossip/blocks", format = "application/json", data = "<block>")]
+fn receive_block(_timed: Timed, block: Json<StupidBlock>, miner: State<Miner>, seen: State<Gossip>,
+    fork_syncs: State<ForkSyncs>, ledger: State<Ledger>) -> JsonResult
+{
+    accept_block(block.0, &miner, &seen, &fork_syncs, &ledger)
+}
+
+#[post("/gossip/blocks", format = "application/octet-stream", data = "<block>", rank = 2)]
+fn receive_encoded_block(_timed: Timed, block: Binary<StupidBlock>, miner: State<Miner>, seen: State<Gossip>,
+    fork_syncs: State<ForkSyncs>, ledger: State<Ledger>) -> JsonResult
+{
+    accept_block(block.0, &miner, &seen, &fork_syncs, &ledger)
+}
+
+/// Adds a block to the ledger, switching forks if it makes a better chain, then relays it
+/// to the peers.
+///
+/// A block on another fork makes the node sync with its peers, once the ledger checked
+/// its seal, and at most once per `gossip::FORK_SYNC_INTERVAL`.
+///
+/// The block is remembered as seen only once the node took it, so that a block it could
+/// not take yet, e.g. one whose fork sync was throttled, is taken when it comes again.
+fn accept_block(block: StupidBlock, miner: &Miner, seen: &Gossip, fork_syncs: &ForkSyncs, ledger: &Ledger)
+    -> JsonResult
+{
+    let hash = StupidLedger::hash(&block);
+    if seen.lock().unwrap().contains(&hash) {
+        return Ok(Json(json!({ "status": "seen" })));
+    }
+
+    let outcome = match ledger.lock().unwrap().receive_block(block.clone()) {
+        Ok(outcome) => outcome,
+        Err(e) => return Err(response::block_error(&e))
+    };
+    let synced = outcome == Outcome::Fork && fork_syncs.lock().unwrap().allow(&Timestamp::current_nanos());
+    let reorg = if synced {
+        match sync_with_peers(ledger) {
+            Ok(reorg) => reorg,
+            Err(e) => return Err(response::error(Status::InternalServerError, &e))
+        }
     } else {
-        0
+        None
     };
-    let proof = ledger.find_proof(last_proof);
-
-    let tx = StupidTransaction::new(String::from("0"), node_id.clone(), 1);
-    if let Err(e) = ledger.add_transaction(tx) {
-        return Json(json!({ "status": "error", "reason": e.description() }));
+    if outcome != Outcome::Fork || synced {
+        seen.lock().unwrap().insert(hash);
     }
 
-    let timestamp = Timestamp::current_nanos();
-    if let Err(e) = ledger.new_block(timestamp, proof) {
-        return Json(json!({ "status": "error", "reason": e.description() }));
+    if outcome == Outcome::Appended || reorg.is_some() {
+        miner.lock().unwrap().abort();
+        let peers = ledger.lock().unwrap().peers.available(&Timestamp::current_nanos());
+        gossip::relay(peers, "/gossip/blocks", block);
     }
+    Ok(Json(json!({ "outcome": outcome, "is_replaced": reorg.is_some(), "reorg": reorg })))
+}
 
-    Json(json!(ledger.last_block().expect("Get last block; but there is none!")))
+/// Syncs with the peers without holding the ledger while fetching from them, since they
+/// may be fetching from the node at the same time.
+fn sync_with_peers(ledger: &Ledger) -> io::Result<Option<Reorg>> {
+    let sync = ledger.lock().unwrap().peer_sync();
+    let synced = sync.fetch();
+    ledger.lock().unwrap().finish_sync(synced)
+}
+
+#[get("/blocks/<index>/transactions/<i>/proof")]
+fn get_transaction_proof(_timed: Timed, index: usize, i: usize, ledger: State<Ledger>) -> JsonResult {
+    let ledger = ledger.lock().unwrap();
+    let block = match ledger.block(index) {
+        Some(block) => block,
+        None => return Err(response::not_found("Block not found"))
+    };
+    let (tx, proof) = match (block.transactions().get(i), block.transaction_proof(i)) {
+        (Some(tx), Some(proof)) => (tx, proof),
+        _ => return Err(response::not_found("Transaction not found"))
+    };
+
+    Ok(Json(json!({
+        "block_hash": StupidLedger::hash(block),
+        "transactions_root": block.transactions_root(),
+        "transaction": tx,
+        "transaction_hash": tx.hash(),
+        "proof": proof
+    })))
 }
 
 #[post("/transactions", format = "application/json", data = "<tx>")]
-fn create_transaction(tx: Json<StupidTransaction>, ledger: State<Ledger>) -> Json<Value> {
+fn create_transaction(_timed: Timed, tx: Json<StupidTransaction>, seen: State<Gossip>, ledger: State<Ledger>)
+    -> JsonResult
+{
+    accept_transaction(tx.0, &seen, &ledger)
+}
+
+#[post("/gossip/transactions", format = "application/json", data = "<tx>")]
+fn receive_transaction(_timed: Timed, tx: Json<StupidTransaction>, seen: State<Gossip>, ledger: State<Ledger>)
+    -> JsonResult
+{
+    if seen.lock().unwrap().contains(&tx.hash()) {
+        return Ok(Json(json!({ "status": "seen" })));
+    }
+    accept_transaction(tx.0, &seen, &ledger)
+}
+
+/// Adds a transaction to the ledger, then relays it to the peers.
+fn accept_transaction(tx: StupidTransaction, seen: &Gossip, ledger: &Ledger) -> JsonResult {
     let mut ledger = ledger.lock().unwrap();
-    let json = match ledger.add_transaction(tx.0) {
-        Ok(index) => json!({ "index": index }),
-        Err(e) => json!({ "status": "error", "reason": e.description() })
+    match ledger.add_transaction(tx.clone()) {
+        Ok(index) => {
+            seen.lock().unwrap().insert(tx.hash());
+            gossip::relay(ledger.peers.available(&Timestamp::current_nanos()), "/gossip/transactions", tx);
+            Ok(Json(json!({ "index": index })))
+        },
+        Err(e) => Err(response::transaction_error(&e))
+    }
+}
+
+#[get("/transactions/<hash>")]
+fn get_transaction(_timed: Timed, hash: String, ledger: State<Ledger>) -> JsonResult {
+    let hash: Hash = match hash.parse() {
+        Ok(hash) => hash,
+        Err(e) => return Err(response::error(Status::BadRequest, &e))
     };
-    Json(json)
+
+    let ledger = ledger.lock().unwrap();
+    match ledger.transaction(&hash) {
+        Some((location, tx)) => Ok(Json(json!({
+            "transaction": tx,
+            "hash": hash,
+            "block": location.block,
+            "position": location.position,
+            "confirmations": ledger.confirmations(&location)
+        }))),
+        None => Err(response::not_found("Transaction not found"))
+    }
 }
 
+#[get("/addresses/<address>/transactions")]
+fn get_address_transactions(_timed: Timed, address: String, ledger: State<Ledger>) -> JsonResult {
+    let address: Address = match address.parse() {
+        Ok(address) => address,
+        Err(e) => return Err(response::error(Status::BadRequest, &e))
+    };
+
+    let ledger = ledger.lock().unwrap();
+    let transactions: Vec<Value> = ledger.transactions_of(&address).into_iter().map(|(location, tx)| json!({
+        "transaction": tx,
+        "hash": tx.hash(),
+        "block": location.block,
+        "position": location.position,
+        "confirmations": ledger.confirmations(&location)
+    })).collect();
+    Ok(Json(json!({ "address": address, "transactions": transactions })))
+}
+
+#[get("/mempool")]
+fn get_mempool(_timed: Timed, ledger: State<Ledger>) -> Json<Value> {
+    let ledger = ledger.lock().unwrap();
+    let entries: Vec<Value> = ledger.mempool().by_fee_rate().into_iter().map(|entry| json!({
+        "transaction": entry.tx,
+        "hash": entry.hash,
+        "fee": entry.fee,
+        "size": entry.size,
+        "fee_rate": entry.fee_rate(),
+        "received": entry.received
+    })).collect();
+    Json(json!({ "size": entries.len(), "transactions": entries }))
+}
+
+#[get("/balances/<address>")]
+fn get_balance(_timed: Timed, address: String, ledger: State<Ledger>) -> JsonResult {
+    let address: Address = match address.parse() {
+        Ok(address) => address,
+        Err(e) => return Err(response::error(Status::BadRequest, &e))
+    };
+
+    let ledger = ledger.lock().unwrap();
+    Ok(Json(json!({
+        "address": address,
+        "confirmed": ledger.confirmed_balances().balance(&address),
+        "available": ledger.balances().balance(&address)
+    })))
+}
+
+#[get("/peers")]
+fn get_peers(_timed: Timed, ledger: State<Ledger>) -> Json<Value> {
+    Json(json!({ "peers": ledger.lock().unwrap().peers.peers() }))
+}
+
+/// Adds peers by their base URL, reporting the URLs that cannot be added.
 #[post("/peers", format = "application/json", data = "<peers>")]
-fn register_peers(peers: Json<Vec<String>>, ledger: State<Ledger>) -> Json<Vec<String>> {
+fn register_peers(_timed: Timed, peers: Json<Vec<String>>, ledger: State<Ledger>) -> JsonResult {
     let mut ledger = ledger.lock().unwrap();
+    let mut rejected = Vec::new();
     for peer in peers.0 {
-        ledger.register_peer(peer);
+        if let Err(e) = ledger.register_peer(&peer) {
+            rejected.push(json!({ "url": peer, "reason": e.to_string() }));
+        }
+    }
+    if let Err(e) = ledger.peers.save() {
+        return Err(response::error(Status::InternalServerError, &e));
     }
 
-    Json(ledger.peers.to_vec())
+    Ok(Json(json!({ "peers": ledger.peers.peers(), "rejected": rejected })))
 }
 
-#[post("/peers/consensus")]
-fn consensus(ledger: State<Ledger>) -> Json<Value> {
+#[delete("/peers", format = "application/json", data = "<peers>")]
+fn remove_peers(_timed: Timed, peers: Json<Vec<String>>, ledger: State<Ledger>) -> JsonResult {
     let mut ledger = ledger.lock().unwrap();
+    for peer in peers.0 {
+        ledger.peers.remove(&peer);
+    }
+    if let Err(e) = ledger.peers.save() {
+        return Err(response::error(Status::InternalServerError, &e));
+    }
 
-    let is_replaced = match ledger.resolve_conflicts() {
-        Ok(is_replaced) => is_replaced,
-        Err(e) => {
-            return Json(json!({ "status": "error", "reason": e.description() }));
-        }
+    Ok(Json(json!({ "peers": ledger.peers.peers() })))
+}
+
+#[post("/peers/consensus")]
+fn consensus(_timed: Timed, miner: State<Miner>, ledger: State<Ledger>) -> JsonResult {
+    let reorg = match sync_with_peers(&ledger) {
+        Ok(reorg) => reorg,
+        Err(e) => return Err(response::error(Status::InternalServerError, &e))
     };
-    Json(json!({ "is_replaced": is_replaced, "chain": ledger.chain.to_vec() }))
+    if reorg.is_some() {
+        miner.lock().unwrap().abort();
+    }
+
+    let ledger = ledger.lock().unwrap();
+    if let Err(e) = ledger.peers.save() {
+        return Err(response::error(Status::InternalServerError, &e));
+    }
+    Ok(Json(json!({ "is_replaced": reorg.is_some(), "reorg": reorg, "chain": ledger.chain.to_vec() })))
 }
 
 fn main() {
-    let node_id = Uuid::new_v4();
+    let key_path = env::var("NODE_KEY_PATH").unwrap_or(String::from("node.key"));
+    let keypair = Keypair::read_from(&key_path).or_else(|_| {
+        let keypair = Keypair::generate();
+        keypair.write_to(&key_path).map(|_| keypair)
+    }).expect("Load node key");
+    let genesis = match env::var("GENESIS_PATH") {
+        Ok(genesis_path) => Genesis::read_from(genesis_path).expect("Load genesis"),
+        Err(_) => Genesis::default()
+    };
+    let node_address = keypair.address();
+    let consensus: Arc<Consensus<StupidBlock>> = match genesis.consensus {
+        ConsensusConfig::ProofOfWork => {
+            let threads = env::var("MINING_THREADS").ok().and_then(|threads| threads.parse().ok()).unwrap_or(1);
+            Arc::new(ProofOfWork { initial_difficulty: genesis.difficulty, search: Search { threads, lowest: false } })
+        },
+        ref config => config.engine(genesis.difficulty, Some(keypair))
+    };
+    let ledger_path = env::var("LEDGER_PATH").unwrap_or(String::from("ledger.dat"));
+    let mut ledger = StupidLedger::with_storage(genesis, consensus, Box::new(FileStorage::new(ledger_path)))
+        .expect("Load ledger from storage");
+    let peers_path = env::var("PEERS_PATH").unwrap_or(String::from("peers.json"));
+    ledger.peers = PeerTable::load(peers_path).expect("Load peers");
+    if env::var("PRUNE").is_ok() {
+        ledger.pruning = true;
+        ledger.prune().expect("Prune ledger");
+    }
+    if let Ok(bootstrap_url) = env::var("BOOTSTRAP_URL") {
+        if ledger.chain.is_empty() {
+            let peer = sync::HttpPeer::new(&bootstrap_url).expect("Bootstrap URL is valid");
+            ledger.bootstrap(&peer).expect("Bootstrap from snapshot");
+        }
+    }
 
-    rocket::ignite()
-        .manage(Mutex::new(StupidLedger::new(vec![])))
-        .manage(node_id.simple().to_string())
+    mount_node(rocket::ignite(), ledger, node_address).launch();
+}
+
+fn mount_node(rocket: rocket::Rocket, mut ledger: StupidLedger, node_address: Address) -> rocket::Rocket {
+    let own_url = format!("http://{}:{}", rocket.config().address, rocket.config().port);
+    ledger.peers.set_own_url(&own_url).expect("Node URL is valid");
+
+    rocket
+        .manage(Arc::new(Mutex::new(ledger)))
+        .manage(Mutex::new(UtxoLedger::new(vec![])))
+        .manage(Arc::new(Mutex::new(Seen::default())))
+        .manage(Arc::new(Mutex::new(Jobs::default())))
+        .manage(Arc::new(Mutex::new(Throttle::default())))
+        .manage(Arc::new(Mutex::new(Latencies::default())))
+        .manage(node_address)
         .mount("/", routes![
             get_full_chain,
+            get_blocks,
+            get_headers,
+            get_snapshot,
+            get_tip,
+            get_block,
+            get_block_by_hash,
+            get_stats,
+            get_metrics,
+            get_genesis,
             mine_block,
+            get_job,
+            receive_block,
+            receive_encoded_block,
+            get_transaction_proof,
             create_transaction,
+            receive_transaction,
+            get_transaction,
+            get_address_transactions,
+            get_mempool,
+            get_balance,
+            get_peers,
             register_peers,
+            remove_peers,
             consensus
         ])
-        .launch();
+        .mount("/utxo", utxo::routes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use reqwest;
+    use rocket::config::{Config, Environment};
+    use rocket_contrib::Value;
+
+    use blockchain::ledger::example::stupid::MINING_REWARD;
+    use super::*;
+
+    #[test]
+    fn gossip_spreads_blocks_and_transactions() {
+        let miner = Keypair::generate();
+        let nodes = vec![
+            start_node(18001, miner.address()),
+            start_node(18002, Keypair::generate().address()),
+            start_node(18003, Keypair::generate().address())
+        ];
+        for node in &nodes {
+            let peers: Vec<&String> = nodes.iter().filter(|peer| peer != &node).collect();
+            post(&format!("{}/peers", node), &json!(peers));
+        }
+
+        post(&format!("{}/blocks", nodes[0]), &json!(null));
+        for node in &nodes {
+            wait_until(|| chain_len(node) == 1);
+        }
+
+        let recipient = Keypair::generate().address();
+        let tx = StupidTransaction::transfer(&miner, recipient.clone(), MINING_REWARD, 0, 0);
+        post(&format!("{}/transactions", nodes[1]), &json!(tx));
+        for node in &nodes {
+            let url = format!("{}/balances/{}", node, recipient);
+            wait_until(|| get(&url)["available"] == json!(MINING_REWARD));
+        }
+
+        let late_node = start_node(18004, Keypair::generate().address());
+        post(&format!("{}/peers", late_node), &json!(nodes));
+        post(&format!("{}/peers", nodes[2]), &json!([late_node]));
+        post(&format!("{}/blocks", nodes[2]), &json!(null));
+        for node in nodes.iter().chain(Some(&late_node)) {
+            wait_until(|| chain_len(node) == 2);
+        }
+        let chain = get(&format!("{}/blocks", nodes[0]));
+        assert_eq!(get(&format!("{}/blocks", late_node)), chain);
+        assert_eq!(get(&format!("{}/blocks?from=2&limit=1", late_node)), json!([chain[1]]));
+        assert_eq!(get(&format!("{}/tip", late_node))["height"], json!(2));
+    }
+
+    #[test]
+    fn throttled_fork_block_is_taken_when_it_comes_again() {
+        let node = start_node(18009, Keypair::generate().address());
+        let peer = start_node(18010, Keypair::generate().address());
+        for url in &[&node, &peer] {
+            post(&format!("{}/blocks", url), &json!(null));
+            wait_until(|| chain_len(url) == 1);
+        }
+        let fork = get(&format!("{}/blocks", peer));
+        let accepted = post(&format!("{}/gossip/blocks", node), &fork[0]);
+        assert_eq!(accepted["outcome"], json!("fork"));
+
+        post(&format!("{}/blocks", peer), &json!(null));
+        wait_until(|| chain_len(&peer) == 2);
+        post(&format!("{}/peers", node), &json!([peer]));
+        let block = get(&format!("{}/blocks", peer))[1].clone();
+        let throttled = post(&format!("{}/gossip/blocks", node), &block);
+        assert_eq!(throttled["outcome"], json!("fork"));
+        assert_eq!(throttled["is_replaced"], json!(false));
+
+        thread::sleep(Duration::from_secs(gossip::FORK_SYNC_INTERVAL));
+        let synced = post(&format!("{}/gossip/blocks", node), &block);
+        assert_eq!(synced["outcome"], json!("fork"));
+        assert_eq!(synced["is_replaced"], json!(true));
+        assert_eq!(get(&format!("{}/blocks", node)), get(&format!("{}/blocks", peer)));
+        assert_eq!(post(&format!("{}/gossip/blocks", node), &block)["status"], json!("seen"));
+    }
+
+    #[test]
+    fn mining_runs_in_background() {
+        let node = start_node(18005, Keypair::generate().address());
+        let job = post(&format!("{}/blocks", node), &json!(null));
+        assert_eq!(job["status"], json!("running"));
+
+        let url = format!("{}/jobs/{}", node, job["job"]);
+        wait_until(|| get(&url)["status"] == json!("mined"));
+        assert_eq!(get(&url)["index"], json!(1));
+        assert_eq!(chain_len(&node), 1);
+
+        let resp = reqwest::get(&format!("{}/jobs/1000", node)).unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NotFound);
+    }
+
+    #[test]
+    fn peers_are_validated_and_removed() {
+        let node = start_node(18006, Keypair::generate().address());
+        let peer = "http://127.0.0.1:18007";
+        let added = post(&format!("{}/peers", node), &json!([node, "not a url", peer, format!("{}/", peer)]));
+        assert_eq!(added["peers"].as_array().map(|peers| peers.len()), Some(1));
+        assert_eq!(added["rejected"].as_array().map(|rejected| rejected.len()), Some(2));
+
+        let removed: Value = reqwest::Client::new().delete(&format!("{}/peers", node)).json(&json!([peer])).send()
+            .unwrap().json().unwrap();
+        assert_eq!(removed["peers"], json!([]));
+    }
+
+    #[test]
+    fn metrics_report_chain_and_route_latencies() {
+        let node = start_node(18008, Keypair::generate().address());
+        post(&format!("{}/blocks", node), &json!(null));
+        wait_until(|| chain_len(&node) == 1);
+
+        let mut resp = reqwest::get(&format!("{}/metrics", node)).unwrap();
+        assert!(resp.status().is_success());
+        let mut text = String::new();
+        resp.read_to_string(&mut text).unwrap();
+        assert!(text.contains("blockchain_height 1\n"));
+        assert!(text.contains("blockchain_reorgs_total 0\n"));
+        assert!(text.contains("blockchain_hash_rate "));
+        assert!(text.contains("blockchain_request_duration_seconds_count{route=\"GET /blocks\"}"));
+        assert!(text.contains("blockchain_request_duration_seconds_count{route=\"POST /blocks\"} 1\n"));
+    }
+
+    fn start_node(port: u16, node_address: Address) -> String {
+        let config = Config::build(Environment::Development)
+            .address("127.0.0.1")
+            .port(port)
+            .finalize()
+            .expect("Build node config");
+        thread::spawn(move || {
+            let ledger = StupidLedger::new(Vec::new()).expect("Empty chain is valid");
+            mount_node(rocket::custom(config, false), ledger, node_address).launch();
+        });
+
+        let url = format!("http://127.0.0.1:{}", port);
+        wait_until(|| reqwest::get(&format!("{}/blocks", url)).is_ok());
+        url
+    }
+
+    fn chain_len(node: &str) -> usize {
+        get(&format!("{}/blocks", node)).as_array().map_or(0, |chain| chain.len())
+    }
+
+    fn get(url: &str) -> Value {
+        reqwest::get(url).unwrap().json().unwrap()
+    }
+
+    fn post(url: &str, body: &Value) -> Value {
+        reqwest::Client::new().post(url).json(body).send().unwrap().json().unwrap()
+    }
+
+    fn wait_until<F: FnMut() -> bool>(mut condition: F) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while ! condition() {
+            assert!(Instant::now() < deadline, "Timed out waiting for the nodes");
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
 }
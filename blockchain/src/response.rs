@@ -0,0 +1,60 @@
+//! # Response
+//!
+//! Maps ledger errors to HTTP error responses.
+
+use std::error::Error;
+
+use rocket::http::Status;
+use rocket::response::status;
+use rocket_contrib::{Json, Value};
+
+use blockchain::ledger::error::{BlockError, TransactionError};
+
+/// Error response: a status with a JSON body describing the error.
+pub type ErrorResponse = status::Custom<Json<Value>>;
+
+/// Result of a route answering with JSON.
+pub type JsonResult = Result<Json<Value>, ErrorResponse>;
+
+/// Describes `e`, followed by the chain of errors that caused it.
+pub fn error<E: Error + ?Sized>(status: Status, e: &E) -> ErrorResponse {
+    let mut causes = Vec::new();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        causes.push(cause.to_string());
+        source = cause.source();
+    }
+    status::Custom(status, Json(json!({ "status": "error", "reason": e.to_string(), "causes": causes })))
+}
+
+pub fn not_found(reason: &str) -> ErrorResponse {
+    status::Custom(Status::NotFound, Json(json!({ "status": "error", "reason": reason, "causes": [] })))
+}
+
+pub fn block_error(e: &BlockError) -> ErrorResponse {
+    let status = match *e {
+        BlockError::EmptyMempool | BlockError::MissingReward | BlockError::CannotSeal { .. } => Status::Conflict,
+        BlockError::Storage(_) => Status::InternalServerError,
+        _ => Status::UnprocessableEntity
+    };
+    error(status, e)
+}
+
+pub fn transaction_error(e: &TransactionError) -> ErrorResponse {
+    let status = match *e {
+        TransactionError::Malformed { .. } |
+        TransactionError::ZeroAmount |
+        TransactionError::UnexpectedReward |
+        TransactionError::UnexpectedSnapshot => Status::BadRequest,
+        TransactionError::InvalidSignature |
+        TransactionError::ScriptFailed { .. } => Status::Forbidden,
+        TransactionError::Duplicate { .. } |
+        TransactionError::DoubleSpend { .. } => Status::Conflict,
+        TransactionError::InsufficientFunds { .. } |
+        TransactionError::UnknownOutput { .. } |
+        TransactionError::UnknownLock { .. } |
+        TransactionError::OutputsExceedInputs { .. } |
+        TransactionError::FeeTooLow { .. } => Status::UnprocessableEntity
+    };
+    error(status, e)
+}
@@ -0,0 +1,43 @@
+//! # Timing
+//!
+//! Times the route handlers, for the latencies served at `GET /metrics`.
+
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use rocket::{Outcome, Request, State};
+use rocket::request::{self, FromRequest};
+
+use blockchain::ledger::metrics::Latencies;
+
+pub type RouteLatencies = Arc<Mutex<Latencies>>;
+
+/// Request guard recording the time from its creation until the handler taking it returns,
+/// under the method and URI of the matched route.
+#[derive(Debug)]
+pub struct Timed {
+    route: String,
+    started: Instant,
+    latencies: RouteLatencies
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Timed {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Timed, ()> {
+        let latencies = match request.guard::<State<RouteLatencies>>() {
+            Outcome::Success(latencies) => latencies.inner().clone(),
+            Outcome::Failure((status, _)) => return Outcome::Failure((status, ())),
+            Outcome::Forward(()) => return Outcome::Forward(())
+        };
+        let route = request.route()
+            .map_or(String::from("unknown"), |route| format!("{} {}", route.method, route.uri));
+        Outcome::Success(Timed { route, started: Instant::now(), latencies })
+    }
+}
+
+impl Drop for Timed {
+    fn drop(&mut self) {
+        self.latencies.lock().unwrap().record(&self.route, self.started.elapsed());
+    }
+}
@@ -0,0 +1,106 @@
+//! # UTXO Routes
+//!
+//! Serves the UTXO ledger behind the same routes as the stupid ledger, to be mounted under
+//! their own base path.
+
+use std::sync::Mutex;
+
+use rocket::{Route, State};
+use rocket::http::Status;
+use rocket_contrib::{Json, Value};
+
+use blockchain::ledger::DistributedLedger;
+use blockchain::ledger::crypto::Address;
+use blockchain::ledger::util::Timestamp;
+use blockchain::ledger::example::utxo::{UtxoLedger, UtxoBlock, UtxoTransaction};
+
+use response::{self, JsonResult};
+use timing::Timed;
+
+pub type Ledger = Mutex<UtxoLedger>;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        get_full_chain,
+        mine_block,
+        create_transaction,
+        get_balance,
+        register_peers,
+        consensus
+    ]
+}
+
+#[get("/blocks")]
+fn get_full_chain(_timed: Timed, ledger: State<Ledger>) -> Json<Vec<UtxoBlock>> {
+    Json(ledger.lock().unwrap().chain.to_vec())
+}
+
+#[post("/blocks")]
+fn mine_block(_timed: Timed, node_address: State<Address>, ledger: State<Ledger>) -> JsonResult {
+    let mut ledger = ledger.lock().unwrap();
+    let last_proof = if let Some(last_block) = ledger.last_block() {
+        last_block.proof
+    } else {
+        0
+    };
+    let proof = ledger.find_proof(last_proof);
+
+    ledger.reward(node_address.clone());
+
+    let timestamp = Timestamp::current_nanos();
+    if let Err(e) = ledger.new_block(timestamp, proof) {
+        return Err(response::block_error(&e));
+    }
+
+    Ok(Json(json!(ledger.last_block().expect("Get last block; but there is none!"))))
+}
+
+#[post("/transactions", format = "application/json", data = "<tx>")]
+fn create_transaction(_timed: Timed, tx: Json<UtxoTransaction>, ledger: State<Ledger>) -> JsonResult {
+    let mut ledger = ledger.lock().unwrap();
+    match ledger.add_transaction(tx.0) {
+        Ok(index) => Ok(Json(json!({ "index": index }))),
+        Err(e) => Err(response::transaction_error(&e))
+    }
+}
+
+#[get("/balances/<address>")]
+fn get_balance(_timed: Timed, address: String, ledger: State<Ledger>) -> JsonResult {
+    let address: Address = match address.parse() {
+        Ok(address) => address,
+        Err(e) => return Err(response::error(Status::BadRequest, &e))
+    };
+
+    let ledger = ledger.lock().unwrap();
+    let utxos = ledger.utxos();
+    let unspent: Vec<Value> = utxos.owned_by(&address).into_iter()
+        .map(|(out_point, output)| json!({ "previous_output": out_point, "amount": output.amount }))
+        .collect();
+    Ok(Json(json!({
+        "address": address,
+        "confirmed": ledger.confirmed_utxos().balance(&address),
+        "available": utxos.balance(&address),
+        "unspent": unspent
+    })))
+}
+
+#[post("/peers", format = "application/json", data = "<peers>")]
+fn register_peers(_timed: Timed, peers: Json<Vec<String>>, ledger: State<Ledger>) -> Json<Vec<String>> {
+    let mut ledger = ledger.lock().unwrap();
+    for peer in peers.0 {
+        ledger.register_peer(peer);
+    }
+
+    Json(ledger.peers.to_vec())
+}
+
+#[post("/peers/consensus")]
+fn consensus(_timed: Timed, ledger: State<Ledger>) -> JsonResult {
+    let mut ledger = ledger.lock().unwrap();
+
+    let is_replaced = match ledger.resolve_conflicts() {
+        Ok(is_replaced) => is_replaced,
+        Err(e) => return Err(response::error(Status::BadGateway, &*e))
+    };
+    Ok(Json(json!({ "is_replaced": is_replaced, "chain": ledger.chain.to_vec() })))
+}